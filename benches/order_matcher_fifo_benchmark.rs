@@ -1,19 +1,17 @@
-use core::num;
-
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use exchange_prototype::order_matcher::OrderMatcher;
+use exchange_prototype::order_matcher::{OrderMatcher, TimeInForce};
 use exchange_prototype::order_matcher_fifo::OrderMatcherFifo;
-use exchange_prototype::orderbook::Orderbook;
+use exchange_prototype::orderbook::{Order, Orderbook};
 use exchange_prototype::symbol::{AskOrBid, Symbol};
 use rand::distributions::Distribution;
 use rand::prelude::StdRng;
-use rand::{thread_rng, RngCore, SeedableRng};
+use rand::{RngCore, SeedableRng};
 use rand_distr::Normal;
 use rust_decimal::Decimal;
 
 fn test_match_limit_performance(
-    price_levels_buy: &Vec<Decimal>,
-    price_levels_sell: &Vec<Decimal>,
+    price_levels_buy: &[Decimal],
+    price_levels_sell: &[Decimal],
     orderbook: &mut Orderbook,
     matcher: &OrderMatcherFifo,
     iterations: u64,
@@ -22,24 +20,27 @@ fn test_match_limit_performance(
     let len_buy = price_levels_buy.len();
     let len_sell = price_levels_sell.len();
     let mut rng = StdRng::seed_from_u64(42);
+    let mut last_trade_id = 0;
 
     for i in 0u64..iterations {
         rand = rng.next_u32() as usize;
 
-        let result = match i % 2 == 0 {
+        let _result = match i % 2 == 0 {
             true => matcher.match_limit(
                 orderbook,
-                &i,
+                &mut last_trade_id,
+                Order::new(i, i, price_levels_buy[rand % len_buy]),
                 AskOrBid::Bid,
                 &price_levels_buy[rand % len_buy],
-                &price_levels_buy[rand % len_buy],
+                TimeInForce::GoodTillCancel,
             ),
             false => matcher.match_limit(
                 orderbook,
-                &i,
+                &mut last_trade_id,
+                Order::new(i, i, price_levels_sell[rand % len_sell]),
                 AskOrBid::Ask,
                 &price_levels_sell[rand % len_sell],
-                &price_levels_sell[rand % len_sell],
+                TimeInForce::GoodTillCancel,
             ),
         };
     }
@@ -59,7 +60,7 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     let mut rng_sell = StdRng::seed_from_u64(41);
 
     // Initialize orderbook and matcher
-    let mut orderbook = Orderbook::new(Symbol::Asset2);
+    let mut orderbook = Orderbook::new(Symbol::new("ETH"));
     let matcher = OrderMatcherFifo::new();
 
     // Set initial market conditions
@@ -77,7 +78,7 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         .map(|x: f32| Decimal::from((x * 1f32) as u32))
         .filter(|x| x.is_sign_positive())
         .collect();
-    let mut price_levels_sell: Vec<Decimal> = normal_sell
+    let price_levels_sell: Vec<Decimal> = normal_sell
         .sample_iter(&mut rng_sell)
         .take(max_orderbook_pages / 2)
         .map(|x: f32| Decimal::from((x * 1f32) as u32))
@@ -96,8 +97,8 @@ pub fn criterion_benchmark(c: &mut Criterion) {
                 |b| {
                     b.iter(|| {
                         test_match_limit_performance(
-                            black_box(&price_levels_buy.iter().take(*num_pages).copied().collect()),
-                            black_box(&price_levels_sell.iter().take(*num_pages).copied().collect()),
+                            black_box(&price_levels_buy.iter().take(*num_pages).copied().collect::<Vec<Decimal>>()),
+                            black_box(&price_levels_sell.iter().take(*num_pages).copied().collect::<Vec<Decimal>>()),
                             &mut orderbook,
                             &matcher,
                             *num_transactions,
@@ -0,0 +1,364 @@
+use json::{object, JsonValue};
+use rust_decimal::prelude::Zero;
+use rust_decimal::Decimal;
+
+use crate::orderbook::Orderbook;
+use crate::symbol::AskOrBid;
+use crate::trade::Trade;
+use crate::{OrderId, TradeId};
+
+/// Price at which a crossed book accumulated during a call auction would be
+/// uncrossed, together with the volume executed at that price.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Uncrossing {
+    pub price: Decimal,
+
+    /// Amount executed on each side at ``price``
+    pub volume: Decimal,
+
+    /// Amount left unexecuted on the heavier side at ``price``
+    pub imbalance: Decimal,
+}
+
+/// Amounts self-trade prevention cancelled during an uncrossing, by order id
+pub type SelfTrades = Vec<(OrderId, Decimal)>;
+
+/// Computes the indicative uncrossing price of a book.
+///
+/// Every price level between the best ask and the best bid is a candidate. The
+/// candidate executing the most volume wins, ties are broken by the smallest
+/// imbalance, then by the distance to ``reference_price`` and finally by the lower
+/// price. Returns None if the book isn't crossed.
+pub fn indicative_uncrossing(orderbook: &Orderbook, reference_price: Option<&Decimal>) -> Option<Uncrossing> {
+    let best_ask = orderbook.get_best_ask()?;
+    let best_bid = orderbook.get_best_bid()?;
+    if best_bid < best_ask {
+        return None;
+    }
+
+    let mut candidates: Vec<Decimal> = orderbook
+        .orders_ask
+        .range(best_ask..=best_bid)
+        .chain(orderbook.orders_bid.range(best_ask..=best_bid))
+        .map(|(price, _)| *price)
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+
+    let mut best: Option<Uncrossing> = None;
+    for price in candidates {
+        // Bids at or above and asks at or below the price execute, hidden reserves included
+        let buy_volume = orderbook.get_matchable_amount(AskOrBid::Ask, &price);
+        let sell_volume = orderbook.get_matchable_amount(AskOrBid::Bid, &price);
+        let candidate = Uncrossing {
+            price,
+            volume: buy_volume.min(sell_volume),
+            imbalance: (buy_volume - sell_volume).abs(),
+        };
+
+        let is_better = match &best {
+            None => true,
+            Some(best) => {
+                if candidate.volume != best.volume {
+                    candidate.volume > best.volume
+                } else if candidate.imbalance != best.imbalance {
+                    candidate.imbalance < best.imbalance
+                } else {
+                    // Candidates are visited in ascending order, the lower price wins a full tie
+                    reference_price.is_some_and(|reference| {
+                        (candidate.price - reference).abs() < (best.price - reference).abs()
+                    })
+                }
+            }
+        };
+        if is_better {
+            best = Some(candidate);
+        }
+    }
+    best
+}
+
+/// Uncrosses a book at its indicative uncrossing price in a single pass.
+///
+/// The front orders of the best bid and ask pages are executed against each
+/// other until the uncrossing volume is used up, all trades are executed at the
+/// uncrossing price. Of each pair of orders the one placed earlier is the maker.
+/// The last trade price of the book is used as reference price.
+///
+/// Pairs of the same account don't trade, the self-trade prevention mode of the
+/// later order decides what's cancelled of both, as if it was the taker. Returns
+/// the cancelled amounts by order id next to the trades.
+pub fn uncross(orderbook: &mut Orderbook, last_trade_id: &mut TradeId) -> Option<(Uncrossing, Vec<Trade>, SelfTrades)> {
+    let uncrossing = indicative_uncrossing(orderbook, orderbook.last_trade_price.as_ref())?;
+    let symbol = orderbook.get_symbol().clone();
+
+    let mut trades: Vec<Trade> = Vec::new();
+    let mut self_trades: SelfTrades = Vec::new();
+    let mut remaining = uncrossing.volume;
+
+    while remaining > Decimal::zero() {
+        let (bid_price, bid_page) = match orderbook.orders_bid.iter_mut().next_back() {
+            Some((price, page)) if *price >= uncrossing.price => (*price, page),
+            _ => break,
+        };
+        let (ask_price, ask_page) = match orderbook.orders_ask.iter_mut().next() {
+            Some((price, page)) if *price <= uncrossing.price => (*price, page),
+            _ => break,
+        };
+        let (bid_id, ask_id, amount) = match (bid_page.orders.front(), ask_page.orders.front()) {
+            (Some((bid_id, bid)), Some((ask_id, ask))) if bid.account == ask.account => {
+                let (newer, older) = match bid_id > ask_id {
+                    true => (bid, ask),
+                    false => (ask, bid),
+                };
+                let (newer_cancelled, older_cancelled) = newer.stp.cancellations(&newer.remaining(), &older.remaining());
+                let (bid_cancelled, ask_cancelled) = match bid_id > ask_id {
+                    true => (newer_cancelled, older_cancelled),
+                    false => (older_cancelled, newer_cancelled),
+                };
+                let (bid_id, ask_id) = (*bid_id, *ask_id);
+
+                if bid_cancelled > Decimal::zero() {
+                    if bid_page.decrement(&bid_id, &bid_cancelled) {
+                        orderbook.orders_index.remove(&bid_id);
+                    }
+                    self_trades.push((bid_id, bid_cancelled));
+                }
+                if bid_page.orders.is_empty() {
+                    orderbook.orders_bid.remove(&bid_price);
+                }
+                if ask_cancelled > Decimal::zero() {
+                    if ask_page.decrement(&ask_id, &ask_cancelled) {
+                        orderbook.orders_index.remove(&ask_id);
+                    }
+                    self_trades.push((ask_id, ask_cancelled));
+                }
+                if ask_page.orders.is_empty() {
+                    orderbook.orders_ask.remove(&ask_price);
+                }
+                continue;
+            }
+            (Some((bid_id, bid)), Some((ask_id, ask))) => {
+                (*bid_id, *ask_id, bid.unfilled.min(ask.unfilled).min(remaining))
+            }
+            _ => break,
+        };
+
+        let (maker_id, taker_id, aggressor) = match bid_id < ask_id {
+            true => (bid_id, ask_id, AskOrBid::Ask),
+            false => (ask_id, bid_id, AskOrBid::Bid),
+        };
+        trades.push(Trade::new(
+            last_trade_id,
+            symbol.clone(),
+            maker_id,
+            taker_id,
+            aggressor,
+            uncrossing.price,
+            amount,
+        ));
+        remaining -= amount;
+
+        if bid_page.fill(&bid_id, &amount) {
+            orderbook.orders_index.remove(&bid_id);
+        }
+        if bid_page.orders.is_empty() {
+            orderbook.orders_bid.remove(&bid_price);
+        }
+        if ask_page.fill(&ask_id, &amount) {
+            orderbook.orders_index.remove(&ask_id);
+        }
+        if ask_page.orders.is_empty() {
+            orderbook.orders_ask.remove(&ask_price);
+        }
+    }
+
+    if !trades.is_empty() {
+        orderbook.last_trade_price = Some(uncrossing.price);
+    }
+    Some((uncrossing, trades, self_trades))
+}
+
+impl From<&Uncrossing> for JsonValue {
+    fn from(uncrossing: &Uncrossing) -> Self {
+        object! {
+            "price" => uncrossing.price.to_string(),
+            "volume" => uncrossing.volume.to_string(),
+            "imbalance" => uncrossing.imbalance.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::auction::{indicative_uncrossing, uncross, Uncrossing};
+    use crate::order_matcher::SelfTradePrevention;
+    use crate::orderbook::{Order, Orderbook};
+    use crate::symbol::{AskOrBid, Symbol};
+
+    fn new_book(orders: &[(AskOrBid, i64, i64)]) -> Orderbook {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        for (i, (side, price, amount)) in orders.iter().enumerate() {
+            orderbook
+                .insert_limit(Order::new(i as u64, i as u64, Decimal::from(*amount)), *side, Decimal::from(*price))
+                .unwrap();
+        }
+        orderbook
+    }
+
+    fn uncrossing(price: i64, volume: i64, imbalance: i64) -> Uncrossing {
+        Uncrossing {
+            price: Decimal::from(price),
+            volume: Decimal::from(volume),
+            imbalance: Decimal::from(imbalance),
+        }
+    }
+
+    #[test]
+    fn test_indicative_max_volume() {
+        let orderbook = new_book(&[
+            (AskOrBid::Bid, 102, 5),
+            (AskOrBid::Bid, 101, 5),
+            (AskOrBid::Ask, 100, 4),
+            (AskOrBid::Ask, 101, 4),
+            (AskOrBid::Ask, 103, 10),
+        ]);
+
+        //At 100 only 4 sell, at 101 8 sell against 10 buy, at 102 only 5 buy
+        assert_eq!(indicative_uncrossing(&orderbook, None), Some(uncrossing(101, 8, 2)));
+    }
+
+    #[test]
+    fn test_indicative_not_crossed() {
+        let orderbook = new_book(&[(AskOrBid::Bid, 99, 5), (AskOrBid::Ask, 100, 5)]);
+        assert_eq!(indicative_uncrossing(&orderbook, None), None);
+        assert_eq!(indicative_uncrossing(&new_book(&[]), None), None);
+    }
+
+    #[test]
+    fn test_indicative_min_imbalance_and_reference_price() {
+        //Volume of 5 at both 100 and 102, imbalance is smaller at 102
+        let orderbook = new_book(&[
+            (AskOrBid::Bid, 102, 5),
+            (AskOrBid::Bid, 100, 2),
+            (AskOrBid::Ask, 100, 5),
+            (AskOrBid::Ask, 102, 1),
+        ]);
+        assert_eq!(indicative_uncrossing(&orderbook, None), Some(uncrossing(102, 5, 1)));
+
+        //Same volume and imbalance at both prices, reference price decides
+        let orderbook = new_book(&[(AskOrBid::Bid, 102, 5), (AskOrBid::Ask, 100, 5)]);
+        assert_eq!(indicative_uncrossing(&orderbook, None), Some(uncrossing(100, 5, 0)));
+        assert_eq!(
+            indicative_uncrossing(&orderbook, Some(&Decimal::from(110))),
+            Some(uncrossing(102, 5, 0))
+        );
+    }
+
+    #[test]
+    fn test_uncross_single_price() {
+        let mut orderbook = new_book(&[
+            (AskOrBid::Bid, 102, 5),
+            (AskOrBid::Bid, 101, 5),
+            (AskOrBid::Ask, 100, 4),
+            (AskOrBid::Ask, 101, 4),
+            (AskOrBid::Ask, 103, 10),
+        ]);
+        let mut last_trade_id = 0;
+
+        let (result, trades, _) = uncross(&mut orderbook, &mut last_trade_id).unwrap();
+        assert_eq!(result, uncrossing(101, 8, 2));
+
+        let fills: Vec<(u64, u64, Decimal)> = trades
+            .iter()
+            .map(|trade| (trade.maker_id, trade.taker_id, trade.amount))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                (0, 2, Decimal::from(4)),
+                (0, 3, Decimal::ONE),
+                (1, 3, Decimal::from(3))
+            ]
+        );
+        assert_eq!(trades.iter().all(|trade| trade.price == Decimal::from(101)), true);
+        assert_eq!(last_trade_id, 3);
+
+        //Imbalance stays on the book, which isn't crossed anymore
+        assert_eq!(orderbook.last_trade_price, Some(Decimal::from(101)));
+        assert_eq!(orderbook.can_match(), false);
+        assert_eq!(orderbook.get_best_bid(), Some(Decimal::from(101)));
+        assert_eq!(orderbook.get_best_ask(), Some(Decimal::from(103)));
+        assert_eq!(orderbook.get_order_mut(&1).unwrap().unfilled, Decimal::from(2));
+        assert_eq!(orderbook.contains_order(&0), false);
+        assert_eq!(orderbook.contains_order(&3), false);
+    }
+
+    #[test]
+    fn test_uncross_iceberg() {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        orderbook
+            .insert_limit(Order::iceberg(0, 0, Decimal::from(10), Decimal::from(2)), AskOrBid::Ask, Decimal::from(100))
+            .unwrap();
+        orderbook
+            .insert_limit(Order::new(1, 1, Decimal::from(7)), AskOrBid::Bid, Decimal::from(100))
+            .unwrap();
+        let mut last_trade_id = 0;
+
+        //Hidden reserve takes part in the uncrossing
+        let (result, trades, _) = uncross(&mut orderbook, &mut last_trade_id).unwrap();
+        assert_eq!(result, uncrossing(100, 7, 3));
+        assert_eq!(trades.len(), 4);
+        assert_eq!(orderbook.orders_bid.is_empty(), true);
+        assert_eq!(orderbook.get_order_mut(&0).unwrap().remaining(), Decimal::from(3));
+    }
+
+    #[test]
+    fn test_uncross_self_trade_prevention() {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        orderbook
+            .insert_limit(Order::new(0, 7, Decimal::from(5)), AskOrBid::Ask, Decimal::from(100))
+            .unwrap();
+        orderbook
+            .insert_limit(Order::new(1, 7, Decimal::from(3)), AskOrBid::Bid, Decimal::from(101))
+            .unwrap();
+        orderbook
+            .insert_limit(Order::new(2, 8, Decimal::from(4)), AskOrBid::Bid, Decimal::from(100))
+            .unwrap();
+        let mut last_trade_id = 0;
+
+        //Later order of the same account is cancelled by default, the other bid trades instead
+        let (result, trades, self_trades) = uncross(&mut orderbook, &mut last_trade_id).unwrap();
+        assert_eq!(result, uncrossing(100, 5, 2));
+        assert_eq!(self_trades, vec![(1, Decimal::from(3))]);
+        let fills: Vec<(u64, u64, Decimal)> = trades
+            .iter()
+            .map(|trade| (trade.maker_id, trade.taker_id, trade.amount))
+            .collect();
+        assert_eq!(fills, vec![(0, 2, Decimal::from(4))]);
+        assert_eq!(orderbook.contains_order(&1), false);
+        assert_eq!(orderbook.get_order_mut(&0).unwrap().unfilled, Decimal::ONE);
+
+        //Mode of the later order applies, cancel oldest takes the earlier order off instead
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        orderbook
+            .insert_limit(Order::new(0, 7, Decimal::from(2)), AskOrBid::Ask, Decimal::from(100))
+            .unwrap();
+        orderbook
+            .insert_limit(
+                Order::new(1, 7, Decimal::from(3)).with_stp(SelfTradePrevention::CancelOldest),
+                AskOrBid::Bid,
+                Decimal::from(100),
+            )
+            .unwrap();
+        let (_, trades, self_trades) = uncross(&mut orderbook, &mut last_trade_id).unwrap();
+        assert_eq!(trades.len(), 0);
+        assert_eq!(self_trades, vec![(0, Decimal::from(2))]);
+        assert_eq!(orderbook.orders_ask.is_empty(), true);
+        assert_eq!(orderbook.get_order_mut(&1).unwrap().unfilled, Decimal::from(3));
+        assert_eq!(orderbook.last_trade_price, None);
+    }
+}
@@ -0,0 +1,59 @@
+//! Replays a write-ahead log into a fresh core without any networking and
+//! prints the trades it produces, the final state of every book and a hash of
+//! the whole state.
+//!
+//! Usage: ``replay <instrument file> <write-ahead log>``
+//!
+//! Replaying the same log with the same instruments always yields the same
+//! output, which makes it possible to reproduce incidents and to check that
+//! changes to the matchers don't alter outcomes.
+use std::env;
+
+use json::{object, JsonValue};
+use log::error;
+
+use exchange_prototype::core::ExchangeCore;
+use exchange_prototype::instrument::InstrumentRegistry;
+use exchange_prototype::wal::WriteAheadLog;
+
+fn main() {
+    env_logger::init();
+
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("Usage: {} <instrument file> <write-ahead log>", args[0]);
+        std::process::exit(2);
+    }
+
+    let instruments = match InstrumentRegistry::from_file(&args[1]) {
+        Ok(instruments) => instruments,
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // The log is only read, a torn last line is skipped but left in place
+    let entries = match WriteAheadLog::read_entries(&args[2], &instruments) {
+        Ok(entries) => entries,
+        Err(err) => {
+            error!("Unable to read write-ahead log {}: {}", args[2], err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut core = ExchangeCore::new(instruments).with_trade_capture();
+    if let Err(err) = core.replay(&entries) {
+        error!("{}", err);
+        std::process::exit(1);
+    }
+
+    let output = object! {
+        "entries" => entries.len(),
+        "last_seq" => core.last_seq(),
+        "trades" => core.take_trades().iter().map(JsonValue::from).collect::<Vec<JsonValue>>(),
+        "books" => core.books_to_json(),
+        "state_hash" => format!("{:016x}", core.state_hash())
+    };
+    println!("{}", output.pretty(2));
+}
@@ -0,0 +1,78 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::Timestamp;
+
+/// Source of the time the core processes messages at.
+///
+/// The core reads the clock once per message, everything the message causes,
+/// trades, session transitions and fee windows alike, happens at that time.
+/// Scheduled transitions happen at the time they were scheduled for.
+pub trait Clock {
+    /// Nanoseconds since unix epoch
+    fn now(&self) -> Timestamp;
+}
+
+/// Clock following the system time
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+/// Clock that only moves when told to, used by tests and to replay messages at
+/// the time they were originally processed at. Clones share the same time, so
+/// a clone kept outside of the core can move the core's clock.
+#[derive(Debug, Default, Clone)]
+pub struct SimulatedClock {
+    now: Arc<AtomicU64>,
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System time is before unix epoch!")
+            .as_nanos() as Timestamp
+    }
+}
+
+impl SimulatedClock {
+    pub fn new(now: Timestamp) -> SimulatedClock {
+        SimulatedClock {
+            now: Arc::new(AtomicU64::new(now)),
+        }
+    }
+
+    /// Moves the clock to ``now``, which may be earlier than the current time
+    pub fn set(&self, now: Timestamp) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    /// Moves the clock forward by ``nanos``
+    pub fn advance(&self, nanos: u64) {
+        self.now.fetch_add(nanos, Ordering::SeqCst);
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> Timestamp {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use crate::clock::{Clock, SimulatedClock, SystemClock};
+
+    #[test]
+    fn test_simulated_clock() {
+        let clock = SimulatedClock::new(100);
+        let shared = clock.clone();
+        clock.advance(50);
+        assert_eq!(shared.now(), 150);
+
+        shared.set(20);
+        assert_eq!(clock.now(), 20);
+        assert_eq!(SystemClock.now() > 0, true);
+    }
+}
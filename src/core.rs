@@ -1,122 +1,2854 @@
 /// Main module for the exchange prototype
 use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::Duration;
 
-use log::info;
-use rust_decimal::Decimal;
+use log::{error, info, warn};
 
+use crate::auction::{indicative_uncrossing, uncross};
+use crate::clock::{Clock, SimulatedClock, SystemClock};
+use crate::custody::{Custodian, InMemoryCustodian};
 use crate::inbound_http_server::InboundHttpServer;
 use crate::inbound_server::{InboundMessage, InboundServer, MessageType};
-use crate::order_matcher::OrderMatcher;
+use crate::error::ExchangeError;
+use crate::instrument::{Instrument, InstrumentRegistry, MatchingAlgorithm, OrderRejection};
+use crate::fees::VolumeTracker;
+use crate::journal::JournalFile;
+use crate::ledger::{is_reserved_account, Ledger};
+use crate::order_matcher::{AmendResult, Match, OrderMatcher, PostOnly, TimeInForce};
 use crate::order_matcher_fifo::OrderMatcherFifo;
-use crate::orderbook::{CancelLimitResult, InsertLimitResult, Order, Orderbook};
-use crate::symbol::Symbol;
-use crate::OrderId;
-use json::JsonValue;
+use crate::order_matcher_fifo_top_order::OrderMatcherFifoTopOrder;
+use crate::order_matcher_pro_rata::OrderMatcherProRata;
+use crate::orderbook::{InsertLimitResult, InsertMarketResult, Order, Orderbook};
+use crate::session::{Session, SessionState};
+use crate::snapshot::{fingerprint, invalid_snapshot, write_snapshot_file, SnapshotReader, SnapshotWriter};
+use crate::symbol::{AskOrBid, Symbol};
+use crate::trade::Trade;
+use crate::trigger_book::{StopOrder, TriggerBook};
+use crate::wal::{WalEntry, WriteAheadLog};
+use crate::{AccountId, OrderId, Timestamp, TradeId};
+use json::{object, JsonValue};
+use rust_decimal::{Decimal, RoundingStrategy};
+
+/// Longest time the run loop waits for a message, bounds how late a scheduled
+/// transition is applied in case the clock isn't the system clock
+const MAX_IDLE_WAIT: Duration = Duration::from_secs(1);
 
 /// Struct holding all all exchange data
 ///
 ///
 pub struct ExchangeCore {
+    /// Reference data of all instruments traded on the exchange
+    instruments: Arc<InstrumentRegistry>,
+
     /// Mapping between symbol and orderbook
     /// Used by the handler for incoming messages to look up the correct
     /// orderbook for a given symbol
     orderbooks: HashMap<Symbol, Orderbook>,
 
-    order_matcher: Box<dyn OrderMatcher>,
+    /// Mapping between symbol and the matcher implementing the instrument's
+    /// matching algorithm
+    order_matchers: HashMap<Symbol, Box<dyn OrderMatcher>>,
+
+    /// Mapping between symbol and the book of stop orders waiting for their trigger
+    trigger_books: HashMap<Symbol, TriggerBook>,
+
+    /// Mapping between symbol and the instrument's trading session, which decides
+    /// which messages are accepted and whether orders are matched
+    sessions: HashMap<Symbol, Session>,
 
     /// Mapping between OrderId and Symbol, used for lookup and cancel messages
     orderbook_id_lookup: HashMap<OrderId, Symbol>,
 
     /// Global seq number for orders, shared accross books
     last_order_id: OrderId,
+
+    /// Global seq number for trades, shared accross books
+    last_trade_id: TradeId,
+
+    /// Balances of all accounts and the funds held by their open orders
+    ledger: Ledger,
+
+    /// Trailing trade volume of each account, decides its fee tier
+    volumes: VolumeTracker,
+
+    /// Wallet service deposits and withdrawals go through before they're booked
+    custodian: Box<dyn Custodian>,
+
+    /// Log every accepted message is written to before it's processed
+    wal: Option<WriteAheadLog>,
+
+    /// File the journal entries of the ledger are appended to after every message
+    journal_file: Option<JournalFile>,
+
+    /// Sequence number of the last message written to or replayed from the log
+    last_seq: u64,
+
+    /// Set while messages are replayed from the log, they've already been
+    /// through the custodian
+    replaying: bool,
+
+    /// Source of the time messages are processed at
+    clock: Box<dyn Clock>,
+
+    /// Time the current message is processed at
+    now: Timestamp,
+
+    /// Directory snapshots are written to every ``snapshot_interval`` log entries
+    snapshot_dir: Option<PathBuf>,
+    snapshot_interval: u64,
+
+    /// Every trade settled since the last ``take_trades``, only kept if enabled
+    /// by ``with_trade_capture``
+    captured_trades: Option<Vec<Trade>>,
 }
 
 impl ExchangeCore {
-    pub fn new() -> ExchangeCore {
+    /// Creates a new core with one orderbook per registered instrument
+    pub fn new(instruments: InstrumentRegistry) -> ExchangeCore {
         let mut orderbooks = HashMap::new();
+        let mut trigger_books = HashMap::new();
+        let mut order_matchers = HashMap::new();
+        let mut sessions = HashMap::new();
         let orderbook_id_lookup = HashMap::new();
 
-        orderbooks.insert(Symbol::Asset1, Orderbook::new(Symbol::Asset1));
-        orderbooks.insert(Symbol::Asset2, Orderbook::new(Symbol::Asset2));
+        for instrument in instruments.iter() {
+            info!("Creating orderbook for {}...", instrument.symbol);
+            orderbooks.insert(instrument.symbol.clone(), Orderbook::new(instrument.symbol.clone()));
+            trigger_books.insert(instrument.symbol.clone(), TriggerBook::new());
+            order_matchers.insert(instrument.symbol.clone(), Self::create_order_matcher(instrument));
+            let mut session = Session::new(SessionState::Continuous);
+            session.reference_price = instrument.bands.reference_price;
+            sessions.insert(instrument.symbol.clone(), session);
+        }
 
         ExchangeCore {
+            instruments: Arc::new(instruments),
             orderbooks,
+            trigger_books,
             last_order_id: 0,
+            last_trade_id: 0,
+            ledger: Ledger::new(),
+            volumes: VolumeTracker::new(),
+            custodian: Box::new(InMemoryCustodian::new()),
+            wal: None,
+            journal_file: None,
+            last_seq: 0,
+            replaying: false,
+            clock: Box::new(SystemClock),
+            now: 0,
+            snapshot_dir: None,
+            snapshot_interval: 0,
+            captured_trades: None,
+            sessions,
             orderbook_id_lookup,
-            order_matcher: Box::new(OrderMatcherFifo::new()),
+            order_matchers,
+        }
+    }
+
+    /// Replaces the in-memory custodian the core is created with
+    pub fn with_custodian(mut self, custodian: Box<dyn Custodian>) -> ExchangeCore {
+        self.custodian = custodian;
+        self
+    }
+
+    /// Writes every accepted message to ``wal`` from now on. Entries already in
+    /// the log have to be replayed first, see ``replay``.
+    pub fn with_write_ahead_log(mut self, wal: WriteAheadLog) -> ExchangeCore {
+        self.wal = Some(wal);
+        self
+    }
+
+    /// Appends the journal entries of the ledger to ``file`` after every message
+    /// instead of keeping them in memory. The file outlives snapshot restores,
+    /// entries it already holds aren't appended again when the log is replayed.
+    pub fn with_journal_file(mut self, file: JournalFile) -> ExchangeCore {
+        self.journal_file = Some(file);
+        self
+    }
+
+    /// Replaces the system clock the core is created with
+    pub fn with_clock(mut self, clock: Box<dyn Clock>) -> ExchangeCore {
+        self.clock = clock;
+        self
+    }
+
+    /// Rebuilds the state of the core by processing the entries of a write-ahead
+    /// log again, in order and at the time they were accepted. The clock of the
+    /// core is replaced by a simulated one while replaying. Entries up to the
+    /// last sequence number the core has seen are skipped. Deposits and
+    /// withdrawals aren't passed to the custodian again.
+    ///
+    /// Fails if an entry was accepted with another next order id than the core
+    /// is at, the log doesn't belong to this state in that case.
+    pub fn replay(&mut self, entries: &[WalEntry]) -> io::Result<()> {
+        let wal = self.wal.take();
+        let clock = SimulatedClock::new(self.now);
+        let live_clock = std::mem::replace(&mut self.clock, Box::new(clock.clone()));
+        self.replaying = true;
+
+        let mut result = Ok(());
+        let applied = self.last_seq;
+        for entry in entries.iter().filter(|entry| entry.seq > applied) {
+            if entry.order_id != self.last_order_id + 1 {
+                result = Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Write-ahead log diverges from the core at entry {}", entry.seq),
+                ));
+                break;
+            }
+            clock.set(entry.timestamp);
+            self.process_inbound_message(&mut entry.message.clone());
+            self.last_seq = entry.seq;
+        }
+
+        self.replaying = false;
+        self.clock = live_clock;
+        self.wal = wal;
+        result
+    }
+
+    /// Writes a snapshot into ``dir`` every ``interval`` entries of the
+    /// write-ahead log, which bounds how much of the log recovery has to replay
+    pub fn with_snapshots(mut self, dir: PathBuf, interval: u64) -> ExchangeCore {
+        self.snapshot_dir = Some(dir);
+        self.snapshot_interval = interval;
+        self
+    }
+
+    /// Keeps every trade the core settles, including those of scheduled auction
+    /// uncrossings, until they're taken by ``take_trades``
+    pub fn with_trade_capture(mut self) -> ExchangeCore {
+        self.captured_trades = Some(Vec::new());
+        self
+    }
+
+    /// Trades captured since the last call in the order they were settled,
+    /// empty if capturing isn't enabled
+    pub fn take_trades(&mut self) -> Vec<Trade> {
+        self.captured_trades.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Full depth of every book along with its session state and number of
+    /// stop orders, by symbol
+    pub fn books_to_json(&self) -> JsonValue {
+        let mut symbols: Vec<&Symbol> = self.orderbooks.keys().collect();
+        symbols.sort_by_key(|symbol| symbol.as_str());
+
+        let mut books = JsonValue::new_object();
+        for symbol in symbols {
+            let mut book = JsonValue::from(&self.orderbooks[symbol]);
+            book["session"] = self.sessions[symbol].state.to_string().into();
+            book["stop_orders"] = self.trigger_books[symbol].len().into();
+            books[symbol.as_str()] = book;
+        }
+        books
+    }
+
+    /// Hash of the snapshot of the current state, equal state yields an equal
+    /// hash no matter which build computed it
+    pub fn state_hash(&self) -> u64 {
+        fingerprint(&self.snapshot())
+    }
+
+    /// Sequence number of the last log entry the state of the core contains
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    /// Encodes the state of the core: every book with its queues and order
+    /// index, stop orders, sessions, the order id lookup, balances, trailing
+    /// volumes and sequence numbers. The custodian keeps its own state.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new();
+        writer.put(&self.last_seq);
+        writer.put(&self.last_order_id);
+        writer.put(&self.last_trade_id);
+        writer.put(&self.now);
+
+        let mut symbols: Vec<&Symbol> = self.orderbooks.keys().collect();
+        symbols.sort_by_key(|symbol| symbol.as_str());
+        writer.put_len(symbols.len());
+        for symbol in symbols {
+            writer.put(&self.orderbooks[symbol]);
+            writer.put(&self.trigger_books[symbol]);
+            writer.put(&self.sessions[symbol]);
+        }
+
+        let mut lookup: Vec<(OrderId, String)> = self
+            .orderbook_id_lookup
+            .iter()
+            .map(|(order_id, symbol)| (*order_id, symbol.to_string()))
+            .collect();
+        lookup.sort_by_key(|(order_id, _)| *order_id);
+        writer.put(&lookup);
+
+        writer.put(&self.ledger);
+        writer.put(&self.volumes);
+        writer.into_bytes()
+    }
+
+    /// Restores the state of a snapshot taken by ``snapshot``. The core has to be
+    /// created with the same instruments, nothing is restored if the snapshot
+    /// can't be read.
+    pub fn restore(&mut self, snapshot: &[u8]) -> io::Result<()> {
+        let mut reader = SnapshotReader::new(snapshot)?;
+        let (last_seq, last_order_id, last_trade_id, now) =
+            (reader.get()?, reader.get()?, reader.get()?, reader.get()?);
+
+        let mut books = Vec::new();
+        for _ in 0..reader.get_len()? {
+            let orderbook: Orderbook = reader.get()?;
+            if self.instruments.get(orderbook.get_symbol()).is_none() {
+                return Err(invalid_snapshot(&format!("unknown symbol {}", orderbook.get_symbol())));
+            }
+            let trigger_book: TriggerBook = reader.get()?;
+            let session: Session = reader.get()?;
+            books.push((orderbook, trigger_book, session));
         }
+
+        let mut lookup = HashMap::new();
+        for (order_id, name) in reader.get::<Vec<(OrderId, String)>>()? {
+            let symbol = self
+                .instruments
+                .resolve(&name)
+                .ok_or_else(|| invalid_snapshot(&format!("unknown symbol {}", name)))?;
+            lookup.insert(order_id, symbol);
+        }
+        let ledger: Ledger = reader.get()?;
+        let volumes: VolumeTracker = reader.get()?;
+        reader.finish()?;
+
+        for (orderbook, trigger_book, session) in books {
+            let symbol = orderbook.get_symbol().clone();
+            self.orderbooks.insert(symbol.clone(), orderbook);
+            self.trigger_books.insert(symbol.clone(), trigger_book);
+            self.sessions.insert(symbol, session);
+        }
+        self.orderbook_id_lookup = lookup;
+        self.ledger = ledger;
+        self.volumes = volumes;
+        self.last_seq = last_seq;
+        self.last_order_id = last_order_id;
+        self.last_trade_id = last_trade_id;
+        self.now = now;
+        Ok(())
+    }
+
+    /// Writes a snapshot of the current state into ``dir``. The write-ahead log
+    /// is synced first, so it never falls behind a snapshot.
+    pub fn write_snapshot<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<PathBuf> {
+        if let Some(wal) = &mut self.wal {
+            wal.sync()?;
+        }
+        write_snapshot_file(dir, self.last_seq, &self.snapshot())
     }
 
     pub fn run(mut self) {
-        let (inbound_reciever, inbound_server) = InboundHttpServer::new();
+        let (inbound_reciever, inbound_server) = InboundHttpServer::new(self.instruments.clone());
 
         inbound_server.run();
 
         loop {
-            if let Ok(msg) = inbound_reciever.try_recv() {
-                let mut cmd = msg.cmd.clone();
-                info!("Processing inbound message: {:?}...", &cmd);
-                msg.resp
-                    .send(self.process_inbound_message(&mut cmd))
-                    .unwrap();
+            self.advance_clock(self.clock.now());
+
+            // Sleeps until the next message arrives or the next transition is due
+            let timeout = self.next_transition().map_or(MAX_IDLE_WAIT, |at| {
+                Duration::from_nanos(at.saturating_sub(self.clock.now())).min(MAX_IDLE_WAIT)
+            });
+            match inbound_reciever.recv_timeout(timeout) {
+                Ok(msg) => {
+                    let mut cmd = msg.cmd.clone();
+                    info!("Processing inbound message: {:?}...", &cmd);
+
+                    // Client might have disconnected in the meantime, that mustn't stop the loop
+                    if msg.resp.send(self.process_inbound_message(&mut cmd)).is_err() {
+                        warn!("Unable to respond to inbound message: {:?}", &cmd);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    error!("Inbound server stopped, shutting down...");
+                    return;
+                }
             }
         }
     }
 
     // Main handler for executing incoming orders
     // Processes an ``InboundMessage`` by resolving the order book and inserting the order
+    // The message is processed at the time of the clock, read once up front
     fn process_inbound_message(&mut self, msg: &mut InboundMessage) -> String {
-        match msg.message_type {
-            MessageType::PlaceLimitOrder => {
-                match (msg.limit_price, msg.amount, &msg.side, &msg.symbol) {
-                    (Some(price), Some(amount), Some(side), Some(symbol)) => {
-                        let orderbook = self
-                            .orderbooks
-                            .get_mut(symbol)
-                            .expect("Orderbook for symbol not found!");
-
-                        self.last_order_id += 1;
-
-                        let limit_result = match self.order_matcher.match_limit(
-                            orderbook,
-                            &self.last_order_id,
-                            *side,
-                            &price,
-                            &amount,
-                        ) {
-                            Some(result) => {
-                                let amount_filled: Decimal = result.makers.iter().map(|item| item.1).sum();
-                                // Order fully filled
-                                if amount_filled == amount {
-                                    InsertLimitResult::FullyFilled
-                                } else {
-                                    InsertLimitResult::PartiallyFilled(self.last_order_id, amount - amount_filled)
-                                }
-                            }
-                            None => InsertLimitResult::Success(self.last_order_id),
-                        };
-
-                        JsonValue::from(limit_result).to_string()
-                    }
-                    _ => "invalid data!".to_string(),
+        // Session has to be up to date before deciding whether the message is allowed
+        self.advance_clock(self.clock.now());
+        let seq = self.last_seq;
+
+        let result = Self::check_account(msg)
+            .and_then(|_| self.check_session(msg))
+            .and_then(|_| match msg.message_type {
+                // Queries don't change any state, transfers are logged once the custodian accepted them
+                MessageType::OpenOrders
+                | MessageType::CheckLedger
+                | MessageType::Deposit
+                | MessageType::Withdraw => Ok(()),
+                _ => self.log_message(msg),
+            })
+            .and_then(|_| match msg.message_type {
+                MessageType::PlaceLimitOrder => self.place_limit(msg),
+                MessageType::CancelLimitOrder => self.cancel_limit(msg),
+                MessageType::PlaceMarketOrder => self.place_market(msg),
+                MessageType::AmendOrder => self.amend_order(msg),
+                MessageType::PlaceStopOrder => self.place_stop(msg),
+                MessageType::SetSession => self.set_session(msg),
+                MessageType::OpenOrders => self.open_orders(msg),
+                MessageType::Deposit => self.deposit(msg),
+                MessageType::Withdraw => self.withdraw(msg),
+                MessageType::CheckLedger => self.check_ledger(),
+            });
+        self.persist_journal();
+
+        if self.last_seq != seq && self.last_seq.is_multiple_of(self.snapshot_interval.max(1)) {
+            if let Some(dir) = self.snapshot_dir.clone() {
+                match self.write_snapshot(&dir) {
+                    Ok(path) => info!("Wrote snapshot {}", path.display()),
+                    Err(err) => warn!("Unable to write snapshot: {}", err),
+                }
+            }
+        }
+
+        match result {
+            Ok(response) => response.to_string(),
+            Err(err) => {
+                info!("Unable to process inbound message: {}", err);
+                JsonValue::from(err).to_string()
+            }
+        }
+    }
+
+    /// Moves the clock of the core to ``now`` and applies the session transitions
+    /// that became due
+    fn advance_clock(&mut self, now: Timestamp) {
+        self.now = now;
+        self.apply_scheduled_transitions(now);
+        self.persist_journal();
+    }
+
+    /// Moves the journal entries of the ledger to the journal file, if there is
+    /// one. Entries that can't be written stay in memory and are retried later.
+    fn persist_journal(&mut self) {
+        if let Some(file) = &mut self.journal_file {
+            if self.ledger.journal().entries().is_empty() {
+                return;
+            }
+            if let Err(err) = self.ledger.persist_journal(file) {
+                error!("Unable to write to journal file: {}", err);
+            }
+        }
+    }
+
+    /// Appends an accepted message to the write-ahead log before it changes any
+    /// state. Messages that can't be logged aren't processed.
+    fn log_message(&mut self, msg: &InboundMessage) -> Result<(), ExchangeError> {
+        if let Some(wal) = &mut self.wal {
+            self.last_seq = wal.append(self.last_order_id + 1, self.now, msg).map_err(|err| {
+                error!("Unable to write to write-ahead log: {}", err);
+                ExchangeError::LogUnavailable
+            })?;
+        }
+        Ok(())
+    }
+
+    fn place_limit(&mut self, msg: &InboundMessage) -> Result<JsonValue, ExchangeError> {
+        let (price, amount, side, symbol) = match (msg.limit_price, msg.amount, &msg.side, &msg.symbol) {
+            (Some(price), Some(amount), Some(side), Some(symbol)) => (price, amount, *side, symbol),
+            (_, _, _, None) => return Err(ExchangeError::UnknownSymbol),
+            _ => return Err(ExchangeError::InvalidMessage),
+        };
+        let account = msg.account.ok_or(ExchangeError::InvalidMessage)?;
+
+        let time_in_force = msg.time_in_force.unwrap_or_default();
+        // Post-only orders must rest on the book
+        if msg.post_only.is_some() && time_in_force != TimeInForce::GoodTillCancel {
+            return Err(ExchangeError::InvalidMessage);
+        }
+        let in_auction = self.is_call_phase(symbol);
+
+        self.validate_order(symbol, Some(&price), &amount)?;
+        // Peak of iceberg orders has to obey the lot size just like the total amount
+        if let Some(peak) = &msg.peak {
+            self.validate_order(symbol, None, peak)?;
+        }
+
+        let slid_price = match msg.post_only {
+            Some(post_only) => self.apply_post_only(symbol, side, &price, &amount, post_only)?,
+            None => None,
+        };
+        let price = slid_price.unwrap_or(price);
+        self.check_price_bands(symbol, &price, true)?;
+
+        let order_id = self.last_order_id + 1;
+        let order = match msg.peak {
+            Some(peak) => Order::iceberg(order_id, account, amount, peak),
+            None => Order::new(order_id, account, amount),
+        }
+        .with_stp(msg.stp.unwrap_or_default());
+        let order = match msg.post_only {
+            Some(post_only) => order.with_post_only(post_only),
+            None => order,
+        };
+        self.reserve_funds(symbol, &order, side, Some(&price))?;
+
+        let orderbook = self
+            .orderbooks
+            .get_mut(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?;
+        if in_auction {
+            if let Err(err) = orderbook.insert_limit(order, side, price) {
+                self.ledger.release(&order_id);
+                return Err(err);
+            }
+            self.last_order_id = order_id;
+            self.orderbook_id_lookup.insert(order_id, symbol.clone());
+
+            let mut response = JsonValue::from(InsertLimitResult::Success(order_id));
+            response["indicative"] = Self::indicative_to_json(orderbook);
+            return Ok(response);
+        }
+
+        let order_matcher = self
+            .order_matchers
+            .get(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?;
+        let result = order_matcher.match_limit(
+            orderbook,
+            &mut self.last_trade_id,
+            order,
+            side,
+            &price,
+            time_in_force,
+        );
+        let result = self.record_match_result(symbol, order_id, result)?;
+        let limit_result = Self::limit_result(order_id, &amount, time_in_force, &result);
+        self.last_order_id = order_id;
+
+        // Remember where the remainder is resting so it can be cancelled later on
+        if self.orderbooks.get(symbol).is_some_and(|orderbook| orderbook.contains_order(&order_id)) {
+            self.orderbook_id_lookup.insert(order_id, symbol.clone());
+        }
+
+        let mut response = JsonValue::from(limit_result);
+        if msg.post_only.is_some() {
+            response["post_only"] = if slid_price.is_some() { "slid" } else { "posted" }.into();
+            response["price"] = price.to_string().into();
+        }
+        response["trades"] = Self::match_to_json(&result);
+        Self::add_self_trades(&mut response, &result);
+        response["triggered"] = self.run_triggers(symbol);
+        Ok(response)
+    }
+
+    fn place_market(&mut self, msg: &InboundMessage) -> Result<JsonValue, ExchangeError> {
+        let (amount, side, symbol) = match (msg.amount, &msg.side, &msg.symbol) {
+            (Some(amount), Some(side), Some(symbol)) => (amount, *side, symbol),
+            (_, _, None) => return Err(ExchangeError::UnknownSymbol),
+            _ => return Err(ExchangeError::InvalidMessage),
+        };
+        let account = msg.account.ok_or(ExchangeError::InvalidMessage)?;
+
+        self.validate_order(symbol, None, &amount)?;
+
+        let order_id = self.last_order_id + 1;
+        let order = Order::new(order_id, account, amount).with_stp(msg.stp.unwrap_or_default());
+        let (result, interrupted) = self.match_market_banded(symbol, order, side)?;
+        let market_result = Self::market_result(order_id, &amount, &result);
+        self.last_order_id = order_id;
+
+        let mut response = JsonValue::from(market_result);
+        response["trades"] = Self::match_to_json(&result);
+        Self::add_self_trades(&mut response, &result);
+        if interrupted {
+            response["volatility_interruption"] = self.start_volatility_interruption(symbol)?;
+        }
+        response["triggered"] = self.run_triggers(symbol);
+        Ok(response)
+    }
+
+    /// Places a stop-market order, or a stop-limit order in case a limit price is
+    /// given. The order waits in the trigger book of its symbol until the last
+    /// trade price reaches the stop price.
+    fn place_stop(&mut self, msg: &InboundMessage) -> Result<JsonValue, ExchangeError> {
+        let (stop_price, amount, side, symbol) = match (msg.stop_price, msg.amount, &msg.side, &msg.symbol) {
+            (Some(stop_price), Some(amount), Some(side), Some(symbol)) => (stop_price, amount, *side, symbol),
+            (_, _, _, None) => return Err(ExchangeError::UnknownSymbol),
+            _ => return Err(ExchangeError::InvalidMessage),
+        };
+        let account = msg.account.ok_or(ExchangeError::InvalidMessage)?;
+
+        self.validate_order(symbol, Some(&stop_price), &amount)?;
+        self.check_price_bands(symbol, &stop_price, false)?;
+        if let Some(limit_price) = &msg.limit_price {
+            self.validate_order(symbol, Some(limit_price), &amount)?;
+            self.check_price_bands(symbol, limit_price, false)?;
+        }
+
+        let order_id = self.last_order_id + 1;
+        let order = StopOrder {
+            id: order_id,
+            side,
+            stop_price,
+            limit_price: msg.limit_price,
+            amount,
+            account,
+            stp: msg.stp.unwrap_or_default(),
+        };
+
+        let last_trade_price = self
+            .orderbooks
+            .get(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?
+            .last_trade_price;
+        if last_trade_price.is_some_and(|price| order.is_triggered_by(&price)) {
+            return Err(OrderRejection::WouldTriggerImmediately.into());
+        }
+
+        self.trigger_books
+            .get_mut(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?
+            .insert(order)?;
+        self.last_order_id = order_id;
+        self.orderbook_id_lookup.insert(order_id, symbol.clone());
+
+        Ok(object! {
+            "status" => "pending",
+            "order_id" => order_id
+        })
+    }
+
+    fn cancel_limit(&mut self, msg: &InboundMessage) -> Result<JsonValue, ExchangeError> {
+        let (order_id, account) = match (msg.order_id, msg.account) {
+            (Some(order_id), Some(account)) => (order_id, account),
+            _ => return Err(ExchangeError::InvalidMessage),
+        };
+        let symbol = self
+            .orderbook_id_lookup
+            .get(&order_id)
+            .ok_or(ExchangeError::UnknownOrder(order_id))?;
+        self.authorize(symbol, &order_id, account)?;
+
+        // Order might still be waiting for its trigger
+        let cancelled = match self
+            .trigger_books
+            .get_mut(symbol)
+            .and_then(|trigger_book| trigger_book.cancel(&order_id))
+        {
+            Some(stop) => stop.amount,
+            None => {
+                self.orderbooks
+                    .get_mut(symbol)
+                    .ok_or(ExchangeError::UnknownSymbol)?
+                    .cancel_limit(&order_id)?
+                    .remaining()
+            }
+        };
+        self.orderbook_id_lookup.remove(&order_id);
+        self.ledger.release(&order_id);
+
+        Ok(object! {
+            "status" => "cancelled",
+            "order_id" => order_id,
+            "cancelled" => cancelled.to_string()
+        })
+    }
+
+    /// Amends price and / or amount of a resting order. Missing values are taken
+    /// over from the resting order.
+    fn amend_order(&mut self, msg: &InboundMessage) -> Result<JsonValue, ExchangeError> {
+        let (order_id, account) = match (msg.order_id, msg.account) {
+            (Some(order_id), Some(account)) => (order_id, account),
+            _ => return Err(ExchangeError::InvalidMessage),
+        };
+        if msg.limit_price.is_none() && msg.amount.is_none() {
+            return Err(ExchangeError::InvalidMessage);
+        }
+
+        let symbol = self
+            .orderbook_id_lookup
+            .get(&order_id)
+            .ok_or(ExchangeError::UnknownOrder(order_id))?
+            .clone();
+        self.authorize(&symbol, &order_id, account)?;
+        let orderbook = self
+            .orderbooks
+            .get_mut(&symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?;
+
+        let (side, current_price) = orderbook.get_order_location(&order_id)?;
+        let resting = orderbook
+            .get_order_mut(&order_id)
+            .ok_or(ExchangeError::BookInconsistent)?;
+        let (current_amount, post_only) = (resting.remaining(), resting.post_only);
+        let price = msg.limit_price.unwrap_or(current_price);
+        let amount = msg.amount.unwrap_or(current_amount);
+
+        self.validate_order(&symbol, Some(&price), &amount)?;
+        // Post-only orders must not take liquidity when they are amended either
+        let slid_price = match post_only {
+            Some(post_only) => self.apply_post_only(&symbol, side, &price, &amount, post_only)?,
+            None => None,
+        };
+        let price = slid_price.unwrap_or(price);
+        if msg.limit_price.is_some() {
+            self.check_price_bands(&symbol, &price, true)?;
+        }
+        // Amended order has to be covered before it's matched again
+        self.ledger
+            .resize_hold(&order_id, Self::resting_funds(side, &price, &amount))?;
+
+        if self.is_call_phase(&symbol) {
+            let response = self.amend_in_auction(&symbol, order_id, &price, &amount);
+            self.sync_holds(&symbol, &[order_id]);
+            return response;
+        }
+
+        let orderbook = self
+            .orderbooks
+            .get_mut(&symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?;
+
+        let order_matcher = self
+            .order_matchers
+            .get(&symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?;
+        let amended = order_matcher.amend_order(
+            orderbook,
+            &mut self.last_trade_id,
+            &order_id,
+            &price,
+            &amount,
+        );
+        let (priority, result) = match amended {
+            Ok(AmendResult::Reduced) => {
+                self.sync_holds(&symbol, &[order_id]);
+                ("kept", None)
+            }
+            Ok(AmendResult::Replaced(replaced)) => ("lost", self.record_match_result(&symbol, order_id, Ok(replaced))?),
+            Err(err) => {
+                // Cancel / replace may have failed after the order already left the book
+                let orderbook = self
+                    .orderbooks
+                    .get_mut(&symbol)
+                    .ok_or(ExchangeError::UnknownSymbol)?;
+                if orderbook.get_order_mut(&order_id).is_none() {
+                    orderbook.orders_index.remove(&order_id);
+                    self.orderbook_id_lookup.remove(&order_id);
+                }
+                self.sync_holds(&symbol, &[order_id]);
+                return Err(err);
+            }
+        };
+
+        let orderbook = self
+            .orderbooks
+            .get_mut(&symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?;
+        let remaining = match orderbook.get_order_mut(&order_id) {
+            Some(order) => order.remaining(),
+            None => {
+                // Amended order got fully filled
+                self.orderbook_id_lookup.remove(&order_id);
+                Decimal::ZERO
+            }
+        };
+
+        let mut response = object! {
+            "status" => "amended",
+            "order_id" => order_id,
+            "priority" => priority,
+            "remaining" => remaining.to_string(),
+            "trades" => Self::match_to_json(&result)
+        };
+        if post_only.is_some() {
+            response["post_only"] = if slid_price.is_some() { "slid" } else { "posted" }.into();
+            response["price"] = price.to_string().into();
+        }
+        Self::add_self_trades(&mut response, &result);
+        response["triggered"] = self.run_triggers(&symbol);
+        Ok(response)
+    }
+
+    /// Amends a resting order while its book is in a call auction. Just like in
+    /// continuous trading, reducing the amount keeps the order's priority, but the
+    /// replaced order rests on the book without being matched.
+    fn amend_in_auction(
+        &mut self,
+        symbol: &Symbol,
+        order_id: OrderId,
+        price: &Decimal,
+        amount: &Decimal,
+    ) -> Result<JsonValue, ExchangeError> {
+        let orderbook = self
+            .orderbooks
+            .get_mut(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?;
+
+        let (side, current_price) = orderbook.get_order_location(&order_id)?;
+        let remaining = orderbook
+            .get_order_mut(&order_id)
+            .ok_or(ExchangeError::BookInconsistent)?
+            .remaining();
+
+        let priority = if *price == current_price && *amount <= remaining {
+            orderbook.reduce_order(&order_id, amount)?;
+            "kept"
+        } else {
+            let cancelled = orderbook.cancel_limit(&order_id)?;
+            orderbook.insert_limit(cancelled.resized(*amount), side, *price)?;
+            "lost"
+        };
+
+        Ok(object! {
+            "status" => "amended",
+            "order_id" => order_id,
+            "priority" => priority,
+            "remaining" => amount.to_string(),
+            "trades" => JsonValue::new_array(),
+            "indicative" => Self::indicative_to_json(orderbook)
+        })
+    }
+
+    /// Lists the orders an account has resting on the books, optionally limited
+    /// to a single symbol. Stop orders waiting for their trigger aren't included.
+    fn open_orders(&self, msg: &InboundMessage) -> Result<JsonValue, ExchangeError> {
+        let account = msg.account.ok_or(ExchangeError::InvalidMessage)?;
+
+        let mut orders: Vec<(OrderId, JsonValue)> = Vec::new();
+        for (symbol, orderbook) in &self.orderbooks {
+            if msg.symbol.as_ref().is_some_and(|filter| filter != symbol) {
+                continue;
+            }
+            for (side, price, order) in orderbook.get_account_orders(&account) {
+                orders.push((
+                    order.id,
+                    object! {
+                        "order_id" => order.id,
+                        "symbol" => symbol.to_string(),
+                        "side" => side.to_string(),
+                        "price" => price.to_string(),
+                        "remaining" => order.remaining().to_string()
+                    },
+                ));
+            }
+        }
+        // Books aren't kept in any particular order, list the oldest orders first
+        orders.sort_by_key(|(order_id, _)| *order_id);
+
+        Ok(object! {
+            "status" => "success",
+            "account" => account,
+            "orders" => orders.into_iter().map(|(_, order)| order).collect::<Vec<JsonValue>>()
+        })
+    }
+
+    /// Checks that the balances of every asset sum up to zero and that every
+    /// journal entry is balanced, lists the assets for which that's not the case
+    fn check_ledger(&self) -> Result<JsonValue, ExchangeError> {
+        let unbalanced = self.ledger.check_consistency();
+
+        Ok(object! {
+            "status" => if unbalanced.is_empty() { "consistent" } else { "inconsistent" },
+            "unbalanced" => unbalanced,
+            "last_entry_id" => self.ledger.journal().last_entry_id()
+        })
+    }
+
+    /// Deposits funds into an account once the custodian accepted them
+    fn deposit(&mut self, msg: &InboundMessage) -> Result<JsonValue, ExchangeError> {
+        let (account, asset, amount) = Self::transfer_fields(msg)?;
+
+        if !self.replaying {
+            self.custodian.deposit(account, &asset, &amount)?;
+            if let Err(err) = self.log_message(msg) {
+                // Custodian mustn't keep funds the log doesn't know about
+                if let Err(reversal) = self.custodian.withdraw(account, &asset, &amount) {
+                    error!("Unable to reverse deposit of {} {} for account {}: {}", amount, asset, account, reversal);
+                }
+                return Err(err);
+            }
+        }
+        self.ledger.credit(account, &asset, &amount);
+        Ok(self.balance_response(account, &asset))
+    }
+
+    /// Withdraws available funds of an account through the custodian, funds
+    /// held by open orders stay untouched
+    fn withdraw(&mut self, msg: &InboundMessage) -> Result<JsonValue, ExchangeError> {
+        let (account, asset, amount) = Self::transfer_fields(msg)?;
+
+        // Custodian mustn't pay out anything the ledger can't book afterwards
+        if amount > self.ledger.balance(&account, &asset).available {
+            return Err(OrderRejection::InsufficientBalance.into());
+        }
+        if !self.replaying {
+            self.custodian.withdraw(account, &asset, &amount)?;
+            if let Err(err) = self.log_message(msg) {
+                // Payout the log doesn't know about is taken back, the ledger keeps the funds
+                if let Err(reversal) = self.custodian.deposit(account, &asset, &amount) {
+                    error!("Unable to reverse withdrawal of {} {} for account {}: {}", amount, asset, account, reversal);
                 }
+                return Err(err);
+            }
+        }
+        self.ledger.debit(account, &asset, &amount)?;
+        Ok(self.balance_response(account, &asset))
+    }
+
+    /// Account, asset and amount of a deposit or withdrawal
+    fn transfer_fields(msg: &InboundMessage) -> Result<(AccountId, String, Decimal), ExchangeError> {
+        let (account, asset, amount) = match (msg.account, &msg.asset, msg.amount) {
+            (Some(account), Some(asset), Some(amount)) => (account, asset.clone(), amount),
+            (_, None, _) => return Err(ExchangeError::UnknownAsset),
+            _ => return Err(ExchangeError::InvalidMessage),
+        };
+        if amount <= Decimal::ZERO {
+            return Err(ExchangeError::InvalidQuantity(amount));
+        }
+        Ok((account, asset, amount))
+    }
+
+    /// Response to deposits and withdrawals carrying the new balance
+    fn balance_response(&self, account: AccountId, asset: &str) -> JsonValue {
+        let balance = self.ledger.balance(&account, asset);
+        object! {
+            "status" => "success",
+            "account" => account,
+            "asset" => asset,
+            "available" => balance.available.to_string(),
+            "held" => balance.held.to_string()
+        }
+    }
+
+    /// Checks that an order may be cancelled or amended with a message from
+    /// ``account``, only the account that placed it may touch it.
+    fn authorize(&self, symbol: &Symbol, order_id: &OrderId, account: AccountId) -> Result<(), ExchangeError> {
+        let owner = match self
+            .trigger_books
+            .get(symbol)
+            .and_then(|trigger_book| trigger_book.get(order_id))
+        {
+            Some(stop) => Some(stop.account),
+            None => self
+                .orderbooks
+                .get(symbol)
+                .ok_or(ExchangeError::UnknownSymbol)?
+                .orders_index
+                .owner(order_id),
+        };
+        match owner {
+            Some(owner) if account != owner => Err(ExchangeError::NotOrderOwner(*order_id)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Moves the session of a symbol into another state, or schedules the
+    /// transition in case a timestamp is given
+    fn set_session(&mut self, msg: &InboundMessage) -> Result<JsonValue, ExchangeError> {
+        let (symbol, state) = match (&msg.symbol, msg.session_state) {
+            (Some(symbol), Some(state)) => (symbol.clone(), state),
+            (None, _) => return Err(ExchangeError::UnknownSymbol),
+            _ => return Err(ExchangeError::InvalidMessage),
+        };
+
+        match msg.at {
+            Some(at) => {
+                self.sessions
+                    .get_mut(&symbol)
+                    .ok_or(ExchangeError::UnknownSymbol)?
+                    .schedule(at, state);
+                Ok(object! {
+                    "status" => "scheduled",
+                    "symbol" => symbol.to_string(),
+                    "state" => state.to_string(),
+                    "at" => at
+                })
+            }
+            None => self.transition_session(&symbol, state),
+        }
+    }
+
+    /// Applies all scheduled session transitions that are due at ``now``.
+    ///
+    /// Transitions are applied in the order of their timestamps, transitions
+    /// that aren't valid anymore by the time they are due are dropped.
+    fn apply_scheduled_transitions(&mut self, now: Timestamp) {
+        let mut due: Vec<(Timestamp, Symbol, SessionState)> = Vec::new();
+        for (symbol, session) in self.sessions.iter_mut() {
+            due.extend(session.take_due(now).into_iter().map(|(at, state)| (at, symbol.clone(), state)));
+        }
+        // Sessions are kept in a HashMap, sort to get a deterministic trade sequence
+        due.sort_by(|(at_a, symbol_a, _), (at_b, symbol_b, _)| {
+            at_a.cmp(at_b).then_with(|| symbol_a.as_str().cmp(symbol_b.as_str()))
+        });
+
+        // Trades of a transition are stamped with the time it was scheduled for
+        // rather than when the core got to it, which differs between a live run
+        // and its replay
+        for (at, symbol, state) in due {
+            self.now = at;
+            match self.transition_session(&symbol, state) {
+                Ok(response) => info!("Scheduled session transition of {}: {}", symbol, response),
+                Err(err) => warn!("Unable to apply scheduled session transition of {}: {}", symbol, err),
+            }
+        }
+        self.now = now;
+    }
+
+    /// Time of the earliest transition scheduled for any instrument
+    fn next_transition(&self) -> Option<Timestamp> {
+        self.sessions.values().filter_map(Session::next_due).min()
+    }
+
+    /// Moves the session of a symbol into ``state``.
+    ///
+    /// Entering continuous trading or closing uncrosses the book at a single
+    /// price in case orders got crossed during a call phase. Stops triggered by
+    /// the uncrossing are executed once continuous trading starts.
+    fn transition_session(&mut self, symbol: &Symbol, state: SessionState) -> Result<JsonValue, ExchangeError> {
+        let session = self
+            .sessions
+            .get_mut(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?;
+        let previous = session.state;
+        if !previous.can_transition_to(state) {
+            return Err(ExchangeError::InvalidSessionTransition(previous, state));
+        }
+        session.state = state;
+
+        let mut response = object! {
+            "status" => "session",
+            "symbol" => symbol.to_string(),
+            "state" => state.to_string(),
+            "previous" => previous.to_string()
+        };
+        if !matches!(state, SessionState::Continuous | SessionState::Closed) {
+            return Ok(response);
+        }
+
+        let orderbook = self
+            .orderbooks
+            .get_mut(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?;
+        if let Some((uncrossing, mut trades, self_trades)) = uncross(orderbook, &mut self.last_trade_id) {
+            session.reference_price = Some(uncrossing.price);
+            self.record_match(symbol, &mut trades, &self_trades);
+            response["price"] = uncrossing.price.to_string().into();
+            response["volume"] = uncrossing.volume.to_string().into();
+            response["trades"] = JsonValue::Array(trades.iter().map(JsonValue::from).collect());
+            if !self_trades.is_empty() {
+                response["self_trade_prevented"] = Self::self_trades_to_json(&self_trades);
+            }
+        }
+        if state == SessionState::Continuous {
+            response["triggered"] = self.run_triggers(symbol);
+        }
+        Ok(response)
+    }
+
+    /// Rejects messages on behalf of the fee or system account, their funds are
+    /// only moved by the exchange itself
+    fn check_account(msg: &InboundMessage) -> Result<(), ExchangeError> {
+        match msg.account {
+            Some(account) if is_reserved_account(account) => Err(ExchangeError::InvalidMessage),
+            _ => Ok(()),
+        }
+    }
+
+    /// Rejects messages the session of their instrument doesn't allow. Cancels
+    /// and amends are checked against the session of the order's symbol.
+    fn check_session(&self, msg: &InboundMessage) -> Result<(), ExchangeError> {
+        let symbol = match (&msg.symbol, &msg.order_id) {
+            (Some(symbol), _) => Some(symbol),
+            (None, Some(order_id)) => self.orderbook_id_lookup.get(order_id),
+            (None, None) => None,
+        };
+        match symbol.and_then(|symbol| self.sessions.get(symbol)) {
+            Some(session) => Ok(session.state.check_message(msg)?),
+            // Unknown symbols and orders are reported by the handlers
+            None => Ok(()),
+        }
+    }
+
+    /// Determins whether orders of a symbol are matched as soon as they arrive
+    fn is_continuous(&self, symbol: &Symbol) -> bool {
+        self.sessions
+            .get(symbol)
+            .is_some_and(|session| session.state == SessionState::Continuous)
+    }
+
+    /// Determins whether orders of a symbol are collected without being matched
+    fn is_call_phase(&self, symbol: &Symbol) -> bool {
+        self.sessions
+            .get(symbol)
+            .is_some_and(|session| session.state.is_call_phase())
+    }
+
+    /// Creates the matcher for the matching algorithm configured for an instrument
+    fn create_order_matcher(instrument: &Instrument) -> Box<dyn OrderMatcher> {
+        match instrument.matching {
+            MatchingAlgorithm::Fifo => Box::new(OrderMatcherFifo::new()),
+            MatchingAlgorithm::ProRata { min_allocation } => {
+                Box::new(OrderMatcherProRata::new(instrument.lot_size, min_allocation))
+            }
+            MatchingAlgorithm::FifoTopOrder { priority_pct } => {
+                Box::new(OrderMatcherFifoTopOrder::new(instrument.lot_size, priority_pct))
+            }
+        }
+    }
+
+    /// Checks an order against the trading rules of its instrument before it
+    /// reaches the book
+    fn validate_order(
+        &self,
+        symbol: &Symbol,
+        price: Option<&Decimal>,
+        amount: &Decimal,
+    ) -> Result<(), ExchangeError> {
+        let instrument = self
+            .instruments
+            .get(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?;
+        Ok(instrument.validate_order(price, amount)?)
+    }
+
+    /// Makes sure a post-only order can't take liquidity.
+    ///
+    /// Returns the price the order has to be placed at instead in case it got slid
+    /// one tick behind the opposite best price.
+    fn apply_post_only(
+        &self,
+        symbol: &Symbol,
+        side: AskOrBid,
+        price: &Decimal,
+        amount: &Decimal,
+        post_only: PostOnly,
+    ) -> Result<Option<Decimal>, ExchangeError> {
+        let orderbook = self
+            .orderbooks
+            .get(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?;
+
+        if !orderbook.can_be_matched_against(side, price) {
+            return Ok(None);
+        }
+        if post_only == PostOnly::Reject {
+            return Err(OrderRejection::WouldCrossSpread.into());
+        }
+
+        let tick_size = self
+            .instruments
+            .get(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?
+            .tick_size;
+        let slid_price = match side {
+            AskOrBid::Bid => orderbook.get_best_ask().ok_or(ExchangeError::BookInconsistent)? - tick_size,
+            AskOrBid::Ask => orderbook.get_best_bid().ok_or(ExchangeError::BookInconsistent)? + tick_size,
+        };
+
+        // The new price has to obey the trading rules as well
+        self.validate_order(symbol, Some(&slid_price), amount)?;
+        Ok(Some(slid_price))
+    }
+
+    /// Executes stop orders of a symbol whose stop price has been reached by the
+    /// last trade price.
+    ///
+    /// Stops are released in rounds: each round takes every stop triggered by
+    /// the current last trade price off the trigger book and executes them in the
+    /// order they have been placed in. Fills of a round move the last trade price
+    /// and might trigger further stops, those are executed in the next round until
+    /// no more stops get triggered.
+    fn run_triggers(&mut self, symbol: &Symbol) -> JsonValue {
+        let mut triggered = JsonValue::new_array();
+
+        while let Some(last_trade_price) = self.orderbooks.get(symbol).and_then(|book| book.last_trade_price) {
+            if !self.is_continuous(symbol) {
+                break;
+            }
+            let stops = match self.trigger_books.get_mut(symbol) {
+                Some(trigger_book) => trigger_book.take_triggered(&last_trade_price),
+                None => break,
+            };
+            if stops.is_empty() {
+                break;
             }
 
-            MessageType::CancelLimitOrder => match msg.order_id {
-                Some(id) => match self.orderbook_id_lookup.get_mut(&id) {
-                    Some(symbol) => {
-                        let limit_result =
-                            self.orderbooks.get_mut(symbol).unwrap().cancel_limit(&id);
-                        if let CancelLimitResult::Success = limit_result {
-                            self.orderbook_id_lookup.remove(&id);
-                        }
-                        limit_result.to_string()
+            for stop in stops {
+                // Stops triggered before a volatility interruption wait for trading to resume
+                if !self.is_continuous(symbol) {
+                    if let Some(trigger_book) = self.trigger_books.get_mut(symbol) {
+                        let _ = trigger_book.insert(stop);
                     }
-                    None => "invalid id!".to_string(),
-                },
-                _ => "no order_id given".to_string(),
-            },
-            MessageType::PlaceMarketOrder => "not implemented".to_string(), //TODO: Use existing implementation
+                    continue;
+                }
+                let order_id = stop.id;
+                let response = match self.execute_stop(symbol, stop) {
+                    Ok(response) => response,
+                    Err(err) => {
+                        warn!("Unable to execute triggered stop order {}: {}", order_id, err);
+                        self.orderbook_id_lookup.remove(&order_id);
+                        let mut response = JsonValue::from(err);
+                        response["order_id"] = order_id.into();
+                        response
+                    }
+                };
+                // Pushing onto an array can't fail
+                let _ = triggered.push(response);
+            }
+        }
+
+        triggered
+    }
+
+    /// Executes a triggered stop order against the book, stop-limit remainders
+    /// are placed as good till cancel orders
+    fn execute_stop(&mut self, symbol: &Symbol, stop: StopOrder) -> Result<JsonValue, ExchangeError> {
+        let (mut response, result, interrupted) = match stop.limit_price {
+            Some(limit_price) => {
+                // Market might have moved away since the stop has been placed
+                self.check_price_bands(symbol, &limit_price, true)?;
+                let order = stop.order();
+                self.reserve_funds(symbol, &order, stop.side, Some(&limit_price))?;
+
+                let orderbook = self
+                    .orderbooks
+                    .get_mut(symbol)
+                    .ok_or(ExchangeError::UnknownSymbol)?;
+                let order_matcher = self
+                    .order_matchers
+                    .get(symbol)
+                    .ok_or(ExchangeError::UnknownSymbol)?;
+                let result = order_matcher.match_limit(
+                    orderbook,
+                    &mut self.last_trade_id,
+                    order,
+                    stop.side,
+                    &limit_price,
+                    TimeInForce::GoodTillCancel,
+                );
+                let result = self.record_match_result(symbol, stop.id, result)?;
+                let limit_result =
+                    Self::limit_result(stop.id, &stop.amount, TimeInForce::GoodTillCancel, &result);
+                (JsonValue::from(limit_result), result, false)
+            }
+            None => {
+                let (result, interrupted) = self.match_market_banded(symbol, stop.order(), stop.side)?;
+                let market_result = Self::market_result(stop.id, &stop.amount, &result);
+                (JsonValue::from(market_result), result, interrupted)
+            }
+        };
+
+        let resting = self
+            .orderbooks
+            .get(symbol)
+            .is_some_and(|orderbook| orderbook.contains_order(&stop.id));
+        if !resting {
+            self.orderbook_id_lookup.remove(&stop.id);
+        }
+
+        response["order_id"] = stop.id.into();
+        response["trades"] = Self::match_to_json(&result);
+        Self::add_self_trades(&mut response, &result);
+        if interrupted {
+            response["volatility_interruption"] = self.start_volatility_interruption(symbol)?;
+        }
+        Ok(response)
+    }
+
+    /// Matches a market order or a triggered stop-market order against the book.
+    ///
+    /// With a dynamic band configured, the order is only matched up to the band's
+    /// limit. Returns whether the order left liquidity beyond the band untouched
+    /// it could have taken, which calls for a volatility interruption.
+    fn match_market_banded(
+        &mut self,
+        symbol: &Symbol,
+        order: Order,
+        side: AskOrBid,
+    ) -> Result<(Option<Match>, bool), ExchangeError> {
+        self.reserve_funds(symbol, &order, side, None)?;
+
+        let instrument = self
+            .instruments
+            .get(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?;
+        let orderbook = self
+            .orderbooks
+            .get_mut(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?;
+        let order_matcher = self
+            .order_matchers
+            .get(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?;
+
+        let order_id = order.id;
+        let amount = order.remaining();
+        let band_limit = instrument.dynamic_band_limit(side, orderbook.last_trade_price.as_ref());
+        let result = match &band_limit {
+            Some(limit) => order_matcher.match_limit(
+                orderbook,
+                &mut self.last_trade_id,
+                order,
+                side,
+                limit,
+                TimeInForce::ImmediateOrCancel,
+            ),
+            None => order_matcher.match_market(orderbook, &mut self.last_trade_id, order, side),
+        };
+        let result = self.record_match_result(symbol, order_id, result)?;
+
+        // Amounts cancelled by self-trade prevention weren't left behind because of the band
+        let filled = result
+            .as_ref()
+            .map(|result| result.filled() + result.taker_cancelled())
+            .unwrap_or_default();
+        let opposite_side = match side {
+            AskOrBid::Ask => AskOrBid::Bid,
+            AskOrBid::Bid => AskOrBid::Ask,
+        };
+        let liquidity_left = self
+            .orderbooks
+            .get(symbol)
+            .is_some_and(|orderbook| orderbook.get_best_price_for_side(opposite_side).is_some());
+        let interrupted = band_limit.is_some() && filled < amount && liquidity_left;
+        Ok((result, interrupted))
+    }
+
+    /// Interrupts continuous trading of a symbol after a trade would have left the
+    /// dynamic band. The instrument is moved into its configured volatility state,
+    /// continuous trading is scheduled to resume after the volatility period.
+    fn start_volatility_interruption(&mut self, symbol: &Symbol) -> Result<JsonValue, ExchangeError> {
+        let bands = self
+            .instruments
+            .get(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?
+            .bands;
+        self.transition_session(symbol, bands.volatility_state)?;
+
+        let until = self.now + bands.volatility_period;
+        self.sessions
+            .get_mut(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?
+            .schedule(until, SessionState::Continuous);
+        warn!("Volatility interruption of {} until {}", symbol, until);
+
+        Ok(object! {
+            "state" => bands.volatility_state.to_string(),
+            "until" => until
+        })
+    }
+
+    /// Checks a price against the price bands of its instrument. The dynamic band
+    /// is only checked if ``dynamic`` is set and the instrument trades continuously.
+    fn check_price_bands(&self, symbol: &Symbol, price: &Decimal, dynamic: bool) -> Result<(), ExchangeError> {
+        let instrument = self
+            .instruments
+            .get(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?;
+        let session = self.sessions.get(symbol).ok_or(ExchangeError::UnknownSymbol)?;
+
+        instrument.check_static_band(price, session.reference_price.as_ref())?;
+        if dynamic && session.state == SessionState::Continuous {
+            let last_trade_price = self
+                .orderbooks
+                .get(symbol)
+                .and_then(|orderbook| orderbook.last_trade_price);
+            instrument.check_dynamic_band(price, last_trade_price.as_ref())?;
         }
+        Ok(())
+    }
+
+    /// Classifies the outcome of matching a limit order
+    fn limit_result(
+        order_id: OrderId,
+        amount: &Decimal,
+        time_in_force: TimeInForce,
+        result: &Option<Match>,
+    ) -> InsertLimitResult {
+        match result {
+            Some(result) => {
+                let amount_filled = result.filled();
+                // Self-trade prevention might have cancelled part of a good till cancel order
+                let remaining = amount - amount_filled - result.taker_cancelled();
+                // Order fully filled
+                if amount_filled == *amount {
+                    InsertLimitResult::FullyFilled
+                } else if time_in_force == TimeInForce::GoodTillCancel && remaining > Decimal::ZERO {
+                    match amount_filled.is_zero() {
+                        true => InsertLimitResult::Success(order_id),
+                        false => InsertLimitResult::PartiallyFilled(order_id, remaining),
+                    }
+                } else if amount_filled.is_zero() {
+                    InsertLimitResult::Cancelled(order_id, *amount)
+                } else {
+                    InsertLimitResult::PartiallyFilledCancelled(
+                        order_id,
+                        amount_filled,
+                        amount - amount_filled,
+                    )
+                }
+            }
+            None => match time_in_force {
+                TimeInForce::GoodTillCancel => InsertLimitResult::Success(order_id),
+                TimeInForce::ImmediateOrCancel => InsertLimitResult::Cancelled(order_id, *amount),
+                TimeInForce::FillOrKill => InsertLimitResult::Killed(order_id),
+            },
+        }
+    }
+
+    /// Classifies the outcome of matching a market order
+    fn market_result(order_id: OrderId, amount: &Decimal, result: &Option<Match>) -> InsertMarketResult {
+        match result {
+            Some(result) => {
+                let amount_filled = result.filled();
+                let average_price = result.average_price().unwrap_or_default();
+                if amount_filled.is_zero() {
+                    // Nothing but self-trades on the other side
+                    InsertMarketResult::NoLiquidity(order_id, *amount)
+                } else if amount_filled == *amount {
+                    InsertMarketResult::FullyFilled(order_id, amount_filled, average_price)
+                } else {
+                    InsertMarketResult::PartiallyFilled(
+                        order_id,
+                        amount_filled,
+                        average_price,
+                        amount - amount_filled,
+                    )
+                }
+            }
+            None => InsertMarketResult::NoLiquidity(order_id, *amount),
+        }
+    }
+
+    /// Updates the last trade price of the book, stamps the trades of a match or
+    /// an auction uncrossing with the time the message is processed at, so that
+    /// replay reproduces them, charges fees on them and settles them in the
+    /// ledger. Orders that got fully filled, or cancelled by self-trade
+    /// prevention, are removed from the id lookup.
+    fn record_match(&mut self, symbol: &Symbol, trades: &mut [Trade], self_trades: &[(OrderId, Decimal)]) {
+        if let (Some(orderbook), Some(trade)) = (self.orderbooks.get_mut(symbol), trades.last()) {
+            orderbook.last_trade_price = Some(trade.price);
+        }
+        if let Some(instrument) = self.instruments.clone().get(symbol) {
+            for trade in trades.iter_mut() {
+                trade.timestamp = self.now;
+                self.charge_fees(instrument, trade);
+                if let Err(err) = self.ledger.settle(trade, &instrument.base_asset, &instrument.quote_asset) {
+                    error!("Unable to settle trade {}: {}", trade.id, err);
+                }
+                if let Some(captured_trades) = &mut self.captured_trades {
+                    captured_trades.push(trade.clone());
+                }
+            }
+        }
+
+        let order_ids: Vec<OrderId> = trades
+            .iter()
+            .flat_map(|trade| [trade.maker_id, trade.taker_id])
+            .chain(self_trades.iter().map(|(order_id, _)| *order_id))
+            .collect();
+        for order_id in &order_ids {
+            if !self.orderbooks.get(symbol).is_some_and(|orderbook| orderbook.contains_order(order_id)) {
+                self.orderbook_id_lookup.remove(order_id);
+            }
+        }
+        self.sync_holds(symbol, &order_ids);
+    }
+
+    /// Records the outcome of matching an order placed by ``taker`` and brings
+    /// the taker's hold in line with what's left of it on the book, on error the
+    /// hold is released
+    fn record_match_result(
+        &mut self,
+        symbol: &Symbol,
+        taker: OrderId,
+        result: Result<Option<Match>, ExchangeError>,
+    ) -> Result<Option<Match>, ExchangeError> {
+        let mut result = match result {
+            Ok(result) => result,
+            Err(err) => {
+                self.sync_holds(symbol, &[taker]);
+                return Err(err);
+            }
+        };
+        if let Some(result) = &mut result {
+            self.record_match(symbol, &mut result.trades, &result.self_trades);
+        }
+        self.sync_holds(symbol, &[taker]);
+        Ok(result)
+    }
+
+    /// Sets the fees of both sides of a trade according to the fee tier their
+    /// account's trailing volume reaches, then adds the trade to that volume.
+    /// Fees are charged in the asset each side receives and rounded to the
+    /// precision of the received amount, fees to the nearest unit and rebates
+    /// towards zero. A fee never exceeds the amount received.
+    ///
+    /// Maker rebates are paid out of the taker fee of the same trade, in the
+    /// asset it's collected in, and never exceed it.
+    fn charge_fees(&mut self, instrument: &Instrument, trade: &mut Trade) {
+        let cost = trade.price * trade.amount;
+        let base = (trade.amount, instrument.qty_precision);
+        let quote = (cost, instrument.price_precision + instrument.qty_precision);
+        let (maker_received, taker_received) = match trade.aggressor {
+            AskOrBid::Bid => (quote, base),
+            AskOrBid::Ask => (base, quote),
+        };
+        let fee = |(received, precision): (Decimal, u32), rate: Decimal| {
+            let strategy = match rate < Decimal::ZERO {
+                true => RoundingStrategy::ToZero,
+                false => RoundingStrategy::MidpointAwayFromZero,
+            };
+            (received * rate).round_dp_with_strategy(precision, strategy).min(received)
+        };
+
+        let mut maker_rate = None;
+        if let Some(maker) = self.ledger.account_of(&trade.maker_id) {
+            let volume = self.volumes.volume(maker, &instrument.quote_asset, trade.timestamp);
+            maker_rate = Some(instrument.fees.rates(&volume).0);
+            self.volumes.record(maker, &instrument.quote_asset, trade.timestamp, &cost);
+        }
+        if let Some(taker) = self.ledger.account_of(&trade.taker_id) {
+            let volume = self.volumes.volume(taker, &instrument.quote_asset, trade.timestamp);
+            let (_, taker_rate) = instrument.fees.rates(&volume);
+            trade.taker_fee = fee(taker_received, taker_rate);
+            self.volumes.record(taker, &instrument.quote_asset, trade.timestamp, &cost);
+        }
+        trade.maker_fee = match maker_rate {
+            Some(rate) if rate < Decimal::ZERO => fee(taker_received, rate).max(-trade.taker_fee),
+            Some(rate) => fee(maker_received, rate),
+            None => trade.maker_fee,
+        };
+    }
+
+    /// Funds a resting order has to hold, quote asset for the price of bids and
+    /// base asset for the amount of asks
+    fn resting_funds(side: AskOrBid, price: &Decimal, amount: &Decimal) -> Decimal {
+        match side {
+            AskOrBid::Ask => *amount,
+            AskOrBid::Bid => price * amount,
+        }
+    }
+
+    /// Holds the funds an order needs before it's matched
+    fn reserve_funds(
+        &mut self,
+        symbol: &Symbol,
+        order: &Order,
+        side: AskOrBid,
+        price: Option<&Decimal>,
+    ) -> Result<(), ExchangeError> {
+        let instrument = self
+            .instruments
+            .get(symbol)
+            .ok_or(ExchangeError::UnknownSymbol)?;
+        let orderbook = self.orderbooks.get(symbol).ok_or(ExchangeError::UnknownSymbol)?;
+
+        let amount = order.remaining();
+        let (asset, required) = match (side, price) {
+            (AskOrBid::Ask, _) => (&instrument.base_asset, amount),
+            (AskOrBid::Bid, Some(price)) => (&instrument.quote_asset, price * amount),
+            // Market bids can't be priced up front, they hold what the book would cost at most
+            (AskOrBid::Bid, None) => (&instrument.quote_asset, orderbook.get_market_cost(side, &amount, order)),
+        };
+        self.ledger.hold(order.id, order.account, asset, required)
+    }
+
+    /// Brings the holds of orders in line with what they have left on the book,
+    /// orders that aren't resting anymore release everything they still hold
+    fn sync_holds(&mut self, symbol: &Symbol, order_ids: &[OrderId]) {
+        for order_id in order_ids {
+            let resting = self.orderbooks.get_mut(symbol).and_then(|orderbook| {
+                let (side, price) = orderbook.get_order_location(order_id).ok()?;
+                let remaining = orderbook.get_order_mut(order_id)?.remaining();
+                Some((side, price, remaining))
+            });
+            match resting {
+                // Resting orders never need more than they already hold
+                Some((side, price, remaining)) => {
+                    let required = Self::resting_funds(side, &price, &remaining);
+                    let _ = self.ledger.resize_hold(order_id, required);
+                }
+                None => {
+                    self.ledger.release(order_id);
+                }
+            }
+        }
+    }
+
+    /// Lists the amounts self-trade prevention cancelled in a response, if any
+    fn add_self_trades(response: &mut JsonValue, result: &Option<Match>) {
+        let self_trades = match result {
+            Some(result) if !result.self_trades.is_empty() => &result.self_trades,
+            _ => return,
+        };
+        response["self_trade_prevented"] = Self::self_trades_to_json(self_trades);
+    }
+
+    fn self_trades_to_json(self_trades: &[(OrderId, Decimal)]) -> JsonValue {
+        self_trades
+            .iter()
+            .map(|(order_id, cancelled)| {
+                object! {
+                    "order_id" => *order_id,
+                    "cancelled" => cancelled.to_string()
+                }
+            })
+            .collect::<Vec<JsonValue>>()
+            .into()
+    }
+
+    /// Indicative uncrossing of a book in a call auction, null if it isn't crossed
+    fn indicative_to_json(orderbook: &Orderbook) -> JsonValue {
+        match indicative_uncrossing(orderbook, orderbook.last_trade_price.as_ref()) {
+            Some(uncrossing) => JsonValue::from(&uncrossing),
+            None => JsonValue::Null,
+        }
+    }
+
+    fn match_to_json(result: &Option<Match>) -> JsonValue {
+        match result {
+            Some(result) => JsonValue::Array(result.trades.iter().map(JsonValue::from).collect()),
+            None => JsonValue::new_array(),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use std::collections::HashMap;
+
+    use json::JsonValue;
+    use rust_decimal::Decimal;
+
+    use crate::clock::SimulatedClock;
+    use crate::core::ExchangeCore;
+    use crate::error::ExchangeError;
+    use crate::inbound_server::InboundMessage;
+    use crate::instrument::InstrumentRegistry;
+    use crate::journal::{JournalFile, SYSTEM_ACCOUNT};
+    use crate::ledger::FEE_ACCOUNT;
+    use crate::order_matcher::{Match, OrderMatcher, TimeInForce};
+    use crate::orderbook::{Order, Orderbook};
+    use crate::session::SessionState;
+    use crate::snapshot::latest_snapshot;
+    use crate::symbol::{AskOrBid, Symbol};
+    use crate::wal::{FsyncPolicy, WriteAheadLog};
+    use crate::TradeId;
+
+    const INSTRUMENTS: &str = r#"{
+        "instruments": [
+            {"symbol": "BTC", "base_asset": "BTC", "quote_asset": "USD", "tick_size": "0.01", "lot_size": "0.001"},
+            {"symbol": "ETH", "base_asset": "ETH", "quote_asset": "USD", "tick_size": "0.01", "lot_size": "0.001"}
+        ]
+    }"#;
+
+    /// Accounts ``new_core`` funds for tests that don't care about who trades,
+    /// asks are placed by the seller and bids by the buyer
+    const SELLER: &str = "10";
+    const BUYER: &str = "20";
+
+    fn new_core() -> ExchangeCore {
+        funded_core(INSTRUMENTS)
+    }
+
+    /// Core trading ``instruments`` with the seller and buyer already funded
+    fn funded_core(instruments: &str) -> ExchangeCore {
+        let mut core = ExchangeCore::new(InstrumentRegistry::from_json_str(instruments).unwrap());
+        fund(&mut core, 10);
+        fund(&mut core, 20);
+        core
+    }
+
+    /// Credits ``account`` with plenty of every asset traded by the core
+    fn fund(core: &mut ExchangeCore, account: u64) {
+        let mut assets: Vec<String> = core
+            .instruments
+            .iter()
+            .flat_map(|instrument| [instrument.base_asset.clone(), instrument.quote_asset.clone()])
+            .collect();
+        assets.sort();
+        assets.dedup();
+        for asset in assets {
+            core.ledger.credit(account, &asset, &Decimal::from(1_000_000));
+        }
+    }
+
+    fn process(core: &mut ExchangeCore, params: &[(&str, &str)]) -> String {
+        let map: HashMap<String, String> = params
+            .iter()
+            .map(|(key, val)| (key.to_string(), val.to_string()))
+            .collect();
+        let mut msg = InboundMessage::from_hashmap(&map, &core.instruments).unwrap();
+        core.process_inbound_message(&mut msg)
+    }
+
+    fn process_json(core: &mut ExchangeCore, params: &[(&str, &str)]) -> JsonValue {
+        json::parse(&process(core, params)).unwrap()
+    }
+
+    #[test]
+    fn test_place_market_partially_filled() {
+        let mut core = new_core();
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "2"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "102"), ("amount", "2"), ("account", SELLER)]);
+
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "5"), ("account", BUYER)]);
+        assert_eq!(result["status"], "partially_filled");
+        assert_eq!(result["filled"], "4");
+        assert_eq!(result["average_price"], "101");
+        assert_eq!(result["cancelled"], "1");
+
+        //Each maker produced a trade at its own price
+        assert_eq!(result["trades"].len(), 2);
+        assert_eq!(result["trades"][0]["price"], "100");
+        assert_eq!(result["trades"][1]["price"], "102");
+        assert_eq!(result["trades"][0]["aggressor"], "bid");
+        assert_eq!(result["trades"][0]["taker_id"], result["order_id"]);
+
+        //Remainder has been cancelled instead of resting on the book
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "ask"), ("amount", "1"), ("account", SELLER)]);
+        assert_eq!(result["status"], "no_liquidity");
+        assert_eq!(result["cancelled"], "1");
+        assert_eq!(result["trades"].len(), 0);
+    }
+
+    #[test]
+    fn test_trade_ids_shared_across_books() {
+        let mut core = new_core();
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "1"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "eth"), ("side", "ask"), ("price", "10"), ("amount", "1"), ("account", SELLER)]);
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "1"), ("account", BUYER)]);
+        assert_eq!(result["trades"][0]["trade_id"], 1);
+        assert_eq!(result["trades"][0]["maker_id"], 1);
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "eth"), ("side", "bid"), ("price", "10"), ("amount", "1"), ("account", BUYER)]);
+        assert_eq!(result["trades"][0]["trade_id"], 2);
+        assert_eq!(result["trades"][0]["maker_id"], 2);
+    }
+
+    #[test]
+    fn test_place_market_fully_filled() {
+        let mut core = new_core();
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "eth"), ("side", "bid"), ("price", "10"), ("amount", "5"), ("account", BUYER)]);
+
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "eth"), ("side", "ask"), ("amount", "5"), ("account", SELLER)]);
+        assert_eq!(result["status"], "fully_filled");
+        assert_eq!(result["filled"], "5");
+        assert_eq!(result["average_price"], "10");
+    }
+
+    #[test]
+    fn test_orderbook_per_instrument() {
+        let core = new_core();
+        assert_eq!(core.orderbooks.len(), 2);
+        assert_eq!(core.orderbooks.contains_key(&Symbol::new("BTC")), true);
+        assert_eq!(core.orderbooks.contains_key(&Symbol::new("ETH")), true);
+    }
+
+    #[test]
+    fn test_unknown_symbol() {
+        let mut core = new_core();
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "doge"), ("side", "bid"), ("price", "10"), ("amount", "5"), ("account", BUYER)]);
+        assert_eq!(result["status"], "error");
+        assert_eq!(result["error"], "unknown_symbol");
+    }
+
+    #[test]
+    fn test_reject_invalid_orders() {
+        let mut core = new_core();
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "10.005"), ("amount", "5"), ("account", BUYER)]);
+        assert_eq!(result["status"], "rejected");
+        assert_eq!(result["reason"], "price_precision_exceeded");
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "0"), ("amount", "5"), ("account", BUYER)]);
+        assert_eq!(result["reason"], "non_positive_price");
+
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "-1"), ("account", BUYER)]);
+        assert_eq!(result["reason"], "non_positive_quantity");
+
+        //Rejected orders neither touch the book nor consume an order id
+        assert_eq!(core.orderbooks.get(&Symbol::new("BTC")).unwrap().orders_index.is_empty(), true);
+        assert_eq!(core.last_order_id, 0);
+    }
+
+    #[test]
+    fn test_place_limit_immediate_or_cancel() {
+        let mut core = new_core();
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "5"), ("tif", "ioc"), ("account", BUYER)]);
+        assert_eq!(result["status"], "cancelled");
+        assert_eq!(result["cancelled"], "5");
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "2"), ("account", SELLER)]);
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "5"), ("tif", "ioc"), ("account", BUYER)]);
+        assert_eq!(result["status"], "partially_filled");
+        assert_eq!(result["filled"], "2");
+        assert_eq!(result["cancelled"], "3");
+        assert_eq!(result["trades"].len(), 1);
+
+        //Cancelled remainder can't be looked up
+        let order_id = result["order_id"].to_string();
+        let result = process_json(&mut core, &[("action", "cancel_limit"), ("order_id", &order_id), ("account", BUYER)]);
+        assert_eq!(result["error"], "unknown_order");
+        assert_eq!(core.orderbooks.get(&Symbol::new("BTC")).unwrap().orders_index.is_empty(), true);
+    }
+
+    #[test]
+    fn test_place_limit_fill_or_kill() {
+        let mut core = new_core();
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "2"), ("account", SELLER)]);
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "3"), ("tif", "fok"), ("account", BUYER)]);
+        assert_eq!(result["status"], "killed");
+        assert_eq!(result["trades"].len(), 0);
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "2"), ("tif", "FOK"), ("account", BUYER)]);
+        assert_eq!(result["status"], "fully_filled");
+        assert_eq!(result["trades"][0]["maker_id"], 1);
+    }
+
+    #[test]
+    fn test_place_limit_post_only_reject() {
+        let mut core = new_core();
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "2"), ("account", SELLER)]);
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "1"), ("post_only", "reject"), ("account", BUYER)]);
+        assert_eq!(result["status"], "rejected");
+        assert_eq!(result["reason"], "would_cross_spread");
+        assert_eq!(core.last_order_id, 1);
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "99.99"), ("amount", "1"), ("post_only", "reject"), ("account", BUYER)]);
+        assert_eq!(result["status"], "success");
+        assert_eq!(result["post_only"], "posted");
+        assert_eq!(result["price"], "99.99");
+
+        //Post-only can't be combined with immediate execution
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "99"), ("amount", "1"), ("post_only", "reject"), ("tif", "ioc"), ("account", BUYER)]);
+        assert_eq!(result["error"], "invalid_message");
+    }
+
+    #[test]
+    fn test_place_limit_post_only_slide() {
+        let mut core = new_core();
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "2"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "90"), ("amount", "2"), ("account", BUYER)]);
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "105"), ("amount", "1"), ("post_only", "slide"), ("account", BUYER)]);
+        assert_eq!(result["status"], "success");
+        assert_eq!(result["post_only"], "slid");
+        assert_eq!(result["price"], "99.99");
+        assert_eq!(result["trades"].len(), 0);
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "80"), ("amount", "1"), ("post_only", "slide"), ("account", SELLER)]);
+        assert_eq!(result["post_only"], "slid");
+        assert_eq!(result["price"], "100.00");
+
+        let orderbook = core.orderbooks.get(&Symbol::new("BTC")).unwrap();
+        assert_eq!(orderbook.get_best_bid(), Some(Decimal::new(9999, 2)));
+        assert_eq!(orderbook.get_best_ask(), Some(Decimal::from(100)));
+        assert_eq!(orderbook.orders_ask.get(&Decimal::from(100)).unwrap().amount, Decimal::from(3));
+    }
+
+    #[test]
+    fn test_stop_market_triggered_by_trade() {
+        let mut core = new_core();
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "2"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "101"), ("amount", "2"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "102"), ("amount", "5"), ("account", SELLER)]);
+
+        let result = process_json(&mut core, &[("action", "place_stop"), ("symbol", "btc"), ("side", "bid"), ("stop_price", "101"), ("amount", "2"), ("account", BUYER)]);
+        assert_eq!(result["status"], "pending");
+        assert_eq!(result["order_id"], 4);
+
+        //Trade at 100 doesn't reach the stop price yet
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "1"), ("account", BUYER)]);
+        assert_eq!(result["triggered"].len(), 0);
+
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "2"), ("account", BUYER)]);
+        assert_eq!(result["trades"][1]["price"], "101");
+        assert_eq!(result["triggered"].len(), 1);
+        assert_eq!(result["triggered"][0]["order_id"], 4);
+        assert_eq!(result["triggered"][0]["status"], "fully_filled");
+        assert_eq!(result["triggered"][0]["trades"][0]["taker_id"], 4);
+        assert_eq!(result["triggered"][0]["trades"][0]["price"], "101");
+        assert_eq!(result["triggered"][0]["trades"][1]["price"], "102");
+
+        assert_eq!(core.trigger_books.get(&Symbol::new("BTC")).unwrap().is_empty(), true);
+        assert_eq!(core.orderbook_id_lookup.contains_key(&4), false);
+    }
+
+    #[test]
+    fn test_stop_cascade() {
+        let mut core = new_core();
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "1"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "101"), ("amount", "1"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "102"), ("amount", "1"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "103"), ("amount", "5"), ("account", SELLER)]);
+
+        //Second stop is only reached by the fills of the first one
+        process(&mut core, &[("action", "place_stop"), ("symbol", "btc"), ("side", "bid"), ("stop_price", "101"), ("amount", "1"), ("account", BUYER)]);
+        process(&mut core, &[("action", "place_stop"), ("symbol", "btc"), ("side", "bid"), ("stop_price", "100"), ("price", "101"), ("amount", "2"), ("account", BUYER)]);
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "1"), ("account", BUYER)]);
+        assert_eq!(result["triggered"].len(), 2);
+
+        //Stop-limit fills at 101, the remainder rests and can be cancelled
+        assert_eq!(result["triggered"][0]["order_id"], 6);
+        assert_eq!(result["triggered"][0]["status"], "partially_filled");
+        assert_eq!(result["triggered"][0]["remaining"], "1");
+        assert_eq!(result["triggered"][1]["order_id"], 5);
+        assert_eq!(result["triggered"][1]["trades"][0]["price"], "102");
+
+        let result = process_json(&mut core, &[("action", "cancel_limit"), ("order_id", "6"), ("account", BUYER)]);
+        assert_eq!(result["status"], "cancelled");
+        assert_eq!(result["cancelled"], "1");
+    }
+
+    #[test]
+    fn test_stop_reject_and_cancel() {
+        let mut core = new_core();
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "1"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "1"), ("account", BUYER)]);
+
+        //Last trade price is already below the sell stop
+        let result = process_json(&mut core, &[("action", "place_stop"), ("symbol", "btc"), ("side", "ask"), ("stop_price", "105"), ("amount", "1"), ("account", SELLER)]);
+        assert_eq!(result["status"], "rejected");
+        assert_eq!(result["reason"], "would_trigger_immediately");
+
+        let result = process_json(&mut core, &[("action", "place_stop"), ("symbol", "btc"), ("side", "ask"), ("stop_price", "95"), ("amount", "1"), ("account", SELLER)]);
+        let order_id = result["order_id"].to_string();
+
+        let result = process_json(&mut core, &[("action", "cancel_limit"), ("order_id", &order_id), ("account", SELLER)]);
+        assert_eq!(result["status"], "cancelled");
+        assert_eq!(result["cancelled"], "1");
+        assert_eq!(core.trigger_books.get(&Symbol::new("BTC")).unwrap().is_empty(), true);
+
+        let result = process_json(&mut core, &[("action", "place_stop"), ("symbol", "btc"), ("side", "ask"), ("amount", "1"), ("account", SELLER)]);
+        assert_eq!(result["error"], "invalid_message");
+    }
+
+    #[test]
+    fn test_iceberg_order() {
+        let mut core = new_core();
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "10"), ("peak", "2"), ("account", SELLER)]);
+        assert_eq!(result["status"], "success");
+        let page_amount = core.orderbooks.get(&Symbol::new("BTC")).unwrap().orders_ask.get(&Decimal::from(100)).unwrap().amount;
+        assert_eq!(page_amount, Decimal::from(2));
+
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "3"), ("account", BUYER)]);
+        assert_eq!(result["trades"].len(), 2);
+
+        //Amending keeps the peak, cancelling reports the hidden reserve as well
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "1"), ("amount", "9"), ("account", SELLER)]);
+        assert_eq!(result["priority"], "lost");
+        assert_eq!(result["remaining"], "9");
+        let page_amount = core.orderbooks.get(&Symbol::new("BTC")).unwrap().orders_ask.get(&Decimal::from(100)).unwrap().amount;
+        assert_eq!(page_amount, Decimal::from(2));
+
+        let result = process_json(&mut core, &[("action", "cancel_limit"), ("order_id", "1"), ("account", SELLER)]);
+        assert_eq!(result["cancelled"], "9");
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "10"), ("peak", "0.0001"), ("account", SELLER)]);
+        assert_eq!(result["reason"], "quantity_precision_exceeded");
+    }
+
+    #[test]
+    fn test_call_auction() {
+        let mut core = new_core();
+
+        let result = process_json(&mut core, &[("action", "set_session"), ("symbol", "btc"), ("state", "auction")]);
+        assert_eq!(result["state"], "auction");
+        assert_eq!(result["previous"], "continuous");
+
+        //Orders accumulate on the book without matching, the book gets crossed
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "102"), ("amount", "5"), ("account", BUYER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "4"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "101"), ("amount", "4"), ("account", SELLER)]);
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "101"), ("amount", "5"), ("account", BUYER)]);
+        assert_eq!(result["status"], "success");
+        assert_eq!(result["indicative"]["price"], "101");
+        assert_eq!(result["indicative"]["volume"], "8");
+        assert_eq!(result["indicative"]["imbalance"], "2");
+
+        //Orders that can't rest on the book are rejected
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "1"), ("account", BUYER)]);
+        assert_eq!(result["reason"], "not_allowed_in_auction");
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "1"), ("tif", "ioc"), ("account", BUYER)]);
+        assert_eq!(result["reason"], "not_allowed_in_auction");
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "1"), ("post_only", "reject"), ("account", BUYER)]);
+        assert_eq!(result["reason"], "not_allowed_in_auction");
+
+        //Other books keep trading continuously
+        process(&mut core, &[("action", "place_limit"), ("symbol", "eth"), ("side", "ask"), ("price", "100"), ("amount", "1"), ("account", SELLER)]);
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "eth"), ("side", "bid"), ("amount", "1"), ("account", BUYER)]);
+        assert_eq!(result["status"], "fully_filled");
+
+        let result = process_json(&mut core, &[("action", "set_session"), ("symbol", "btc"), ("state", "continuous")]);
+        assert_eq!(result["state"], "continuous");
+        assert_eq!(result["price"], "101");
+        assert_eq!(result["volume"], "8");
+        assert_eq!(result["trades"].len(), 3);
+        for trade in result["trades"].members() {
+            assert_eq!(trade["price"], "101");
+        }
+
+        //Fully filled orders are gone, the imbalance rests and trading is continuous again
+        let result = process_json(&mut core, &[("action", "cancel_limit"), ("order_id", "1"), ("account", BUYER)]);
+        assert_eq!(result["error"], "unknown_order");
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "ask"), ("amount", "2"), ("account", SELLER)]);
+        assert_eq!(result["status"], "fully_filled");
+        assert_eq!(result["average_price"], "101");
+    }
+
+    #[test]
+    fn test_amend_in_auction() {
+        let mut core = new_core();
+
+        process(&mut core, &[("action", "set_session"), ("symbol", "btc"), ("state", "auction")]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "4"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "99"), ("amount", "2"), ("account", BUYER)]);
+
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "1"), ("amount", "3"), ("account", SELLER)]);
+        assert_eq!(result["priority"], "kept");
+        assert_eq!(result["indicative"], JsonValue::Null);
+
+        //Crossing the book doesn't match while the auction is running
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "2"), ("price", "100"), ("account", BUYER)]);
+        assert_eq!(result["priority"], "lost");
+        assert_eq!(result["trades"].len(), 0);
+        assert_eq!(result["indicative"]["volume"], "2");
+
+        let result = process_json(&mut core, &[("action", "set_session"), ("symbol", "btc"), ("state", "continuous")]);
+        assert_eq!(result["volume"], "2");
+        let result = process_json(&mut core, &[("action", "cancel_limit"), ("order_id", "1"), ("account", SELLER)]);
+        assert_eq!(result["cancelled"], "1");
+    }
+
+    #[test]
+    fn test_session_halted_and_closed() {
+        let mut core = new_core();
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "1"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "101"), ("amount", "1"), ("account", SELLER)]);
+        process(&mut core, &[("action", "set_session"), ("symbol", "btc"), ("state", "halted")]);
+
+        //No new orders or amends while halted, cancels are still possible
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "1"), ("account", BUYER)]);
+        assert_eq!(result["reason"], "not_allowed_in_session");
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "1"), ("price", "99"), ("account", SELLER)]);
+        assert_eq!(result["reason"], "not_allowed_in_session");
+        let result = process_json(&mut core, &[("action", "cancel_limit"), ("order_id", "1"), ("account", SELLER)]);
+        assert_eq!(result["status"], "cancelled");
+
+        //Other instruments aren't affected
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "eth"), ("side", "ask"), ("price", "100"), ("amount", "1"), ("account", SELLER)]);
+        assert_eq!(result["status"], "success");
+
+        process(&mut core, &[("action", "set_session"), ("symbol", "btc"), ("state", "closed")]);
+        let result = process_json(&mut core, &[("action", "set_session"), ("symbol", "btc"), ("state", "continuous")]);
+        assert_eq!(result["error"], "invalid_session_transition");
+        assert_eq!(result["from"], "closed");
+        assert_eq!(result["to"], "continuous");
+
+        //Next day starts with pre-open, orders resting overnight are kept
+        process(&mut core, &[("action", "set_session"), ("symbol", "btc"), ("state", "pre_open")]);
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "102"), ("amount", "1"), ("account", BUYER)]);
+        assert_eq!(result["status"], "success");
+        assert_eq!(result["indicative"]["price"], "101");
+        let result = process_json(&mut core, &[("action", "set_session"), ("symbol", "btc"), ("state", "continuous")]);
+        assert_eq!(result["trades"].len(), 1);
+    }
+
+    #[test]
+    fn test_scheduled_session_transitions() {
+        let mut core = new_core();
+
+        let result = process_json(&mut core, &[("action", "set_session"), ("symbol", "btc"), ("state", "halted"), ("at", "1")]);
+        assert_eq!(result["status"], "scheduled");
+        process(&mut core, &[("action", "set_session"), ("symbol", "btc"), ("state", "continuous"), ("at", "18446744073709551615")]);
+
+        //Transition in the past is applied before the next message is processed
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "1"), ("account", SELLER)]);
+        assert_eq!(result["reason"], "not_allowed_in_session");
+        assert_eq!(core.sessions.get(&Symbol::new("BTC")).unwrap().state, SessionState::Halted);
+
+        core.apply_scheduled_transitions(u64::MAX);
+        assert_eq!(core.sessions.get(&Symbol::new("BTC")).unwrap().state, SessionState::Continuous);
+    }
+
+    #[test]
+    fn test_closing_auction_uncrosses() {
+        let mut core = new_core();
+
+        process(&mut core, &[("action", "set_session"), ("symbol", "btc"), ("state", "auction")]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "2"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "1"), ("account", BUYER)]);
+
+        let result = process_json(&mut core, &[("action", "set_session"), ("symbol", "btc"), ("state", "closed")]);
+        assert_eq!(result["volume"], "1");
+        assert_eq!(result["trades"].len(), 1);
+        assert_eq!(core.orderbooks.get(&Symbol::new("BTC")).unwrap().can_match(), false);
+    }
+
+    fn new_banded_core() -> ExchangeCore {
+        funded_core(
+            r#"{
+                "instruments": [
+                    {"symbol": "BTC", "base_asset": "BTC", "quote_asset": "USD", "tick_size": "1", "lot_size": "1",
+                     "static_band_pct": "20", "dynamic_band_pct": "5", "reference_price": "100", "volatility_period_secs": 60}
+                ]
+            }"#,
+        )
+    }
+
+    #[test]
+    fn test_price_bands() {
+        let mut core = new_banded_core();
+
+        //Static band around the reference price
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "121"), ("amount", "1"), ("account", SELLER)]);
+        assert_eq!(result["reason"], "outside_static_band");
+        let result = process_json(&mut core, &[("action", "place_stop"), ("symbol", "btc"), ("side", "ask"), ("stop_price", "79"), ("amount", "1"), ("account", SELLER)]);
+        assert_eq!(result["reason"], "outside_static_band");
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "120"), ("amount", "1"), ("account", SELLER)]);
+
+        //Dynamic band only applies once something traded
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "1"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "1"), ("account", BUYER)]);
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "106"), ("amount", "1"), ("account", BUYER)]);
+        assert_eq!(result["reason"], "outside_dynamic_band");
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "1"), ("price", "94"), ("account", SELLER)]);
+        assert_eq!(result["reason"], "outside_dynamic_band");
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "105"), ("amount", "1"), ("account", BUYER)]);
+        assert_eq!(result["status"], "success");
+
+        //Amending only the amount of an order outside the band is fine
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "1"), ("amount", "2"), ("account", SELLER)]);
+        assert_eq!(result["status"], "amended");
+    }
+
+    #[test]
+    fn test_volatility_interruption() {
+        let mut core = new_banded_core();
+
+        //Ask at 110 is only accepted since nothing traded yet
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "1"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "103"), ("amount", "1"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "110"), ("amount", "1"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "1"), ("account", BUYER)]);
+        process(&mut core, &[("action", "place_stop"), ("symbol", "btc"), ("side", "bid"), ("stop_price", "103"), ("amount", "1"), ("account", BUYER)]);
+
+        //Market order stops at the band, the ask at 110 would print outside of it
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "2"), ("account", BUYER)]);
+        assert_eq!(result["status"], "partially_filled");
+        assert_eq!(result["filled"], "1");
+        assert_eq!(result["average_price"], "103");
+        assert_eq!(result["volatility_interruption"]["state"], "auction");
+
+        //Triggered stop waits for continuous trading to resume
+        assert_eq!(result["triggered"].len(), 0);
+        assert_eq!(core.trigger_books.get(&Symbol::new("BTC")).unwrap().len(), 1);
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "1"), ("account", BUYER)]);
+        assert_eq!(result["reason"], "not_allowed_in_auction");
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "104"), ("amount", "1"), ("account", SELLER)]);
+
+        //Volatility auction ends after the configured period, the stop is executed within the band
+        core.apply_scheduled_transitions(u64::MAX);
+        assert_eq!(core.sessions.get(&Symbol::new("BTC")).unwrap().state, SessionState::Continuous);
+        assert_eq!(core.trigger_books.get(&Symbol::new("BTC")).unwrap().is_empty(), true);
+        let orderbook = core.orderbooks.get(&Symbol::new("BTC")).unwrap();
+        assert_eq!(orderbook.last_trade_price, Some(Decimal::from(104)));
+        assert_eq!(orderbook.get_best_ask(), Some(Decimal::from(110)));
+    }
+
+    #[test]
+    fn test_matching_algorithm_per_instrument() {
+        let mut core = funded_core(
+            r#"{
+            "instruments": [
+                {"symbol": "BTC", "base_asset": "BTC", "quote_asset": "USD", "tick_size": "1", "lot_size": "1"},
+                {"symbol": "ES", "base_asset": "ES", "quote_asset": "USD", "tick_size": "1", "lot_size": "1", "matching": "pro_rata"},
+                {"symbol": "NQ", "base_asset": "NQ", "quote_asset": "USD", "tick_size": "1", "lot_size": "1", "matching": "fifo_top_order", "top_order_pct": "25"}
+            ]
+        }"#,
+        );
+
+        for symbol in ["btc", "es", "nq"] {
+            process(&mut core, &[("action", "place_limit"), ("symbol", symbol), ("side", "ask"), ("price", "100"), ("amount", "10"), ("account", SELLER)]);
+            process(&mut core, &[("action", "place_limit"), ("symbol", symbol), ("side", "ask"), ("price", "100"), ("amount", "30"), ("account", SELLER)]);
+        }
+
+        //Fifo fills the first order only, pro-rata splits the fill 1:3
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "8"), ("account", BUYER)]);
+        assert_eq!(result["trades"].len(), 1);
+        assert_eq!(result["trades"][0]["amount"], "8");
+
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "es"), ("side", "bid"), ("amount", "8"), ("account", BUYER)]);
+        assert_eq!(result["trades"].len(), 2);
+        assert_eq!(result["trades"][0]["amount"], "2");
+        assert_eq!(result["trades"][1]["amount"], "6");
+
+        //Top order gets its 25% share, the second order the rest
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "nq"), ("side", "bid"), ("amount", "8"), ("account", BUYER)]);
+        assert_eq!(result["trades"].len(), 2);
+        assert_eq!(result["trades"][0]["amount"], "2");
+        assert_eq!(result["trades"][1]["amount"], "6");
+    }
+
+    #[test]
+    fn test_self_trade_prevention() {
+        let mut core = new_core();
+        fund(&mut core, 1);
+        fund(&mut core, 2);
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "2"), ("account", "1")]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "2"), ("account", "2")]);
+
+        //Own ask is cancelled, the other account's ask is traded
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "3"), ("account", "1"), ("stp", "cancel_oldest")]);
+        assert_eq!(result["status"], "partially_filled");
+        assert_eq!(result["remaining"], "1");
+        assert_eq!(result["trades"].len(), 1);
+        assert_eq!(result["trades"][0]["maker_id"], 2);
+        assert_eq!(result["self_trade_prevented"][0]["order_id"], 1);
+        assert_eq!(result["self_trade_prevented"][0]["cancelled"], "2");
+
+        let result = process_json(&mut core, &[("action", "cancel_limit"), ("order_id", "1"), ("account", "1")]);
+        assert_eq!(result["error"], "unknown_order");
+
+        //Cancel newest by default, the resting bid is left alone
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "ask"), ("amount", "1"), ("account", "1")]);
+        assert_eq!(result["status"], "no_liquidity");
+        assert_eq!(result["self_trade_prevented"][0]["order_id"], 4);
+        assert_eq!(result["self_trade_prevented"][0]["cancelled"], "1");
+
+        //Orders of other accounts trade with it as usual
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "ask"), ("amount", "1"), ("account", SELLER)]);
+        assert_eq!(result["status"], "fully_filled");
+        assert_eq!(result["self_trade_prevented"].is_null(), true);
+    }
+
+    #[test]
+    fn test_funds_reservation() {
+        let mut core = new_core();
+        core.ledger.credit(1, "USD", &Decimal::from(1000));
+        core.ledger.credit(2, "BTC", &Decimal::from(10));
+        let balance = |core: &ExchangeCore, account, asset| {
+            let balance = core.ledger.balance(&account, asset);
+            (balance.available.to_string(), balance.held.to_string())
+        };
+
+        //Bids hold price times amount of the quote asset
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "3"), ("account", "1")]);
+        assert_eq!(balance(&core, 1, "USD"), ("700".to_string(), "300".to_string()));
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "8"), ("account", "1")]);
+        assert_eq!(result["status"], "rejected");
+        assert_eq!(result["reason"], "insufficient_balance");
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "ask"), ("amount", "11"), ("account", "2")]);
+        assert_eq!(result["reason"], "insufficient_balance");
+
+        //Fills move funds at the trade price
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "90"), ("amount", "2"), ("account", "2")]);
+        assert_eq!(balance(&core, 1, "USD"), ("700".to_string(), "100".to_string()));
+        assert_eq!(balance(&core, 1, "BTC"), ("2".to_string(), "0".to_string()));
+        assert_eq!(balance(&core, 2, "BTC"), ("8".to_string(), "0".to_string()));
+        assert_eq!(balance(&core, 2, "USD"), ("200".to_string(), "0".to_string()));
+
+        //Bid filled below its limit gets the difference back
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "101"), ("amount", "1"), ("account", "2")]);
+        assert_eq!(balance(&core, 2, "BTC"), ("7".to_string(), "1".to_string()));
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "105"), ("amount", "1"), ("account", "1")]);
+        assert_eq!(balance(&core, 1, "USD"), ("599".to_string(), "100".to_string()));
+        assert_eq!(balance(&core, 2, "USD"), ("301".to_string(), "0".to_string()));
+
+        //Cancels and amends release what's no longer needed
+        process(&mut core, &[("action", "amend"), ("order_id", "1"), ("price", "50"), ("account", "1")]);
+        assert_eq!(balance(&core, 1, "USD"), ("649".to_string(), "50".to_string()));
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "1"), ("amount", "20"), ("account", "1")]);
+        assert_eq!(result["reason"], "insufficient_balance");
+        process(&mut core, &[("action", "cancel_limit"), ("order_id", "1"), ("account", "1")]);
+        assert_eq!(balance(&core, 1, "USD"), ("699".to_string(), "0".to_string()));
+        assert_eq!(core.ledger.check_consistency().is_empty(), true);
+    }
+
+    #[test]
+    fn test_unfunded_orders_never_reach_the_book() {
+        let mut core = new_core();
+
+        //Account 3 never deposited anything
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "1"), ("account", "3")]);
+        assert_eq!(result["reason"], "insufficient_balance");
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "1"), ("account", "3")]);
+        assert_eq!(result["reason"], "insufficient_balance");
+        assert_eq!(core.orderbooks.get(&Symbol::new("BTC")).unwrap().orders_index.is_empty(), true);
+
+        //Stops are checked once they trigger
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "2"), ("account", SELLER)]);
+        let result = process_json(&mut core, &[("action", "place_stop"), ("symbol", "btc"), ("side", "bid"), ("stop_price", "100"), ("amount", "1"), ("account", "3")]);
+        assert_eq!(result["status"], "pending");
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "1"), ("account", BUYER)]);
+        assert_eq!(result["triggered"][0]["reason"], "insufficient_balance");
+        assert_eq!(core.ledger.balance(&3, "BTC").available, Decimal::ZERO);
+        assert_eq!(core.ledger.check_consistency().is_empty(), true);
+    }
+
+    #[test]
+    fn test_fees() {
+        let mut core = funded_core(
+            r#"{
+                "instruments": [
+                    {"symbol": "BTC", "base_asset": "BTC", "quote_asset": "USD", "tick_size": "0.01", "lot_size": "0.001",
+                     "fee_tiers": [
+                        {"min_volume": "0", "maker_rate": "-0.001", "taker_rate": "0.002"},
+                        {"min_volume": "1000", "maker_rate": "0", "taker_rate": "0.0015"}
+                     ]}
+                ]
+            }"#,
+        );
+        core.ledger.credit(1, "BTC", &Decimal::from(20));
+        core.ledger.credit(2, "USD", &Decimal::from(5000));
+        let available = |core: &ExchangeCore, account, asset| core.ledger.balance(&account, asset).available.to_string();
+
+        //Taker pays its fee in the base asset it receives, the maker's rebate is
+        //paid out of it in the same asset, so the fee account never goes negative
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "20"), ("account", "1")]);
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "10"), ("account", "2")]);
+        assert_eq!(result["trades"][0]["maker_fee"], "-0.010");
+        assert_eq!(result["trades"][0]["taker_fee"], "0.020");
+        assert_eq!(available(&core, 1, "USD"), "1000");
+        assert_eq!(available(&core, 1, "BTC"), "0.010");
+        assert_eq!(available(&core, 2, "BTC"), "9.980");
+        assert_eq!(available(&core, FEE_ACCOUNT, "BTC"), "0.010");
+        assert_eq!(available(&core, FEE_ACCOUNT, "USD"), "0");
+
+        //Both accounts traded 1000 USD, the next fill is charged at the second tier.
+        //Fees are rounded to the precision of the amount they're paid from
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "1"), ("account", "2")]);
+        assert_eq!(result["trades"][0]["maker_fee"], "0.00000");
+        assert_eq!(result["trades"][0]["taker_fee"], "0.002");
+        assert_eq!(available(&core, FEE_ACCOUNT, "BTC"), "0.012");
+
+        //Tiers are per account, a taker without volume pays the first tier
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "1"), ("account", BUYER)]);
+        assert_eq!(result["trades"][0]["taker_fee"], "0.002");
+        assert_eq!(result["trades"][0]["maker_fee"], "0.00000");
+        assert_eq!(core.ledger.check_consistency().is_empty(), true);
+    }
+
+    #[test]
+    fn test_fees_on_tiny_fills() {
+        let mut core = funded_core(
+            r#"{
+                "instruments": [
+                    {"symbol": "BTC", "base_asset": "BTC", "quote_asset": "USD", "tick_size": "0.01", "lot_size": "0.001",
+                     "fee_tiers": [{"min_volume": "0", "maker_rate": "0", "taker_rate": "0.001"}]}
+                ]
+            }"#,
+        );
+
+        //Fee on 0.00001 USD of proceeds rounds to nothing instead of up to a whole tick
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "0.01"), ("amount", "0.001"), ("account", BUYER)]);
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "ask"), ("amount", "0.001"), ("account", SELLER)]);
+        assert_eq!(result["trades"][0]["taker_fee"], "0.00000");
+        let seller = core.ledger.balance(&10, "USD");
+        assert_eq!(seller.available + seller.held, Decimal::from(1_000_000) + Decimal::new(1, 5));
+
+        //Same on a minimum lot bought in the base asset
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "0.01"), ("amount", "0.001"), ("account", SELLER)]);
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "0.001"), ("account", BUYER)]);
+        assert_eq!(result["trades"][0]["taker_fee"], "0.000");
+        assert_eq!(core.ledger.balance(&20, "BTC").available, Decimal::from(1_000_000) + Decimal::new(2, 3));
+        assert_eq!(core.ledger.check_consistency().is_empty(), true);
+    }
+
+    #[test]
+    fn test_deposit_and_withdraw() {
+        let mut core = new_core();
+
+        let result = process_json(&mut core, &[("action", "deposit"), ("account", "1"), ("asset", "usd"), ("amount", "500")]);
+        assert_eq!(result["status"], "success");
+        assert_eq!(result["asset"], "USD");
+        assert_eq!(result["available"], "500");
+        assert_eq!(core.custodian.reserves("USD"), Decimal::from(500));
+
+        let result = process_json(&mut core, &[("action", "deposit"), ("account", "1"), ("asset", "doge"), ("amount", "1")]);
+        assert_eq!(result["error"], "unknown_asset");
+        let result = process_json(&mut core, &[("action", "deposit"), ("account", "1"), ("asset", "usd"), ("amount", "-1")]);
+        assert_eq!(result["error"], "invalid_quantity");
+        let result = process_json(&mut core, &[("action", "deposit"), ("asset", "usd"), ("amount", "1")]);
+        assert_eq!(result["error"], "invalid_message");
+
+        //Funds held by the resting bid can't be withdrawn
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "3"), ("account", "1")]);
+        let result = process_json(&mut core, &[("action", "withdraw"), ("account", "1"), ("asset", "usd"), ("amount", "201")]);
+        assert_eq!(result["reason"], "insufficient_balance");
+        assert_eq!(core.custodian.reserves("USD"), Decimal::from(500));
+
+        let result = process_json(&mut core, &[("action", "withdraw"), ("account", "1"), ("asset", "usd"), ("amount", "200")]);
+        assert_eq!(result["available"], "0");
+        assert_eq!(result["held"], "300");
+        assert_eq!(core.custodian.reserves("USD"), Decimal::from(300));
+        assert_eq!(core.ledger.check_consistency().is_empty(), true);
+    }
+
+    #[test]
+    fn test_reserved_accounts() {
+        let mut core = new_core();
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "1"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "1"), ("account", BUYER)]);
+        let fees = core.ledger.balance(&FEE_ACCOUNT, "BTC");
+
+        //Collected fees can't be withdrawn, nor can anyone trade as the fee or system account
+        let system = SYSTEM_ACCOUNT.to_string();
+        for account in ["0", system.as_str()] {
+            let result = process_json(&mut core, &[("action", "withdraw"), ("account", account), ("asset", "btc"), ("amount", "0.001")]);
+            assert_eq!(result["error"], "invalid_message");
+            let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "1"), ("account", account)]);
+            assert_eq!(result["error"], "invalid_message");
+        }
+        assert_eq!(core.ledger.balance(&FEE_ACCOUNT, "BTC"), fees);
+        assert_eq!(core.orderbooks.get(&Symbol::new("BTC")).unwrap().orders_index.len(), 0);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_transfers_reversed_if_not_logged() {
+        let mut core = new_core();
+        process(&mut core, &[("action", "deposit"), ("account", "1"), ("asset", "usd"), ("amount", "500")]);
+        core.wal = Some(WriteAheadLog::unwritable());
+
+        //Neither the custodian nor the ledger keep a transfer the log doesn't hold
+        let result = process_json(&mut core, &[("action", "deposit"), ("account", "1"), ("asset", "usd"), ("amount", "100")]);
+        assert_eq!(result["error"], "log_unavailable");
+        let result = process_json(&mut core, &[("action", "withdraw"), ("account", "1"), ("asset", "usd"), ("amount", "200")]);
+        assert_eq!(result["error"], "log_unavailable");
+        assert_eq!(core.custodian.reserves("USD"), Decimal::from(500));
+        assert_eq!(core.ledger.balance(&1, "USD").available, Decimal::from(500));
+        assert_eq!(core.wal.as_ref().unwrap().last_seq(), 0);
+    }
+
+    #[test]
+    fn test_write_ahead_log_replay() {
+        let path = std::env::temp_dir().join(format!("core_wal_test_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let instruments = InstrumentRegistry::from_json_str(INSTRUMENTS).unwrap();
+
+        let (wal, _) = WriteAheadLog::open(&path, FsyncPolicy::Always, &instruments).unwrap();
+        let mut core = new_core().with_write_ahead_log(wal);
+        process(&mut core, &[("action", "deposit"), ("account", "1"), ("asset", "usd"), ("amount", "1000")]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "3"), ("account", "1")]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "101"), ("amount", "2"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "102"), ("amount", "2"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "ask"), ("amount", "1"), ("account", SELLER)]);
+        process(&mut core, &[("action", "cancel_limit"), ("order_id", "2"), ("account", SELLER)]);
+        process(&mut core, &[("action", "open_orders"), ("account", "1")]);
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "eth"), ("side", "bid"), ("account", BUYER)]);
+        assert_eq!(result["error"], "invalid_message");
+        assert_eq!(core.last_seq, 7);
+
+        //Replaying into a fresh core rebuilds books and balances, without depositing again
+        let (wal, entries) = WriteAheadLog::open(&path, FsyncPolicy::Always, &instruments).unwrap();
+        assert_eq!(wal.last_seq(), 7);
+        let mut replayed = new_core();
+        replayed.replay(&entries).unwrap();
+        let replayed = replayed.with_write_ahead_log(wal);
+
+        let book = replayed.orderbooks.get(&Symbol::new("BTC")).unwrap();
+        assert_eq!(book.get_best_bid(), Some(Decimal::from(100)));
+        assert_eq!(book.get_best_ask(), Some(Decimal::from(102)));
+        assert_eq!(book.orders_index.len(), 2);
+        assert_eq!(replayed.last_order_id, core.last_order_id);
+        assert_eq!(replayed.last_trade_id, core.last_trade_id);
+        assert_eq!(replayed.ledger.balance(&1, "USD"), core.ledger.balance(&1, "USD"));
+        assert_eq!(replayed.ledger.balance(&1, "BTC").available, Decimal::from(1));
+        assert_eq!(replayed.custodian.reserves("USD"), Decimal::ZERO);
+
+        //Entries don't fit a core that already holds orders
+        let mut diverged = new_core();
+        process(&mut diverged, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "101"), ("amount", "2"), ("account", SELLER)]);
+        assert_eq!(diverged.replay(&entries).is_err(), true);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_replay_is_deterministic() {
+        let path = std::env::temp_dir().join(format!("core_replay_test_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let instruments = InstrumentRegistry::from_json_str(INSTRUMENTS).unwrap();
+        let clock = SimulatedClock::new(100);
+
+        let (wal, _) = WriteAheadLog::open(&path, FsyncPolicy::Always, &instruments).unwrap();
+        let mut core = new_core()
+            .with_clock(Box::new(clock.clone()))
+            .with_write_ahead_log(wal)
+            .with_trade_capture();
+        process(&mut core, &[("action", "set_session"), ("symbol", "btc"), ("state", "auction")]);
+        clock.set(200);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "101"), ("amount", "2"), ("account", BUYER)]);
+        clock.advance(100);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "1"), ("account", SELLER)]);
+        clock.advance(100);
+        process(&mut core, &[("action", "set_session"), ("symbol", "btc"), ("state", "continuous"), ("at", "1000")]);
+        //Run loop wakes up for the transition later than scheduled, replay applies it with the next entry
+        assert_eq!(core.next_transition(), Some(1000));
+        core.advance_clock(1500);
+        assert_eq!(core.next_transition(), None);
+        clock.set(2000);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "eth"), ("side", "ask"), ("price", "50"), ("amount", "1"), ("account", SELLER)]);
+        clock.set(3000);
+        process(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "ask"), ("amount", "1"), ("account", SELLER)]);
+
+        //The scheduled uncrossing is captured along with the trade of the market order,
+        //stamped with the time it was scheduled for
+        let trades = core.take_trades();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].timestamp, 1000);
+        assert_eq!(trades[1].timestamp, 3000);
+        assert_eq!(core.take_trades().len(), 0);
+
+        let entries = WriteAheadLog::read_entries(&path, &instruments).unwrap();
+        for _ in 0..2 {
+            let mut replayed = new_core().with_trade_capture();
+            replayed.replay(&entries).unwrap();
+            assert_eq!(replayed.take_trades(), trades);
+            assert_eq!(replayed.books_to_json(), core.books_to_json());
+            assert_eq!(replayed.state_hash(), core.state_hash());
+        }
+
+        let books = core.books_to_json();
+        assert_eq!(books["BTC"]["session"], "continuous");
+        assert_eq!(books["BTC"]["last_trade_price"], "101");
+        assert_eq!(books["ETH"]["asks"][0]["orders"][0]["order_id"], 3);
+        assert_eq!(new_core().state_hash() == core.state_hash(), false);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_recovery() {
+        let dir = std::env::temp_dir().join(format!("core_snapshot_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let wal_path = dir.join("wal.jsonl");
+        let instruments = InstrumentRegistry::from_json_str(INSTRUMENTS).unwrap();
+
+        let (wal, _) = WriteAheadLog::open(&wal_path, FsyncPolicy::Always, &instruments).unwrap();
+        let mut core = new_core().with_write_ahead_log(wal).with_snapshots(dir.clone(), 4);
+        process(&mut core, &[("action", "deposit"), ("account", "1"), ("asset", "btc"), ("amount", "10")]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "101"), ("amount", "2"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "101"), ("amount", "5"), ("peak", "1"), ("account", "1")]);
+        process(&mut core, &[("action", "place_stop"), ("symbol", "eth"), ("side", "bid"), ("stop_price", "50"), ("amount", "1"), ("account", BUYER)]);
+        process(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "3"), ("account", BUYER)]);
+        process(&mut core, &[("action", "set_session"), ("symbol", "eth"), ("state", "halted"), ("at", "18446744073709551615")]);
+        assert_eq!(core.last_seq(), 6);
+
+        //Snapshot was taken after the fourth entry, the rest is replayed from the log
+        let (seq, path) = latest_snapshot(&dir).unwrap().unwrap();
+        assert_eq!(seq, 4);
+        let (_, entries) = WriteAheadLog::open(&wal_path, FsyncPolicy::Always, &instruments).unwrap();
+        let mut recovered = new_core();
+        recovered.restore(&std::fs::read(path).unwrap()).unwrap();
+        assert_eq!(recovered.last_seq(), 4);
+        recovered.replay(&entries).unwrap();
+        assert_eq!(recovered.snapshot(), core.snapshot());
+
+        //Queue order within the page survived, the iceberg was moved behind order 1
+        let page = recovered.orderbooks.get(&Symbol::new("BTC")).unwrap().orders_ask.get(&Decimal::from(101)).unwrap();
+        assert_eq!(page.orders.keys().copied().collect::<Vec<u64>>(), vec![2]);
+        assert_eq!(page.get(&2).unwrap().hidden, Decimal::from(3));
+        assert_eq!(recovered.trigger_books.get(&Symbol::new("ETH")).unwrap().len(), 1);
+        assert_eq!(recovered.ledger.balance(&1, "BTC").held, Decimal::from(4));
+
+        //Corrupt snapshots leave the core untouched
+        let mut snapshot = core.snapshot();
+        snapshot.truncate(snapshot.len() - 1);
+        let mut fresh = new_core();
+        assert_eq!(fresh.restore(&snapshot).is_err(), true);
+        assert_eq!(fresh.last_order_id, 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_journal_file_and_check_ledger() {
+        let dir = std::env::temp_dir().join(format!("core_journal_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (wal_path, journal_path) = (dir.join("wal.jsonl"), dir.join("journal.csv"));
+        let instruments = InstrumentRegistry::from_json_str(INSTRUMENTS).unwrap();
+
+        let (wal, _) = WriteAheadLog::open(&wal_path, FsyncPolicy::Always, &instruments).unwrap();
+        let mut core = new_core()
+            .with_journal_file(JournalFile::open(&journal_path).unwrap())
+            .with_write_ahead_log(wal);
+        process(&mut core, &[("action", "deposit"), ("account", "1"), ("asset", "usd"), ("amount", "1000")]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "101"), ("amount", "2"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "1"), ("account", "1")]);
+
+        //Entries are moved to the file after every message instead of piling up in memory
+        let result = process_json(&mut core, &[("action", "check_ledger")]);
+        assert_eq!(result["status"], "consistent");
+        assert_eq!(result["unbalanced"].len(), 0);
+        let last_entry_id = result["last_entry_id"].as_u64().unwrap();
+        assert_eq!(core.ledger.journal().entries().len(), 0);
+        let journal = std::fs::read_to_string(&journal_path).unwrap();
+        assert_eq!(journal.lines().last().unwrap().starts_with(&format!("{},", last_entry_id)), true);
+
+        //Replaying the log after a restart doesn't book the entries twice
+        let entries = WriteAheadLog::read_entries(&wal_path, &instruments).unwrap();
+        let mut replayed = new_core().with_journal_file(JournalFile::open(&journal_path).unwrap());
+        replayed.replay(&entries).unwrap();
+        assert_eq!(replayed.ledger.journal().last_entry_id(), last_entry_id);
+        assert_eq!(std::fs::read_to_string(&journal_path).unwrap(), journal);
+
+        //Check isn't logged, it doesn't change any state
+        assert_eq!(core.last_seq(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_order_ownership() {
+        let mut core = new_core();
+        fund(&mut core, 1);
+        fund(&mut core, 2);
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "10"), ("amount", "5"), ("account", "1")]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "eth"), ("side", "ask"), ("price", "20"), ("amount", "3"), ("account", "1")]);
+        process(&mut core, &[("action", "place_stop"), ("symbol", "btc"), ("side", "ask"), ("stop_price", "5"), ("amount", "1"), ("account", "1")]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "9"), ("amount", "1"), ("account", "2")]);
+
+        //Only the owning account may cancel or amend, messages without account are invalid
+        for (account, error) in [(None, "invalid_message"), (Some("2"), "not_order_owner")] {
+            for params in [vec![("action", "cancel_limit"), ("order_id", "1")], vec![("action", "amend"), ("order_id", "1"), ("amount", "1")], vec![("action", "cancel_limit"), ("order_id", "3")]] {
+                let mut params = params;
+                if let Some(account) = account {
+                    params.push(("account", account));
+                }
+                let result = process_json(&mut core, &params);
+                assert_eq!(result["error"], error);
+            }
+        }
+
+        //Orders can't be placed without account either
+        for action in ["place_limit", "place_market", "place_stop"] {
+            let result = process_json(&mut core, &[("action", action), ("symbol", "btc"), ("side", "bid"), ("price", "10"), ("stop_price", "20"), ("amount", "1")]);
+            assert_eq!(result["error"], "invalid_message");
+        }
+
+        let result = process_json(&mut core, &[("action", "open_orders"), ("account", "1")]);
+        assert_eq!(result["orders"].len(), 2);
+        assert_eq!(result["orders"][0]["order_id"], 1);
+        assert_eq!(result["orders"][0]["remaining"], "5");
+        assert_eq!(result["orders"][1]["symbol"], "ETH");
+        assert_eq!(result["orders"][1]["side"], "ask");
+
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "1"), ("amount", "2"), ("account", "1")]);
+        assert_eq!(result["status"], "amended");
+        let result = process_json(&mut core, &[("action", "cancel_limit"), ("order_id", "2"), ("account", "1")]);
+        assert_eq!(result["status"], "cancelled");
+
+        let result = process_json(&mut core, &[("action", "open_orders"), ("symbol", "btc"), ("account", "1")]);
+        assert_eq!(result["orders"].len(), 1);
+        assert_eq!(result["orders"][0]["remaining"], "2");
+
+        //Listing open orders requires an account
+        let result = process_json(&mut core, &[("action", "open_orders")]);
+        assert_eq!(result["error"], "invalid_message");
+    }
+
+    #[test]
+    fn test_cancel_resting_limit() {
+        let mut core = new_core();
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "10"), ("amount", "5"), ("account", BUYER)]);
+        let order_id = result["order_id"].to_string();
+
+        let result = process_json(&mut core, &[("action", "cancel_limit"), ("order_id", &order_id), ("account", BUYER)]);
+        assert_eq!(result["status"], "cancelled");
+        assert_eq!(result["cancelled"], "5");
+
+        //Order is gone after the first cancel
+        let result = process_json(&mut core, &[("action", "cancel_limit"), ("order_id", &order_id), ("account", BUYER)]);
+        assert_eq!(result["status"], "error");
+        assert_eq!(result["error"], "unknown_order");
+    }
+
+    #[test]
+    fn test_amend_reduce_keeps_priority() {
+        let mut core = new_core();
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "5"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "5"), ("account", SELLER)]);
+
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "1"), ("amount", "2"), ("account", SELLER)]);
+        assert_eq!(result["status"], "amended");
+        assert_eq!(result["priority"], "kept");
+        assert_eq!(result["remaining"], "2");
+
+        //First order is still first in line
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "3"), ("account", BUYER)]);
+        assert_eq!(result["trades"][0]["maker_id"], 1);
+        assert_eq!(result["trades"][0]["amount"], "2");
+        assert_eq!(result["trades"][1]["maker_id"], 2);
+    }
+
+    #[test]
+    fn test_amend_increase_loses_priority() {
+        let mut core = new_core();
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "5"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "5"), ("account", SELLER)]);
+
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "1"), ("amount", "6"), ("account", SELLER)]);
+        assert_eq!(result["priority"], "lost");
+        assert_eq!(result["remaining"], "6");
+
+        let result = process_json(&mut core, &[("action", "place_market"), ("symbol", "btc"), ("side", "bid"), ("amount", "3"), ("account", BUYER)]);
+        assert_eq!(result["trades"][0]["maker_id"], 2);
+    }
+
+    #[test]
+    fn test_amend_price_triggers_matching() {
+        let mut core = new_core();
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "5"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "90"), ("amount", "3"), ("account", BUYER)]);
+
+        //Moving the bid across the spread executes it against the ask
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "2"), ("price", "100"), ("account", BUYER)]);
+        assert_eq!(result["priority"], "lost");
+        assert_eq!(result["remaining"], "0");
+        assert_eq!(result["trades"][0]["maker_id"], 1);
+        assert_eq!(result["trades"][0]["taker_id"], 2);
+        assert_eq!(result["trades"][0]["amount"], "3");
+
+        //Fully filled order can't be amended anymore
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "2"), ("amount", "1"), ("account", BUYER)]);
+        assert_eq!(result["error"], "unknown_order");
+
+        //Invalid amendments leave the order untouched
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "1"), ("price", "100.001"), ("account", SELLER)]);
+        assert_eq!(result["reason"], "price_precision_exceeded");
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "1"), ("account", SELLER)]);
+        assert_eq!(result["error"], "invalid_message");
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "1"), ("amount", "2"), ("account", SELLER)]);
+        assert_eq!(result["remaining"], "2");
+    }
+
+    /// Matcher that fails every match, the order being amended is already off
+    /// the book by then
+    struct FailingMatcher;
+
+    impl OrderMatcher for FailingMatcher {
+        fn match_limit(
+            &self,
+            _orderbook: &mut Orderbook,
+            _last_trade_id: &mut TradeId,
+            _order: Order,
+            _side: AskOrBid,
+            _price: &Decimal,
+            _time_in_force: TimeInForce,
+        ) -> Result<Option<Match>, ExchangeError> {
+            Err(ExchangeError::BookInconsistent)
+        }
+
+        fn match_market(
+            &self,
+            _orderbook: &mut Orderbook,
+            _last_trade_id: &mut TradeId,
+            _order: Order,
+            _side: AskOrBid,
+        ) -> Result<Option<Match>, ExchangeError> {
+            Err(ExchangeError::BookInconsistent)
+        }
+    }
+
+    #[test]
+    fn test_failed_amend_forgets_removed_order() {
+        let mut core = new_core();
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "100"), ("amount", "2"), ("account", BUYER)]);
+        core.order_matchers.insert(Symbol::new("BTC"), Box::new(FailingMatcher));
+
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "1"), ("price", "101"), ("account", BUYER)]);
+        assert_eq!(result["error"], "book_inconsistent");
+        assert_eq!(core.ledger.held_by(&1), Decimal::ZERO);
+
+        //Order is gone, so is every trace of it
+        assert_eq!(core.orderbook_id_lookup.contains_key(&1), false);
+        let orderbook = core.orderbooks.get(&Symbol::new("BTC")).unwrap();
+        assert_eq!(orderbook.orders_index.account_orders(&20).count(), 0);
+        let result = process_json(&mut core, &[("action", "cancel_limit"), ("order_id", "1"), ("account", BUYER)]);
+        assert_eq!(result["error"], "unknown_order");
+        let result = process_json(&mut core, &[("action", "open_orders"), ("account", BUYER)]);
+        assert_eq!(result["orders"].len(), 0);
+    }
+
+    #[test]
+    fn test_amend_keeps_post_only() {
+        let mut core = new_core();
+
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "100"), ("amount", "5"), ("account", SELLER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "90"), ("amount", "1"), ("post_only", "reject"), ("account", BUYER)]);
+        process(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "90"), ("amount", "1"), ("post_only", "slide"), ("account", BUYER)]);
+
+        //Rejecting order stays where it is instead of taking liquidity
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "2"), ("price", "100"), ("account", BUYER)]);
+        assert_eq!(result["status"], "rejected");
+        assert_eq!(result["reason"], "would_cross_spread");
+        let orderbook = core.orderbooks.get(&Symbol::new("BTC")).unwrap();
+        assert_eq!(orderbook.get_order_location(&2).unwrap(), (AskOrBid::Bid, Decimal::from(90)));
+
+        //Sliding order is moved one tick behind the best ask
+        let result = process_json(&mut core, &[("action", "amend"), ("order_id", "3"), ("price", "105"), ("account", BUYER)]);
+        assert_eq!(result["status"], "amended");
+        assert_eq!(result["post_only"], "slid");
+        assert_eq!(result["price"], "99.99");
+        assert_eq!(result["trades"].len(), 0);
+        let orderbook = core.orderbooks.get(&Symbol::new("BTC")).unwrap();
+        assert_eq!(orderbook.get_best_bid(), Some(Decimal::new(9999, 2)));
+        assert_eq!(orderbook.get_best_ask(), Some(Decimal::from(100)));
+    }
+
+    #[test]
+    fn test_invalid_messages_dont_stop_processing() {
+        let mut core = new_core();
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("amount", "5"), ("account", BUYER)]);
+        assert_eq!(result["error"], "invalid_message");
+
+        let result = process_json(&mut core, &[("action", "cancel_limit")]);
+        assert_eq!(result["error"], "invalid_message");
+
+        let result = process_json(&mut core, &[("action", "cancel_limit"), ("order_id", "42"), ("account", BUYER)]);
+        assert_eq!(result["error"], "unknown_order");
+        assert_eq!(result["order_id"], 42);
+
+        let result = process_json(&mut core, &[("action", "place_limit"), ("symbol", "btc"), ("side", "bid"), ("price", "10"), ("amount", "5"), ("account", BUYER)]);
+        assert_eq!(result["status"], "success");
     }
 }
@@ -0,0 +1,255 @@
+use core::fmt;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use json::{object, JsonValue};
+use rust_decimal::prelude::FromStr;
+use rust_decimal::Decimal;
+
+use crate::AccountId;
+
+/// Errors reported by a custodian, the balance change is not booked in that case
+#[derive(Debug, Clone, PartialEq)]
+pub enum CustodyError {
+    /// Custodian doesn't hold enough of the asset to pay out the withdrawal
+    InsufficientReserves,
+
+    /// Transfer couldn't be persisted
+    Io(String),
+}
+
+impl fmt::Display for CustodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CustodyError::InsufficientReserves => write!(f, "insufficient_reserves"),
+            CustodyError::Io(_) => write!(f, "custody_unavailable"),
+        }
+    }
+}
+
+/// Direction of a transfer between an account's wallet and the exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferKind {
+    Deposit,
+    Withdrawal,
+}
+
+/// Funds moved in or out of the exchange on behalf of an account
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transfer {
+    pub kind: TransferKind,
+    pub account: AccountId,
+    pub asset: String,
+    pub amount: Decimal,
+}
+
+/// Wallet service holding the funds deposited to the exchange.
+///
+/// Deposits and withdrawals are only booked in the ledger once the custodian
+/// accepted them.
+pub trait Custodian {
+    /// Accepts ``amount`` of ``asset`` deposited by ``account``
+    fn deposit(&mut self, account: AccountId, asset: &str, amount: &Decimal) -> Result<(), CustodyError>;
+
+    /// Pays out ``amount`` of ``asset`` to ``account``
+    fn withdraw(&mut self, account: AccountId, asset: &str, amount: &Decimal) -> Result<(), CustodyError>;
+
+    /// Total of an asset held in custody
+    fn reserves(&self, asset: &str) -> Decimal;
+}
+
+impl fmt::Display for TransferKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransferKind::Deposit => write!(f, "deposit"),
+            TransferKind::Withdrawal => write!(f, "withdrawal"),
+        }
+    }
+}
+
+impl From<&Transfer> for JsonValue {
+    fn from(transfer: &Transfer) -> Self {
+        object! {
+            "kind" => transfer.kind.to_string(),
+            "account" => transfer.account,
+            "asset" => transfer.asset.clone(),
+            "amount" => transfer.amount.to_string()
+        }
+    }
+}
+
+impl Transfer {
+    pub fn new(kind: TransferKind, account: AccountId, asset: &str, amount: &Decimal) -> Transfer {
+        Transfer {
+            kind,
+            account,
+            asset: asset.to_string(),
+            amount: *amount,
+        }
+    }
+
+    /// Parses a transfer written by ``From<&Transfer> for JsonValue``
+    fn from_json(value: &JsonValue) -> Option<Transfer> {
+        Some(Transfer {
+            kind: match value["kind"].as_str()? {
+                "deposit" => TransferKind::Deposit,
+                "withdrawal" => TransferKind::Withdrawal,
+                _ => return None,
+            },
+            account: value["account"].as_u64()?,
+            asset: value["asset"].as_str()?.to_string(),
+            amount: Decimal::from_str(value["amount"].as_str()?).ok()?,
+        })
+    }
+}
+
+/// Custodian keeping its reserves in memory, every transfer is accepted unless
+/// it would overdraw the reserves
+#[derive(Default)]
+pub struct InMemoryCustodian {
+    reserves: HashMap<String, Decimal>,
+
+    /// All accepted transfers, oldest first
+    pub transfers: Vec<Transfer>,
+}
+
+impl InMemoryCustodian {
+    pub fn new() -> InMemoryCustodian {
+        InMemoryCustodian::default()
+    }
+
+    /// Checks that a withdrawal doesn't overdraw the reserves
+    fn check(&self, transfer: &Transfer) -> Result<(), CustodyError> {
+        match transfer.kind {
+            TransferKind::Withdrawal if transfer.amount > self.reserves(&transfer.asset) => {
+                Err(CustodyError::InsufficientReserves)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Books a transfer against the reserves
+    fn apply(&mut self, transfer: Transfer) -> Result<(), CustodyError> {
+        self.check(&transfer)?;
+        let reserves = self.reserves.entry(transfer.asset.clone()).or_default();
+        match transfer.kind {
+            TransferKind::Deposit => *reserves += transfer.amount,
+            TransferKind::Withdrawal => *reserves -= transfer.amount,
+        }
+        self.transfers.push(transfer);
+        Ok(())
+    }
+}
+
+impl Custodian for InMemoryCustodian {
+    fn deposit(&mut self, account: AccountId, asset: &str, amount: &Decimal) -> Result<(), CustodyError> {
+        self.apply(Transfer::new(TransferKind::Deposit, account, asset, amount))
+    }
+
+    fn withdraw(&mut self, account: AccountId, asset: &str, amount: &Decimal) -> Result<(), CustodyError> {
+        self.apply(Transfer::new(TransferKind::Withdrawal, account, asset, amount))
+    }
+
+    fn reserves(&self, asset: &str) -> Decimal {
+        self.reserves.get(asset).copied().unwrap_or_default()
+    }
+}
+
+/// Custodian appending every accepted transfer to a file, one json object per
+/// line. Reserves are restored from the file when it's opened.
+pub struct FileCustodian {
+    path: PathBuf,
+    custodian: InMemoryCustodian,
+}
+
+impl FileCustodian {
+    /// Opens the transfer file at ``path``, a missing file starts out empty
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FileCustodian, CustodyError> {
+        let mut custodian = InMemoryCustodian::new();
+        if path.as_ref().exists() {
+            let file = File::open(&path).map_err(|err| CustodyError::Io(err.to_string()))?;
+            for line in BufReader::new(file).lines() {
+                let line = line.map_err(|err| CustodyError::Io(err.to_string()))?;
+                let transfer = json::parse(&line)
+                    .ok()
+                    .and_then(|value| Transfer::from_json(&value))
+                    .ok_or_else(|| CustodyError::Io(format!("Invalid transfer: {}", line)))?;
+                custodian.apply(transfer)?;
+            }
+        }
+
+        Ok(FileCustodian {
+            path: path.as_ref().to_path_buf(),
+            custodian,
+        })
+    }
+
+    /// Writes a transfer to the file before booking it, nothing is booked if
+    /// it can't be written
+    fn apply(&mut self, transfer: Transfer) -> Result<(), CustodyError> {
+        self.custodian.check(&transfer)?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{}", JsonValue::from(&transfer)))
+            .map_err(|err| CustodyError::Io(err.to_string()))?;
+        self.custodian.apply(transfer)
+    }
+}
+
+impl Custodian for FileCustodian {
+    fn deposit(&mut self, account: AccountId, asset: &str, amount: &Decimal) -> Result<(), CustodyError> {
+        self.apply(Transfer::new(TransferKind::Deposit, account, asset, amount))
+    }
+
+    fn withdraw(&mut self, account: AccountId, asset: &str, amount: &Decimal) -> Result<(), CustodyError> {
+        self.apply(Transfer::new(TransferKind::Withdrawal, account, asset, amount))
+    }
+
+    fn reserves(&self, asset: &str) -> Decimal {
+        self.custodian.reserves(asset)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use std::fs;
+
+    use rust_decimal::Decimal;
+
+    use crate::custody::{Custodian, CustodyError, FileCustodian, InMemoryCustodian};
+
+    #[test]
+    fn test_in_memory_custodian() {
+        let mut custodian = InMemoryCustodian::new();
+        custodian.deposit(1, "BTC", &Decimal::from(5)).unwrap();
+        custodian.withdraw(1, "BTC", &Decimal::from(2)).unwrap();
+
+        assert_eq!(
+            custodian.withdraw(1, "BTC", &Decimal::from(4)),
+            Err(CustodyError::InsufficientReserves)
+        );
+        assert_eq!(custodian.reserves("BTC"), Decimal::from(3));
+        assert_eq!(custodian.transfers.len(), 2);
+    }
+
+    #[test]
+    fn test_file_custodian_restores_reserves() {
+        let path = std::env::temp_dir().join(format!("custody_test_{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut custodian = FileCustodian::open(&path).unwrap();
+        custodian.deposit(1, "USD", &Decimal::from(100)).unwrap();
+        custodian.withdraw(1, "USD", &Decimal::from(30)).unwrap();
+        assert_eq!(custodian.withdraw(1, "USD", &Decimal::from(80)).is_err(), true);
+
+        let custodian = FileCustodian::open(&path).unwrap();
+        assert_eq!(custodian.reserves("USD"), Decimal::from(70));
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
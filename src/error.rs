@@ -0,0 +1,139 @@
+use core::fmt;
+
+use json::{object, JsonValue};
+use rust_decimal::Decimal;
+
+use crate::custody::CustodyError;
+use crate::instrument::OrderRejection;
+use crate::session::SessionState;
+use crate::OrderId;
+
+/// Errors that can occur while processing an order.
+///
+/// Errors are propagated from the orderbook and the matchers up to the
+/// ``ExchangeCore``, which maps them to a json error response instead of
+/// bringing down the matching loop.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExchangeError {
+    /// Price is zero or negative
+    InvalidPrice(Decimal),
+
+    /// Amount is zero or negative
+    InvalidQuantity(Decimal),
+
+    /// An order with that id is already sitting in the book
+    DuplicateOrderId(OrderId),
+
+    /// No orderbook exists for the given symbol
+    UnknownSymbol,
+
+    /// Asset isn't traded by any instrument
+    UnknownAsset,
+
+    /// No open order with that id exists
+    UnknownOrder(OrderId),
+
+    /// Order belongs to another account than the one of the message
+    NotOrderOwner(OrderId),
+
+    /// Book is crossed or the order index points to a non existing page
+    BookInconsistent,
+
+    /// Message is missing a field required for its type
+    InvalidMessage,
+
+    /// Order violates the trading rules of its instrument
+    Rejected(OrderRejection),
+
+    /// Session of an instrument can't move between the given states
+    InvalidSessionTransition(SessionState, SessionState),
+
+    /// Custodian didn't accept a deposit or withdrawal
+    Custody(CustodyError),
+
+    /// Message couldn't be written to the write-ahead log and wasn't processed
+    LogUnavailable,
+}
+
+impl fmt::Display for ExchangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExchangeError::InvalidPrice(_) => write!(f, "invalid_price"),
+            ExchangeError::InvalidQuantity(_) => write!(f, "invalid_quantity"),
+            ExchangeError::DuplicateOrderId(_) => write!(f, "duplicate_order_id"),
+            ExchangeError::UnknownSymbol => write!(f, "unknown_symbol"),
+            ExchangeError::UnknownAsset => write!(f, "unknown_asset"),
+            ExchangeError::UnknownOrder(_) => write!(f, "unknown_order"),
+            ExchangeError::NotOrderOwner(_) => write!(f, "not_order_owner"),
+            ExchangeError::BookInconsistent => write!(f, "book_inconsistent"),
+            ExchangeError::InvalidMessage => write!(f, "invalid_message"),
+            ExchangeError::Rejected(rejection) => write!(f, "{}", rejection),
+            ExchangeError::InvalidSessionTransition(_, _) => write!(f, "invalid_session_transition"),
+            ExchangeError::Custody(err) => write!(f, "{}", err),
+            ExchangeError::LogUnavailable => write!(f, "log_unavailable"),
+        }
+    }
+}
+
+impl From<OrderRejection> for ExchangeError {
+    fn from(rejection: OrderRejection) -> Self {
+        ExchangeError::Rejected(rejection)
+    }
+}
+
+impl From<CustodyError> for ExchangeError {
+    fn from(err: CustodyError) -> Self {
+        ExchangeError::Custody(err)
+    }
+}
+
+impl From<ExchangeError> for JsonValue {
+    fn from(err: ExchangeError) -> Self {
+        let error = err.to_string();
+        match err {
+            ExchangeError::Rejected(rejection) => JsonValue::from(rejection),
+            ExchangeError::InvalidPrice(price) => {
+                object! {
+                    "status" => "error",
+                    "error" => error,
+                    "price" => price.to_string()
+                }
+            }
+            ExchangeError::InvalidQuantity(amount) => {
+                object! {
+                    "status" => "error",
+                    "error" => error,
+                    "amount" => amount.to_string()
+                }
+            }
+            ExchangeError::DuplicateOrderId(order_id)
+            | ExchangeError::UnknownOrder(order_id)
+            | ExchangeError::NotOrderOwner(order_id) => {
+                object! {
+                    "status" => "error",
+                    "error" => error,
+                    "order_id" => order_id
+                }
+            }
+            ExchangeError::InvalidSessionTransition(from, to) => {
+                object! {
+                    "status" => "error",
+                    "error" => error,
+                    "from" => from.to_string(),
+                    "to" => to.to_string()
+                }
+            }
+            ExchangeError::UnknownSymbol
+            | ExchangeError::UnknownAsset
+            | ExchangeError::BookInconsistent
+            | ExchangeError::InvalidMessage
+            | ExchangeError::Custody(_)
+            | ExchangeError::LogUnavailable => {
+                object! {
+                    "status" => "error",
+                    "error" => error
+                }
+            }
+        }
+    }
+}
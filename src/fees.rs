@@ -0,0 +1,195 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+
+use rust_decimal::prelude::Zero;
+use rust_decimal::Decimal;
+
+use crate::snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
+use crate::{AccountId, Timestamp};
+
+/// Window of the trailing volume fee tiers are based on, 30 days in nanoseconds
+pub const VOLUME_WINDOW: Timestamp = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Fee rates that apply from a certain trailing volume on. Rates are fractions
+/// of the received amount, negative maker rates are rebates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeeTier {
+    /// Trailing volume in quote asset an account needs to reach the tier
+    pub min_volume: Decimal,
+    pub maker_rate: Decimal,
+    pub taker_rate: Decimal,
+}
+
+/// Maker and taker fees of an instrument, tiered by trailing volume
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FeeSchedule {
+    /// Tiers in ascending order of their minimum volume, no fees if empty
+    pub tiers: Vec<FeeTier>,
+}
+
+/// Trailing trade volume of each account per quote asset
+#[derive(Default)]
+pub struct VolumeTracker {
+    /// Volume of each trade within the window together with its timestamp, oldest first
+    trades: HashMap<(AccountId, String), VecDeque<(Timestamp, Decimal)>>,
+
+    /// Sum of the volumes in ``trades``
+    totals: HashMap<(AccountId, String), Decimal>,
+}
+
+impl FeeSchedule {
+    /// Maker and taker rate of the highest tier ``volume`` reaches
+    pub fn rates(&self, volume: &Decimal) -> (Decimal, Decimal) {
+        self.tiers
+            .iter()
+            .rev()
+            .find(|tier| tier.min_volume <= *volume)
+            .map(|tier| (tier.maker_rate, tier.taker_rate))
+            .unwrap_or_default()
+    }
+
+    /// Checks that tiers are ordered by volume and that maker rebates are within
+    /// the taker rate. Rebates are paid out of the taker fee of the same trade
+    /// and capped at it, maker and taker may sit in different tiers, so the
+    /// highest rebate of any tier can't exceed the lowest taker rate of any tier
+    /// for rebates to be paid in full.
+    pub fn is_valid(&self) -> bool {
+        let ordered = self
+            .tiers
+            .windows(2)
+            .all(|tiers| tiers[0].min_volume < tiers[1].min_volume);
+        let non_negative = self
+            .tiers
+            .iter()
+            .all(|tier| tier.min_volume >= Decimal::zero() && tier.taker_rate >= Decimal::zero());
+        let max_rebate = self.tiers.iter().map(|tier| -tier.maker_rate).max();
+        let min_taker_rate = self.tiers.iter().map(|tier| tier.taker_rate).min();
+        ordered && non_negative && max_rebate <= min_taker_rate
+    }
+}
+
+impl VolumeTracker {
+    pub fn new() -> VolumeTracker {
+        VolumeTracker::default()
+    }
+
+    /// Adds the volume of a trade at ``at`` to the trailing volume of ``account``
+    pub fn record(&mut self, account: AccountId, asset: &str, at: Timestamp, volume: &Decimal) {
+        let key = (account, asset.to_string());
+        self.trades.entry(key.clone()).or_default().push_back((at, *volume));
+        *self.totals.entry(key).or_default() += volume;
+    }
+
+    /// Trailing volume of ``account`` in ``asset`` over the window ending at ``now``.
+    ///
+    /// Trades that dropped out of the window are forgotten.
+    pub fn volume(&mut self, account: AccountId, asset: &str, now: Timestamp) -> Decimal {
+        let key = (account, asset.to_string());
+        let (trades, total) = match (self.trades.get_mut(&key), self.totals.get_mut(&key)) {
+            (Some(trades), Some(total)) => (trades, total),
+            _ => return Decimal::zero(),
+        };
+
+        let start = now.saturating_sub(VOLUME_WINDOW);
+        while let Some((at, volume)) = trades.front() {
+            if *at >= start {
+                break;
+            }
+            *total -= volume;
+            trades.pop_front();
+        }
+        *total
+    }
+}
+
+/// Trades are written per account and asset in key order, totals are summed
+/// up again while reading
+impl Snapshot for VolumeTracker {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        let mut keys: Vec<&(AccountId, String)> = self.trades.keys().collect();
+        keys.sort();
+        writer.put_len(keys.len());
+        for key in keys {
+            writer.put(key);
+            writer.put(&self.trades[key].iter().copied().collect::<Vec<_>>());
+        }
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        let mut tracker = VolumeTracker::new();
+        for _ in 0..reader.get_len()? {
+            let (account, asset): (AccountId, String) = reader.get()?;
+            for (at, volume) in reader.get::<Vec<(Timestamp, Decimal)>>()? {
+                tracker.record(account, &asset, at, &volume);
+            }
+        }
+        Ok(tracker)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::fees::{FeeSchedule, FeeTier, VolumeTracker, VOLUME_WINDOW};
+
+    fn tier(min_volume: i64, maker_rate: &str, taker_rate: &str) -> FeeTier {
+        FeeTier {
+            min_volume: Decimal::from(min_volume),
+            maker_rate: maker_rate.parse().unwrap(),
+            taker_rate: taker_rate.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_rates_by_tier() {
+        let schedule = FeeSchedule {
+            tiers: vec![tier(0, "0.001", "0.002"), tier(1000, "-0.0001", "0.001")],
+        };
+        assert_eq!(schedule.is_valid(), true);
+        assert_eq!(
+            schedule.rates(&Decimal::from(999)),
+            ("0.001".parse().unwrap(), "0.002".parse().unwrap())
+        );
+        assert_eq!(
+            schedule.rates(&Decimal::from(1000)),
+            ("-0.0001".parse().unwrap(), "0.001".parse().unwrap())
+        );
+        assert_eq!(FeeSchedule::default().rates(&Decimal::ONE), (Decimal::ZERO, Decimal::ZERO));
+
+        //Rebates can't exceed taker fees, not even those of another tier
+        assert_eq!(FeeSchedule { tiers: vec![tier(0, "-0.002", "0.001")] }.is_valid(), false);
+        assert_eq!(
+            FeeSchedule {
+                tiers: vec![tier(0, "0", "0.002"), tier(1000, "-0.0015", "0.0015"), tier(5000, "0", "0.001")]
+            }
+            .is_valid(),
+            false
+        );
+
+        //Tiers have to be ascending
+        assert_eq!(
+            FeeSchedule {
+                tiers: vec![tier(10, "0", "0.001"), tier(10, "0", "0.001")]
+            }
+            .is_valid(),
+            false
+        );
+    }
+
+    #[test]
+    fn test_trailing_volume() {
+        let mut volumes = VolumeTracker::new();
+        volumes.record(1, "USD", 100, &Decimal::from(50));
+        volumes.record(1, "USD", 200, &Decimal::from(30));
+        volumes.record(1, "EUR", 200, &Decimal::from(7));
+
+        assert_eq!(volumes.volume(1, "USD", 200), Decimal::from(80));
+        assert_eq!(volumes.volume(2, "USD", 200), Decimal::ZERO);
+
+        //First trade drops out of the window
+        assert_eq!(volumes.volume(1, "USD", 101 + VOLUME_WINDOW), Decimal::from(30));
+        assert_eq!(volumes.volume(1, "EUR", 201 + VOLUME_WINDOW), Decimal::ZERO);
+    }
+}
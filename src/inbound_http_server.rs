@@ -4,30 +4,37 @@ use std::io::{Read, Write};
 use std::net::{TcpListener, TcpStream};
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
 use std::thread;
 
 use json::object;
 use log::info;
 
 use crate::inbound_server::{AsyncMessage, InboundMessage, InboundServer};
+use crate::instrument::InstrumentRegistry;
 
 const LOCAL_ADDR: &str = "127.0.0.1:80";
 const REQ_BUFFER_SIZE: usize = 1024;
 
 pub struct InboundHttpServer {
     tx: Sender<AsyncMessage<InboundMessage>>,
+    instruments: Arc<InstrumentRegistry>,
 }
 
 /// Handler for incoming data on a TCP connection.
 /// 
 /// Reads the message from the raw ``TCPStream`` and passes the parsed message
 /// into the channel specified by ``tx``.
-fn handle_connection(mut stream: TcpStream, tx: Sender<AsyncMessage<InboundMessage>>) {
+fn handle_connection(
+    mut stream: TcpStream,
+    tx: Sender<AsyncMessage<InboundMessage>>,
+    instruments: &InstrumentRegistry,
+) {
     let mut buffer = [0; REQ_BUFFER_SIZE];
     let bytes_read = stream.read(&mut buffer).unwrap();
     let buffer = Vec::from(&buffer[0..bytes_read]);
 
-    let msg = parse_request(buffer);
+    let msg = parse_request(buffer, instruments);
     let response = match msg {
         Some(msg) => {
             let (msg, rx) = AsyncMessage::new(msg);
@@ -57,16 +64,16 @@ fn handle_connection(mut stream: TcpStream, tx: Sender<AsyncMessage<InboundMessa
         }
     };
 
-    stream.write(response.as_bytes()).unwrap();
+    stream.write_all(response.as_bytes()).unwrap();
     stream.flush().unwrap();
 }
 
 /// Parses binary read from a TCPStream into an InboundMessage or None, in case
 /// there were any parsing errors.
-fn parse_request(bytes: Vec<u8>) -> Option<InboundMessage> {
+fn parse_request(bytes: Vec<u8>, instruments: &InstrumentRegistry) -> Option<InboundMessage> {
     let request = String::from_utf8_lossy(&bytes[0..bytes.len()]).to_string();
 
-    if request.len() == 0 {
+    if request.is_empty() {
         return None;
     }
 
@@ -75,35 +82,33 @@ fn parse_request(bytes: Vec<u8>) -> Option<InboundMessage> {
     let map: Option<HashMap<String, String>> = request
         .split("\r\n")
         .next()? //GET line
-        .split("?")
-        .skip(1)
-        .next()?
-        .split(" ")
+        .split('?')
+        .nth(1)?
+        .split(' ')
         .next()? //Extract params
-        .split("&")
-        .into_iter()
+        .split('&')
         .map(|x| {
             //Split params
-            let mut split_iter = x.split("="); //Split each key, value
+            let mut split_iter = x.split('='); //Split each key, value
             let key = split_iter.next()?.to_string();
             let val = split_iter.next()?.to_string();
-            return Some((key, val));
+            Some((key, val))
         })
         .collect(); //Collect into hashmap
 
     info!("parsed request: {:?}", map);
 
-    InboundMessage::from_hashmap(&map?)
+    InboundMessage::from_hashmap(&map?, instruments)
 }
 
 impl InboundServer for InboundHttpServer {
     /// Creates new instance of ``InboundServer`` as well as a receiver for the 
     /// async channel into which incomming messages are getting pushed
-    fn new() -> (Receiver<AsyncMessage<InboundMessage>>, Self) {
+    fn new(instruments: Arc<InstrumentRegistry>) -> (Receiver<AsyncMessage<InboundMessage>>, Self) {
         info!("Initializing inbound http server...");
         let (tx, rx) = mpsc::channel::<AsyncMessage<InboundMessage>>();
 
-        (rx, InboundHttpServer { tx })
+        (rx, InboundHttpServer { tx, instruments })
     }
 
     /// Runs the server loop
@@ -111,6 +116,7 @@ impl InboundServer for InboundHttpServer {
         info!("Starting inbound http server...");
 
         let tx = self.tx.clone();
+        let instruments = self.instruments.clone();
 
         thread::spawn(move || {
             let listener = TcpListener::bind(LOCAL_ADDR).expect("Unable to bind to TcpListener!");
@@ -118,8 +124,9 @@ impl InboundServer for InboundHttpServer {
             for stream in listener.incoming() {
                 let stream = stream.unwrap();
                 let tx = tx.clone();
+                let instruments = instruments.clone();
                 thread::spawn(move || {
-                    handle_connection(stream, tx);
+                    handle_connection(stream, tx, &instruments);
                 });
             }
         });
@@ -127,17 +134,94 @@ impl InboundServer for InboundHttpServer {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
+    use rust_decimal::Decimal;
+
     use crate::inbound_http_server::parse_request;
+    use crate::inbound_server::MessageType;
+    use crate::instrument::{Instrument, InstrumentRegistry};
+    use crate::order_matcher::{SelfTradePrevention, TimeInForce};
+    use crate::session::SessionState;
+    use crate::symbol::{AskOrBid, Symbol};
+
+    fn instruments() -> InstrumentRegistry {
+        let mut instruments = InstrumentRegistry::new();
+        instruments
+            .insert(Instrument::new(Symbol::new("BTC"), "BTC", "USD", Decimal::ONE, Decimal::ONE))
+            .unwrap();
+        instruments
+    }
 
     #[test]
     fn test_parse_place_limit() {
         let request = format!(
-            "GET /api?action={}&symbol={}&side={}&price={}&amount={}\
+            "GET /api?action={}&symbol={}&side={}&price={}&amount={} \
             HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n)",
             "place_limit", "BTC", "bid", "1234", "231"
         )
         .into_bytes();
-        parse_request(request);
+        let msg = parse_request(request, &instruments()).unwrap();
+        assert_eq!(msg.message_type, MessageType::PlaceLimitOrder);
+        assert_eq!(msg.symbol, Some(Symbol::new("BTC")));
+        assert_eq!(msg.side, Some(AskOrBid::Bid));
+        assert_eq!(msg.limit_price, Some(Decimal::from(1234)));
+        assert_eq!(msg.amount, Some(Decimal::from(231)));
+        assert_eq!(msg.time_in_force, None);
+    }
+
+    #[test]
+    fn test_parse_time_in_force() {
+        let request = "GET /api?action=place_limit&symbol=btc&side=bid&price=1&amount=1&tif=ioc HTTP/1.1\r\n\r\n"
+            .to_string()
+            .into_bytes();
+        let msg = parse_request(request, &instruments()).unwrap();
+        assert_eq!(msg.time_in_force, Some(TimeInForce::ImmediateOrCancel));
+
+        //Unknown time in force invalidates the whole message
+        let request = "GET /api?action=place_limit&symbol=btc&side=bid&price=1&amount=1&tif=day HTTP/1.1\r\n\r\n"
+            .to_string()
+            .into_bytes();
+        assert_eq!(parse_request(request, &instruments()).is_none(), true);
+    }
+
+    #[test]
+    fn test_parse_session() {
+        let request = "GET /api?action=set_session&symbol=btc&state=pre_open&at=100 HTTP/1.1\r\n\r\n"
+            .to_string()
+            .into_bytes();
+        let msg = parse_request(request, &instruments()).unwrap();
+        assert_eq!(msg.message_type, MessageType::SetSession);
+        assert_eq!(msg.session_state, Some(SessionState::PreOpen));
+        assert_eq!(msg.at, Some(100));
+
+        let request = "GET /api?action=set_session&symbol=btc&state=open HTTP/1.1\r\n\r\n"
+            .to_string()
+            .into_bytes();
+        assert_eq!(parse_request(request, &instruments()).is_none(), true);
+    }
+
+    #[test]
+    fn test_parse_self_trade_prevention() {
+        let request = "GET /api?action=place_limit&symbol=btc&side=bid&price=1&amount=1&account=7&stp=cancel_both HTTP/1.1\r\n\r\n"
+            .to_string()
+            .into_bytes();
+        let msg = parse_request(request, &instruments()).unwrap();
+        assert_eq!(msg.account, Some(7));
+        assert_eq!(msg.stp, Some(SelfTradePrevention::CancelBoth));
+
+        let request = "GET /api?action=place_limit&symbol=btc&side=bid&price=1&amount=1&account=7&stp=cancel HTTP/1.1\r\n\r\n"
+            .to_string()
+            .into_bytes();
+        assert_eq!(parse_request(request, &instruments()).is_none(), true);
+    }
+
+    #[test]
+    fn test_parse_unknown_symbol() {
+        let request = "GET /api?action=place_limit&symbol=doge&side=bid&price=1&amount=1 HTTP/1.1\r\n\r\n"
+            .to_string()
+            .into_bytes();
+        let msg = parse_request(request, &instruments()).unwrap();
+        assert_eq!(msg.symbol, None);
     }
 }
@@ -4,11 +4,16 @@ use std::fmt::Formatter;
 
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
 
 use rust_decimal::prelude::FromStr;
 use rust_decimal::Decimal;
 
+use crate::instrument::InstrumentRegistry;
+use crate::order_matcher::{PostOnly, SelfTradePrevention, TimeInForce};
+use crate::session::SessionState;
 use crate::symbol::{AskOrBid, Symbol};
+use crate::{AccountId, Timestamp};
 
 /// Struct representing an async channel command of type T,
 /// as well as an async channel sender that can be used to reply to the command.
@@ -31,6 +36,13 @@ pub enum MessageType {
     PlaceLimitOrder = 1,
     CancelLimitOrder = 2,
     PlaceMarketOrder = 3,
+    AmendOrder = 4,
+    PlaceStopOrder = 5,
+    SetSession = 6,
+    OpenOrders = 7,
+    Deposit = 8,
+    Withdraw = 9,
+    CheckLedger = 10,
 }
 
 /// Struct for an inbound order message.
@@ -42,6 +54,35 @@ pub struct InboundMessage {
     pub limit_price: Option<Decimal>,
     pub amount: Option<Decimal>,
     pub order_id: Option<u64>,
+
+    /// Displayed amount of iceberg orders, the rest of the amount is hidden
+    pub peak: Option<Decimal>,
+
+    /// Price at which a stop order is triggered
+    pub stop_price: Option<Decimal>,
+
+    /// Time in force of limit orders, good till cancel if not given
+    pub time_in_force: Option<TimeInForce>,
+
+    /// Marks a limit order as post-only and defines how crossing orders are handled
+    pub post_only: Option<PostOnly>,
+
+    /// Session state an instrument is moved to by session messages
+    pub session_state: Option<SessionState>,
+
+    /// Nanoseconds since unix epoch at which a session transition is scheduled,
+    /// the transition is applied right away if not given
+    pub at: Option<Timestamp>,
+
+    /// Account placing, cancelling or amending the order, required for all of
+    /// them. Orders of the same account never trade against each other
+    pub account: Option<AccountId>,
+
+    /// How self-trades are prevented, cancel newest if not given
+    pub stp: Option<SelfTradePrevention>,
+
+    /// Asset moved by deposits and withdrawals
+    pub asset: Option<String>,
 }
 
 /// Trait representing a runnable inbound server.
@@ -50,7 +91,9 @@ pub struct InboundMessage {
 pub trait InboundServer {
     /// Creates new instance of ``InboundServer`` as well as a receiver for the 
     /// async channel into which incomming messages are getting pushed
-    fn new() -> (Receiver<AsyncMessage<InboundMessage>>, Self);
+    /// 
+    /// Symbols of incoming messages are resolved against ``instruments``.
+    fn new(instruments: Arc<InstrumentRegistry>) -> (Receiver<AsyncMessage<InboundMessage>>, Self);
 
     /// Runs the server loop
     fn run(self);
@@ -65,40 +108,63 @@ impl<T> AsyncMessage<T> {
 
 impl MessageType {
     /// Determins whether the message type holds an amount
-    fn has_amount(&self) -> bool {
-        match self {
-            MessageType::CancelLimitOrder => false,
-            _ => true,
-        }
+    pub fn has_amount(&self) -> bool {
+        !matches!(
+            self,
+            MessageType::CancelLimitOrder
+                | MessageType::SetSession
+                | MessageType::OpenOrders
+                | MessageType::CheckLedger
+        )
     }
 
     /// Determins whether the message type holds a concrete price (ex. limit) or
     /// no price data (ex. market)
     pub fn has_price(&self) -> bool {
-        match self {
-            MessageType::PlaceLimitOrder => true,
-            _ => false,
-        }
+        matches!(
+            self,
+            MessageType::PlaceLimitOrder | MessageType::AmendOrder | MessageType::PlaceStopOrder
+        )
     }
 
     /// Determins whether the message type holds a specific order id or not.
     /// 
     /// Cancel and lookup messages will hold an id while place orders don't 
     pub fn has_order_id(&self) -> bool {
+        matches!(self, MessageType::CancelLimitOrder | MessageType::AmendOrder)
+    }
+
+    /// Action name the message type is parsed from, see ``from_string``
+    pub fn action(&self) -> &'static str {
         match self {
-            MessageType::CancelLimitOrder => true,
-            _ => false,
+            MessageType::PlaceLimitOrder => "place_limit",
+            MessageType::CancelLimitOrder => "cancel_limit",
+            MessageType::PlaceMarketOrder => "place_market",
+            MessageType::AmendOrder => "amend",
+            MessageType::PlaceStopOrder => "place_stop",
+            MessageType::SetSession => "set_session",
+            MessageType::OpenOrders => "open_orders",
+            MessageType::Deposit => "deposit",
+            MessageType::Withdraw => "withdraw",
+            MessageType::CheckLedger => "check_ledger",
         }
     }
 
     /// Converts a string to a concrete MessageType.
     /// 
     /// In case the string couldn't be parsed, it'll reject the option.
-    pub fn from_string(value: &String) -> Option<MessageType> {
+    pub fn from_string(value: &str) -> Option<MessageType> {
         match value.to_lowercase().as_str() {
             "place_limit" => Some(MessageType::PlaceLimitOrder),
             "cancel_limit" => Some(MessageType::CancelLimitOrder),
             "place_market" => Some(MessageType::PlaceMarketOrder),
+            "amend" => Some(MessageType::AmendOrder),
+            "place_stop" => Some(MessageType::PlaceStopOrder),
+            "set_session" => Some(MessageType::SetSession),
+            "open_orders" => Some(MessageType::OpenOrders),
+            "deposit" => Some(MessageType::Deposit),
+            "withdraw" => Some(MessageType::Withdraw),
+            "check_ledger" => Some(MessageType::CheckLedger),
             _ => None,
         }
     }
@@ -122,22 +188,72 @@ from a HashMap
 fn opt_from_str_opt<T: FromStr>(value: Option<&String>) -> Option<T> {
     match value {
         None => None,
-        Some(value) => match T::from_str(value) {
-            Ok(decimal) => Some(decimal),
-            Err(_err) => None,
-        },
+        Some(value) => T::from_str(value).ok(),
     }
 }
 
 impl InboundMessage {
-    pub fn from_hashmap(map: &HashMap<String, String>) -> Option<InboundMessage> {
+    /// Parses a message from its key value pairs, symbols and assets that aren't
+    /// registered in ``instruments`` are dropped.
+    pub fn from_hashmap(
+        map: &HashMap<String, String>,
+        instruments: &InstrumentRegistry,
+    ) -> Option<InboundMessage> {
         Some(InboundMessage {
             message_type: MessageType::from_string(map.get("action")?)?,
-            symbol: opt_from_str_opt::<Symbol>(map.get("symbol")),
+            symbol: map.get("symbol").and_then(|name| instruments.resolve(name)),
             side: opt_from_str_opt::<AskOrBid>(map.get("side")),
             limit_price: opt_from_str_opt::<Decimal>(map.get("price")),
             amount: opt_from_str_opt::<Decimal>(map.get("amount")),
             order_id: opt_from_str_opt::<u64>(map.get("order_id")),
+            peak: opt_from_str_opt::<Decimal>(map.get("peak")),
+            stop_price: opt_from_str_opt::<Decimal>(map.get("stop_price")),
+            // Unknown time in force must not silently fall back to good till cancel
+            time_in_force: match map.get("tif") {
+                Some(tif) => Some(TimeInForce::from_str(tif).ok()?),
+                None => None,
+            },
+            post_only: match map.get("post_only") {
+                Some(post_only) => Some(PostOnly::from_str(post_only).ok()?),
+                None => None,
+            },
+            session_state: match map.get("state") {
+                Some(state) => Some(SessionState::from_str(state).ok()?),
+                None => None,
+            },
+            at: opt_from_str_opt::<Timestamp>(map.get("at")),
+            account: opt_from_str_opt::<AccountId>(map.get("account")),
+            stp: match map.get("stp") {
+                Some(stp) => Some(SelfTradePrevention::from_str(stp).ok()?),
+                None => None,
+            },
+            asset: map.get("asset").and_then(|name| instruments.resolve_asset(name)),
         })
     }
+
+    /// Converts the message back into the key value pairs it is parsed from by
+    /// ``from_hashmap``, fields that aren't set are left out
+    pub fn to_hashmap(&self) -> HashMap<String, String> {
+        let fields = [
+            ("action", Some(self.message_type.action().to_string())),
+            ("symbol", self.symbol.as_ref().map(|symbol| symbol.to_string())),
+            ("side", self.side.map(|side| side.to_string())),
+            ("price", self.limit_price.map(|price| price.to_string())),
+            ("amount", self.amount.map(|amount| amount.to_string())),
+            ("order_id", self.order_id.map(|order_id| order_id.to_string())),
+            ("peak", self.peak.map(|peak| peak.to_string())),
+            ("stop_price", self.stop_price.map(|price| price.to_string())),
+            ("tif", self.time_in_force.map(|tif| tif.to_string())),
+            ("post_only", self.post_only.map(|post_only| post_only.to_string())),
+            ("state", self.session_state.map(|state| state.to_string())),
+            ("at", self.at.map(|at| at.to_string())),
+            ("account", self.account.map(|account| account.to_string())),
+            ("stp", self.stp.map(|stp| stp.to_string())),
+            ("asset", self.asset.clone()),
+        ];
+        fields
+            .iter()
+            .filter_map(|(key, value)| Some((key.to_string(), value.clone()?)))
+            .collect()
+    }
 }
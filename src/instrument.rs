@@ -0,0 +1,809 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+use json::{object, JsonValue};
+use rust_decimal::Decimal;
+
+use crate::fees::{FeeSchedule, FeeTier};
+use crate::session::SessionState;
+use crate::symbol::{AskOrBid, Symbol};
+use crate::Timestamp;
+
+/// Duration of volatility interruptions in case the instrument file doesn't configure one
+const DEFAULT_VOLATILITY_PERIOD_SECS: u64 = 300;
+
+/// Trading status of an instrument as configured in the instrument file
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum InstrumentStatus {
+    Trading,
+    Halted,
+    Disabled,
+}
+
+/// Algorithm used to allocate fills between orders resting at the same price level
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchingAlgorithm {
+    /// Price-time priority
+    Fifo,
+
+    /// Pro-rata allocation by displayed size, shares below ``min_allocation`` are
+    /// dropped
+    ProRata { min_allocation: Decimal },
+
+    /// Price-time priority, the top order of a price level is allocated
+    /// ``priority_pct`` percent of every incoming order first
+    FifoTopOrder { priority_pct: Decimal },
+}
+
+/// Price protection of an instrument, bands are given in percent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PriceBands {
+    /// Maximum deviation of order prices from the reference price
+    pub static_pct: Option<Decimal>,
+
+    /// Maximum deviation of order prices and trades from the last trade price
+    /// during continuous trading
+    pub dynamic_pct: Option<Decimal>,
+
+    /// Reference price the session starts with, e.g. the previous close
+    pub reference_price: Option<Decimal>,
+
+    /// Session state the instrument is moved into once a trade would leave the
+    /// dynamic band, either auction or halted
+    pub volatility_state: SessionState,
+
+    /// Nanoseconds until continuous trading resumes after a volatility interruption
+    pub volatility_period: Timestamp,
+}
+
+/// Static reference data of a tradeable instrument
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instrument {
+    pub symbol: Symbol,
+    pub base_asset: String,
+    pub quote_asset: String,
+
+    /// Minimum price increment
+    pub tick_size: Decimal,
+
+    /// Minimum quantity increment
+    pub lot_size: Decimal,
+
+    /// Maximum number of decimal places allowed for prices
+    pub price_precision: u32,
+
+    /// Maximum number of decimal places allowed for quantities
+    pub qty_precision: u32,
+
+    /// Minimum value (price * quantity) of an order in quote asset
+    pub min_notional: Decimal,
+
+    pub status: InstrumentStatus,
+
+    /// How fills are allocated within a price level
+    pub matching: MatchingAlgorithm,
+
+    pub bands: PriceBands,
+
+    /// Maker and taker fees charged on every fill
+    pub fees: FeeSchedule,
+}
+
+/// Registry of all instruments known to the exchange.
+///
+/// Loaded once at startup, orderbooks are created for each registered instrument and
+/// symbols of inbound messages are resolved against it.
+#[derive(Debug, Clone, Default)]
+pub struct InstrumentRegistry {
+    instruments: HashMap<Symbol, Instrument>,
+}
+
+/// Reasons for rejecting an order before it reaches the book
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OrderRejection {
+    InstrumentNotTrading,
+    NonPositivePrice,
+    NonPositiveQuantity,
+    PricePrecisionExceeded,
+    QuantityPrecisionExceeded,
+    PriceNotOnTick,
+    QuantityNotOnLot,
+    BelowMinNotional,
+    /// Post-only order would have taken liquidity
+    WouldCrossSpread,
+    /// Stop price has already been reached by the last trade price
+    WouldTriggerImmediately,
+    /// Price deviates too far from the reference price
+    OutsideStaticBand,
+    /// Price deviates too far from the last trade price
+    OutsideDynamicBand,
+    /// Order type can't take part in a call auction
+    NotAllowedInAuction,
+    /// Session of the instrument is halted or closed
+    NotAllowedInSession,
+    /// Account can't cover the funds the order has to hold
+    InsufficientBalance,
+}
+
+/// Errors that can occur while loading the instrument configuration
+#[derive(Debug)]
+pub enum InstrumentConfigError {
+    Io(std::io::Error),
+    Parse(json::Error),
+    /// Symbol (if known), name of the missing or invalid field
+    InvalidField(Option<String>, String),
+    DuplicateSymbol(String),
+}
+
+impl FromStr for InstrumentStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "trading" => Ok(InstrumentStatus::Trading),
+            "halted" => Ok(InstrumentStatus::Halted),
+            "disabled" => Ok(InstrumentStatus::Disabled),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for InstrumentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InstrumentStatus::Trading => write!(f, "trading"),
+            InstrumentStatus::Halted => write!(f, "halted"),
+            InstrumentStatus::Disabled => write!(f, "disabled"),
+        }
+    }
+}
+
+impl fmt::Display for MatchingAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatchingAlgorithm::Fifo => write!(f, "fifo"),
+            MatchingAlgorithm::ProRata { .. } => write!(f, "pro_rata"),
+            MatchingAlgorithm::FifoTopOrder { .. } => write!(f, "fifo_top_order"),
+        }
+    }
+}
+
+impl fmt::Display for OrderRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderRejection::InstrumentNotTrading => write!(f, "instrument_not_trading"),
+            OrderRejection::NonPositivePrice => write!(f, "non_positive_price"),
+            OrderRejection::NonPositiveQuantity => write!(f, "non_positive_quantity"),
+            OrderRejection::PricePrecisionExceeded => write!(f, "price_precision_exceeded"),
+            OrderRejection::QuantityPrecisionExceeded => write!(f, "quantity_precision_exceeded"),
+            OrderRejection::PriceNotOnTick => write!(f, "price_not_on_tick"),
+            OrderRejection::QuantityNotOnLot => write!(f, "quantity_not_on_lot"),
+            OrderRejection::BelowMinNotional => write!(f, "below_min_notional"),
+            OrderRejection::WouldCrossSpread => write!(f, "would_cross_spread"),
+            OrderRejection::WouldTriggerImmediately => write!(f, "would_trigger_immediately"),
+            OrderRejection::OutsideStaticBand => write!(f, "outside_static_band"),
+            OrderRejection::OutsideDynamicBand => write!(f, "outside_dynamic_band"),
+            OrderRejection::NotAllowedInAuction => write!(f, "not_allowed_in_auction"),
+            OrderRejection::NotAllowedInSession => write!(f, "not_allowed_in_session"),
+            OrderRejection::InsufficientBalance => write!(f, "insufficient_balance"),
+        }
+    }
+}
+
+impl From<OrderRejection> for JsonValue {
+    fn from(rejection: OrderRejection) -> Self {
+        object! {
+            "status" => "rejected",
+            "reason" => rejection.to_string()
+        }
+    }
+}
+
+impl fmt::Display for InstrumentConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InstrumentConfigError::Io(err) => write!(f, "unable to read instrument file: {}", err),
+            InstrumentConfigError::Parse(err) => write!(f, "unable to parse instrument file: {}", err),
+            InstrumentConfigError::InvalidField(Some(symbol), field) => {
+                write!(f, "instrument {}: missing or invalid field '{}'", symbol, field)
+            }
+            InstrumentConfigError::InvalidField(None, field) => {
+                write!(f, "missing or invalid field '{}'", field)
+            }
+            InstrumentConfigError::DuplicateSymbol(symbol) => {
+                write!(f, "instrument {} is configured more than once", symbol)
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for InstrumentConfigError {
+    fn from(err: std::io::Error) -> Self {
+        InstrumentConfigError::Io(err)
+    }
+}
+
+impl From<json::Error> for InstrumentConfigError {
+    fn from(err: json::Error) -> Self {
+        InstrumentConfigError::Parse(err)
+    }
+}
+
+/// Reads a decimal from a json value. Decimals should be given as strings to avoid
+/// floating point rounding, plain json numbers are accepted as well.
+fn decimal_field(
+    value: &JsonValue,
+    symbol: &str,
+    field: &str,
+) -> Result<Decimal, InstrumentConfigError> {
+    let invalid = || InstrumentConfigError::InvalidField(Some(symbol.to_string()), field.to_string());
+    if !(value[field].is_string() || value[field].is_number()) {
+        return Err(invalid());
+    }
+    Decimal::from_str(&value[field].to_string()).map_err(|_| invalid())
+}
+
+/// Reads an optional percentage from a json value, has to be positive
+fn pct_field(value: &JsonValue, symbol: &str, field: &str) -> Result<Option<Decimal>, InstrumentConfigError> {
+    if value[field].is_null() {
+        return Ok(None);
+    }
+    let pct = decimal_field(value, symbol, field)?;
+    if pct <= Decimal::ZERO {
+        return Err(InstrumentConfigError::InvalidField(Some(symbol.to_string()), field.to_string()));
+    }
+    Ok(Some(pct))
+}
+
+/// Reads a string from a json value
+fn string_field(value: &JsonValue, symbol: &str, field: &str) -> Result<String, InstrumentConfigError> {
+    value[field]
+        .as_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| InstrumentConfigError::InvalidField(Some(symbol.to_string()), field.to_string()))
+}
+
+impl Instrument {
+    /// Creates a new trading instrument with default precision derived from tick
+    /// and lot size and no minimum notional
+    pub fn new(symbol: Symbol, base_asset: &str, quote_asset: &str, tick_size: Decimal, lot_size: Decimal) -> Instrument {
+        Instrument {
+            symbol,
+            base_asset: base_asset.to_string(),
+            quote_asset: quote_asset.to_string(),
+            tick_size,
+            lot_size,
+            price_precision: tick_size.normalize().scale(),
+            qty_precision: lot_size.normalize().scale(),
+            min_notional: Decimal::ZERO,
+            status: InstrumentStatus::Trading,
+            matching: MatchingAlgorithm::Fifo,
+            bands: PriceBands::default(),
+            fees: FeeSchedule::default(),
+        }
+    }
+
+    /// Parses a single instrument from its json config entry.
+    ///
+    /// ``price_precision`` and ``qty_precision`` default to the number of decimal
+    /// places of tick and lot size, ``min_notional`` defaults to zero, ``status``
+    /// to trading and ``matching`` to fifo. Price bands are disabled unless their
+    /// percentage is given, no fees are charged unless ``fee_tiers`` are given.
+    pub fn from_json(value: &JsonValue) -> Result<Instrument, InstrumentConfigError> {
+        let symbol = value["symbol"]
+            .as_str()
+            .ok_or_else(|| InstrumentConfigError::InvalidField(None, "symbol".to_string()))?;
+
+        let tick_size = decimal_field(value, symbol, "tick_size")?;
+        let lot_size = decimal_field(value, symbol, "lot_size")?;
+        if tick_size <= Decimal::ZERO {
+            return Err(InstrumentConfigError::InvalidField(Some(symbol.to_string()), "tick_size".to_string()));
+        }
+        if lot_size <= Decimal::ZERO {
+            return Err(InstrumentConfigError::InvalidField(Some(symbol.to_string()), "lot_size".to_string()));
+        }
+
+        let mut instrument = Instrument::new(
+            Symbol::new(symbol),
+            &string_field(value, symbol, "base_asset")?,
+            &string_field(value, symbol, "quote_asset")?,
+            tick_size,
+            lot_size,
+        );
+
+        if !value["price_precision"].is_null() {
+            instrument.price_precision = value["price_precision"].as_u32().ok_or_else(|| {
+                InstrumentConfigError::InvalidField(Some(symbol.to_string()), "price_precision".to_string())
+            })?;
+        }
+        if !value["qty_precision"].is_null() {
+            instrument.qty_precision = value["qty_precision"].as_u32().ok_or_else(|| {
+                InstrumentConfigError::InvalidField(Some(symbol.to_string()), "qty_precision".to_string())
+            })?;
+        }
+        if !value["min_notional"].is_null() {
+            instrument.min_notional = decimal_field(value, symbol, "min_notional")?;
+        }
+        if !value["status"].is_null() {
+            instrument.status = value["status"]
+                .as_str()
+                .and_then(|status| InstrumentStatus::from_str(status).ok())
+                .ok_or_else(|| InstrumentConfigError::InvalidField(Some(symbol.to_string()), "status".to_string()))?;
+        }
+        if !value["matching"].is_null() {
+            instrument.matching = match value["matching"].as_str() {
+                Some("fifo") => MatchingAlgorithm::Fifo,
+                Some("pro_rata") => MatchingAlgorithm::ProRata {
+                    min_allocation: match value["min_allocation"].is_null() {
+                        true => Decimal::ZERO,
+                        false => decimal_field(value, symbol, "min_allocation")?,
+                    },
+                },
+                Some("fifo_top_order") => {
+                    let priority_pct = decimal_field(value, symbol, "top_order_pct")?;
+                    if priority_pct <= Decimal::ZERO || priority_pct > Decimal::ONE_HUNDRED {
+                        return Err(InstrumentConfigError::InvalidField(
+                            Some(symbol.to_string()),
+                            "top_order_pct".to_string(),
+                        ));
+                    }
+                    MatchingAlgorithm::FifoTopOrder { priority_pct }
+                }
+                _ => {
+                    return Err(InstrumentConfigError::InvalidField(
+                        Some(symbol.to_string()),
+                        "matching".to_string(),
+                    ))
+                }
+            };
+        }
+
+        instrument.bands.static_pct = pct_field(value, symbol, "static_band_pct")?;
+        instrument.bands.dynamic_pct = pct_field(value, symbol, "dynamic_band_pct")?;
+        if !value["reference_price"].is_null() {
+            instrument.bands.reference_price = Some(decimal_field(value, symbol, "reference_price")?);
+        }
+        if !value["volatility_state"].is_null() {
+            instrument.bands.volatility_state = match value["volatility_state"].as_str() {
+                Some("auction") => SessionState::Auction,
+                Some("halted") => SessionState::Halted,
+                _ => {
+                    return Err(InstrumentConfigError::InvalidField(
+                        Some(symbol.to_string()),
+                        "volatility_state".to_string(),
+                    ))
+                }
+            };
+        }
+        if !value["volatility_period_secs"].is_null() {
+            let secs = value["volatility_period_secs"].as_u64().ok_or_else(|| {
+                InstrumentConfigError::InvalidField(Some(symbol.to_string()), "volatility_period_secs".to_string())
+            })?;
+            instrument.bands.volatility_period = secs * 1_000_000_000;
+        }
+        if !value["fee_tiers"].is_null() {
+            let invalid = || InstrumentConfigError::InvalidField(Some(symbol.to_string()), "fee_tiers".to_string());
+            if !value["fee_tiers"].is_array() {
+                return Err(invalid());
+            }
+            for tier in value["fee_tiers"].members() {
+                instrument.fees.tiers.push(FeeTier {
+                    min_volume: decimal_field(tier, symbol, "min_volume")?,
+                    maker_rate: decimal_field(tier, symbol, "maker_rate")?,
+                    taker_rate: decimal_field(tier, symbol, "taker_rate")?,
+                });
+            }
+            if !instrument.fees.is_valid() {
+                return Err(invalid());
+            }
+        }
+
+        Ok(instrument)
+    }
+
+    /// Checks a price against the static band around ``reference_price``, passes
+    /// if no static band or reference price is set
+    pub fn check_static_band(&self, price: &Decimal, reference_price: Option<&Decimal>) -> Result<(), OrderRejection> {
+        match (self.bands.static_pct, reference_price) {
+            (Some(pct), Some(reference)) if !PriceBands::within(price, reference, &pct) => {
+                Err(OrderRejection::OutsideStaticBand)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks a price against the dynamic band around ``last_trade_price``, passes
+    /// if no dynamic band is set or nothing traded yet
+    pub fn check_dynamic_band(&self, price: &Decimal, last_trade_price: Option<&Decimal>) -> Result<(), OrderRejection> {
+        match (self.bands.dynamic_pct, last_trade_price) {
+            (Some(pct), Some(last)) if !PriceBands::within(price, last, &pct) => {
+                Err(OrderRejection::OutsideDynamicBand)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Worst price an order of ``side`` may trade at without leaving the dynamic
+    /// band around ``last_trade_price``
+    pub fn dynamic_band_limit(&self, side: AskOrBid, last_trade_price: Option<&Decimal>) -> Option<Decimal> {
+        let (pct, last) = (self.bands.dynamic_pct?, last_trade_price?);
+        let deviation = last * pct / Decimal::ONE_HUNDRED;
+        match side {
+            AskOrBid::Bid => Some(last + deviation),
+            AskOrBid::Ask => Some(last - deviation),
+        }
+    }
+
+    /// Validates an order against the instrument's trading rules.
+    ///
+    /// ``price`` is None for market orders, in which case only the quantity can be
+    /// checked.
+    pub fn validate_order(&self, price: Option<&Decimal>, amount: &Decimal) -> Result<(), OrderRejection> {
+        if self.status != InstrumentStatus::Trading {
+            return Err(OrderRejection::InstrumentNotTrading);
+        }
+
+        if *amount <= Decimal::ZERO {
+            return Err(OrderRejection::NonPositiveQuantity);
+        }
+        if amount.normalize().scale() > self.qty_precision {
+            return Err(OrderRejection::QuantityPrecisionExceeded);
+        }
+        if !(amount % self.lot_size).is_zero() {
+            return Err(OrderRejection::QuantityNotOnLot);
+        }
+
+        if let Some(price) = price {
+            if *price <= Decimal::ZERO {
+                return Err(OrderRejection::NonPositivePrice);
+            }
+            if price.normalize().scale() > self.price_precision {
+                return Err(OrderRejection::PricePrecisionExceeded);
+            }
+            if !(price % self.tick_size).is_zero() {
+                return Err(OrderRejection::PriceNotOnTick);
+            }
+            if price * amount < self.min_notional {
+                return Err(OrderRejection::BelowMinNotional);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for PriceBands {
+    fn default() -> Self {
+        PriceBands {
+            static_pct: None,
+            dynamic_pct: None,
+            reference_price: None,
+            volatility_state: SessionState::Auction,
+            volatility_period: DEFAULT_VOLATILITY_PERIOD_SECS * 1_000_000_000,
+        }
+    }
+}
+
+impl PriceBands {
+    /// Determins whether ``price`` deviates at most ``pct`` percent from ``reference``
+    pub fn within(price: &Decimal, reference: &Decimal, pct: &Decimal) -> bool {
+        (price - reference).abs() * Decimal::ONE_HUNDRED <= reference * pct
+    }
+}
+
+impl InstrumentRegistry {
+    pub fn new() -> InstrumentRegistry {
+        InstrumentRegistry {
+            instruments: HashMap::new(),
+        }
+    }
+
+    /// Loads the registry from a json file of the form ``{"instruments": [...]}``
+    pub fn from_file(path: &str) -> Result<InstrumentRegistry, InstrumentConfigError> {
+        InstrumentRegistry::from_json_str(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_json_str(config: &str) -> Result<InstrumentRegistry, InstrumentConfigError> {
+        let config = json::parse(config)?;
+        if !config["instruments"].is_array() {
+            return Err(InstrumentConfigError::InvalidField(None, "instruments".to_string()));
+        }
+
+        let mut registry = InstrumentRegistry::new();
+        for entry in config["instruments"].members() {
+            registry.insert(Instrument::from_json(entry)?)?;
+        }
+        Ok(registry)
+    }
+
+    /// Adds an instrument to the registry, symbols have to be unique
+    pub fn insert(&mut self, instrument: Instrument) -> Result<(), InstrumentConfigError> {
+        if self.instruments.contains_key(&instrument.symbol) {
+            return Err(InstrumentConfigError::DuplicateSymbol(instrument.symbol.to_string()));
+        }
+        self.instruments.insert(instrument.symbol.clone(), instrument);
+        Ok(())
+    }
+
+    /// Resolves a symbol name (case insensitive) to a registered symbol
+    pub fn resolve(&self, name: &str) -> Option<Symbol> {
+        let symbol = Symbol::new(name);
+        match self.instruments.contains_key(&symbol) {
+            true => Some(symbol),
+            false => None,
+        }
+    }
+
+    /// Resolves an asset name (case insensitive) to an asset traded by any of
+    /// the registered instruments
+    pub fn resolve_asset(&self, name: &str) -> Option<String> {
+        self.instruments
+            .values()
+            .flat_map(|instrument| [&instrument.base_asset, &instrument.quote_asset])
+            .find(|asset| asset.eq_ignore_ascii_case(name))
+            .cloned()
+    }
+
+    pub fn get(&self, symbol: &Symbol) -> Option<&Instrument> {
+        self.instruments.get(symbol)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Instrument> {
+        self.instruments.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.instruments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instruments.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rust_decimal::Decimal;
+
+    use crate::instrument::{
+        Instrument, InstrumentConfigError, InstrumentRegistry, InstrumentStatus, MatchingAlgorithm,
+        OrderRejection, PriceBands,
+    };
+    use crate::session::SessionState;
+    use crate::symbol::{AskOrBid, Symbol};
+
+    const CONFIG: &str = r#"{
+        "instruments": [
+            {
+                "symbol": "BTC",
+                "base_asset": "BTC",
+                "quote_asset": "USD",
+                "tick_size": "0.5",
+                "lot_size": "0.001",
+                "min_notional": "10",
+                "status": "trading"
+            },
+            {
+                "symbol": "eth",
+                "base_asset": "ETH",
+                "quote_asset": "USD",
+                "tick_size": 1,
+                "lot_size": "0.01",
+                "price_precision": 4,
+                "status": "halted",
+                "matching": "pro_rata",
+                "min_allocation": "0.05",
+                "static_band_pct": "10",
+                "dynamic_band_pct": 2,
+                "reference_price": "2000",
+                "volatility_state": "halted",
+                "volatility_period_secs": 60
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn test_load_registry() {
+        let registry = InstrumentRegistry::from_json_str(CONFIG).unwrap();
+        assert_eq!(registry.len(), 2);
+
+        let btc = registry.get(&Symbol::new("BTC")).unwrap();
+        assert_eq!(btc.base_asset, "BTC");
+        assert_eq!(btc.quote_asset, "USD");
+        assert_eq!(btc.tick_size, Decimal::from_str("0.5").unwrap());
+        assert_eq!(btc.lot_size, Decimal::from_str("0.001").unwrap());
+        assert_eq!(btc.price_precision, 1);
+        assert_eq!(btc.qty_precision, 3);
+        assert_eq!(btc.min_notional, Decimal::from(10));
+        assert_eq!(btc.status, InstrumentStatus::Trading);
+        assert_eq!(btc.matching, MatchingAlgorithm::Fifo);
+        assert_eq!(btc.bands, PriceBands::default());
+
+        let eth = registry.get(&Symbol::new("ETH")).unwrap();
+        assert_eq!(eth.tick_size, Decimal::ONE);
+        assert_eq!(eth.price_precision, 4);
+        assert_eq!(eth.min_notional, Decimal::ZERO);
+        assert_eq!(eth.status, InstrumentStatus::Halted);
+        assert_eq!(
+            eth.matching,
+            MatchingAlgorithm::ProRata {
+                min_allocation: Decimal::from_str("0.05").unwrap()
+            }
+        );
+        assert_eq!(
+            eth.bands,
+            PriceBands {
+                static_pct: Some(Decimal::from(10)),
+                dynamic_pct: Some(Decimal::from(2)),
+                reference_price: Some(Decimal::from(2000)),
+                volatility_state: SessionState::Halted,
+                volatility_period: 60_000_000_000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_symbol() {
+        let registry = InstrumentRegistry::from_json_str(CONFIG).unwrap();
+
+        assert_eq!(registry.resolve("btc"), Some(Symbol::new("BTC")));
+        assert_eq!(registry.resolve("Eth"), Some(Symbol::new("ETH")));
+        assert_eq!(registry.resolve("doge"), None);
+
+        assert_eq!(registry.resolve_asset("usd"), Some("USD".to_string()));
+        assert_eq!(registry.resolve_asset("doge"), None);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let result = InstrumentRegistry::from_json_str(
+            r#"{"instruments": [{"symbol": "BTC", "base_asset": "BTC", "quote_asset": "USD", "tick_size": "0", "lot_size": "1"}]}"#,
+        );
+        assert!(matches!(result, Err(InstrumentConfigError::InvalidField(_, field)) if field == "tick_size"));
+
+        let result = InstrumentRegistry::from_json_str(
+            r#"{"instruments": [{"symbol": "BTC", "quote_asset": "USD", "tick_size": "1", "lot_size": "1"}]}"#,
+        );
+        assert!(matches!(result, Err(InstrumentConfigError::InvalidField(_, field)) if field == "base_asset"));
+
+        let entry = r#"{"symbol": "BTC", "base_asset": "BTC", "quote_asset": "USD", "tick_size": "1", "lot_size": "1"}"#;
+        let result = InstrumentRegistry::from_json_str(&format!(r#"{{"instruments": [{}, {}]}}"#, entry, entry));
+        assert!(matches!(result, Err(InstrumentConfigError::DuplicateSymbol(_))));
+
+        let result = InstrumentRegistry::from_json_str(
+            r#"{"instruments": [{"symbol": "BTC", "base_asset": "BTC", "quote_asset": "USD", "tick_size": "1", "lot_size": "1", "matching": "lifo"}]}"#,
+        );
+        assert!(matches!(result, Err(InstrumentConfigError::InvalidField(_, field)) if field == "matching"));
+
+        let result = InstrumentRegistry::from_json_str(
+            r#"{"instruments": [{"symbol": "BTC", "base_asset": "BTC", "quote_asset": "USD", "tick_size": "1", "lot_size": "1", "matching": "fifo_top_order", "top_order_pct": "120"}]}"#,
+        );
+        assert!(matches!(result, Err(InstrumentConfigError::InvalidField(_, field)) if field == "top_order_pct"));
+
+        let result = InstrumentRegistry::from_json_str(
+            r#"{"instruments": [{"symbol": "BTC", "base_asset": "BTC", "quote_asset": "USD", "tick_size": "1", "lot_size": "1", "dynamic_band_pct": "-1"}]}"#,
+        );
+        assert!(matches!(result, Err(InstrumentConfigError::InvalidField(_, field)) if field == "dynamic_band_pct"));
+
+        let result = InstrumentRegistry::from_json_str(
+            r#"{"instruments": [{"symbol": "BTC", "base_asset": "BTC", "quote_asset": "USD", "tick_size": "1", "lot_size": "1", "volatility_state": "closed"}]}"#,
+        );
+        assert!(matches!(result, Err(InstrumentConfigError::InvalidField(_, field)) if field == "volatility_state"));
+
+        let result = InstrumentRegistry::from_json_str(
+            r#"{"instruments": [{"symbol": "BTC", "base_asset": "BTC", "quote_asset": "USD", "tick_size": "1", "lot_size": "1", "matching": "fifo_top_order", "top_order_pct": "25"}]}"#,
+        );
+        assert_eq!(
+            result.unwrap().get(&Symbol::new("BTC")).unwrap().matching,
+            MatchingAlgorithm::FifoTopOrder {
+                priority_pct: Decimal::from(25)
+            }
+        );
+
+        let result = InstrumentRegistry::from_json_str(
+            r#"{"instruments": [{"symbol": "BTC", "base_asset": "BTC", "quote_asset": "USD", "tick_size": "1", "lot_size": "1",
+                "fee_tiers": [{"min_volume": "100", "maker_rate": "0", "taker_rate": "0.001"}, {"min_volume": "0", "maker_rate": "0", "taker_rate": "0.002"}]}]}"#,
+        );
+        assert!(matches!(result, Err(InstrumentConfigError::InvalidField(_, field)) if field == "fee_tiers"));
+
+        let result = InstrumentRegistry::from_json_str(
+            r#"{"instruments": [{"symbol": "BTC", "base_asset": "BTC", "quote_asset": "USD", "tick_size": "1", "lot_size": "1",
+                "fee_tiers": [{"min_volume": "0", "maker_rate": "-0.002", "taker_rate": "0.001"}]}]}"#,
+        );
+        assert!(matches!(result, Err(InstrumentConfigError::InvalidField(_, field)) if field == "fee_tiers"));
+
+        assert!(matches!(
+            InstrumentRegistry::from_json_str("{}"),
+            Err(InstrumentConfigError::InvalidField(None, _))
+        ));
+    }
+
+    #[test]
+    fn test_validate_order() {
+        let registry = InstrumentRegistry::from_json_str(CONFIG).unwrap();
+        let btc = registry.get(&Symbol::new("BTC")).unwrap();
+        let dec = |value: &str| Decimal::from_str(value).unwrap();
+
+        assert_eq!(btc.validate_order(Some(&dec("100.5")), &dec("0.2")), Ok(()));
+        assert_eq!(btc.validate_order(None, &dec("0.001")), Ok(()));
+
+        assert_eq!(
+            btc.validate_order(Some(&dec("0")), &dec("1")),
+            Err(OrderRejection::NonPositivePrice)
+        );
+        assert_eq!(
+            btc.validate_order(Some(&dec("100")), &dec("-1")),
+            Err(OrderRejection::NonPositiveQuantity)
+        );
+        assert_eq!(
+            btc.validate_order(Some(&dec("100.25")), &dec("1")),
+            Err(OrderRejection::PricePrecisionExceeded)
+        );
+        assert_eq!(
+            btc.validate_order(Some(&dec("100")), &dec("0.0001")),
+            Err(OrderRejection::QuantityPrecisionExceeded)
+        );
+        assert_eq!(
+            btc.validate_order(Some(&dec("100.3")), &dec("1")),
+            Err(OrderRejection::PriceNotOnTick)
+        );
+        assert_eq!(
+            btc.validate_order(Some(&dec("100")), &dec("0.05")),
+            Err(OrderRejection::BelowMinNotional)
+        );
+        assert_eq!(
+            btc.validate_order(Some(&dec("9.5")), &dec("1")),
+            Err(OrderRejection::BelowMinNotional)
+        );
+
+        //Trailing zeros don't count towards precision
+        assert_eq!(btc.validate_order(Some(&dec("100.50000")), &dec("1.000")), Ok(()));
+
+        let mut lot = Instrument::new(Symbol::new("LOT"), "LOT", "USD", dec("1"), dec("5"));
+        assert_eq!(lot.validate_order(Some(&dec("10")), &dec("10")), Ok(()));
+        assert_eq!(
+            lot.validate_order(Some(&dec("10")), &dec("7")),
+            Err(OrderRejection::QuantityNotOnLot)
+        );
+
+        lot.status = InstrumentStatus::Halted;
+        assert_eq!(
+            lot.validate_order(Some(&dec("10")), &dec("10")),
+            Err(OrderRejection::InstrumentNotTrading)
+        );
+    }
+
+    #[test]
+    fn test_price_bands() {
+        let mut instrument = Instrument::new(Symbol::new("BTC"), "BTC", "USD", Decimal::ONE, Decimal::ONE);
+        let price = |value: i64| Decimal::from(value);
+
+        //Bands are disabled by default
+        assert_eq!(instrument.check_static_band(&price(1000), Some(&price(100))), Ok(()));
+        assert_eq!(instrument.dynamic_band_limit(AskOrBid::Bid, Some(&price(100))), None);
+
+        instrument.bands.static_pct = Some(Decimal::from(10));
+        instrument.bands.dynamic_pct = Some(Decimal::from(5));
+
+        assert_eq!(instrument.check_static_band(&price(110), Some(&price(100))), Ok(()));
+        assert_eq!(instrument.check_static_band(&price(90), Some(&price(100))), Ok(()));
+        assert_eq!(
+            instrument.check_static_band(&price(111), Some(&price(100))),
+            Err(OrderRejection::OutsideStaticBand)
+        );
+        assert_eq!(instrument.check_static_band(&price(111), None), Ok(()));
+
+        assert_eq!(
+            instrument.check_dynamic_band(&price(94), Some(&price(100))),
+            Err(OrderRejection::OutsideDynamicBand)
+        );
+        assert_eq!(instrument.check_dynamic_band(&price(95), Some(&price(100))), Ok(()));
+        assert_eq!(instrument.dynamic_band_limit(AskOrBid::Bid, Some(&price(100))), Some(price(105)));
+        assert_eq!(instrument.dynamic_band_limit(AskOrBid::Ask, Some(&price(100))), Some(price(95)));
+        assert_eq!(instrument.dynamic_band_limit(AskOrBid::Ask, None), None);
+    }
+}
@@ -0,0 +1,397 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use rust_decimal::Decimal;
+
+use crate::{AccountId, EntryId, OrderId, TradeId};
+
+/// Account standing for everything outside of the exchange. Deposits are booked
+/// from it and withdrawals to it.
+pub const SYSTEM_ACCOUNT: AccountId = AccountId::MAX;
+
+/// What caused a balance change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    Deposit,
+    Withdrawal,
+    Hold,
+    Release,
+    Trade,
+    Fee,
+}
+
+/// Part of a balance a posting is applied to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bucket {
+    Available,
+    Held,
+}
+
+/// Order or trade a journal entry originates from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reference {
+    Order(OrderId),
+    Trade(TradeId),
+}
+
+/// Change of a single balance, negative amounts are debits
+#[derive(Debug, Clone, PartialEq)]
+pub struct Posting {
+    pub account: AccountId,
+    pub asset: String,
+    pub bucket: Bucket,
+    pub amount: Decimal,
+}
+
+/// Set of postings booked together. The postings of every asset sum to zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    pub id: EntryId,
+    pub kind: EntryKind,
+    pub reference: Option<Reference>,
+    pub postings: Vec<Posting>,
+}
+
+/// Append only record of every balance change of the ledger. Entries are kept
+/// in memory until they're persisted and cleared, see ``JournalFile``.
+#[derive(Default)]
+pub struct Journal {
+    entries: Vec<JournalEntry>,
+    last_entry_id: EntryId,
+
+    /// Assets of every unbalanced entry recorded, cleared entries included
+    unbalanced: BTreeSet<String>,
+}
+
+/// Journal persisted as csv file, one line per posting, see ``Journal::write_csv``.
+///
+/// Entries already in the file are skipped when they're appended again, so
+/// replaying the write-ahead log after a restart doesn't book them twice.
+pub struct JournalFile {
+    file: File,
+    last_entry_id: EntryId,
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EntryKind::Deposit => write!(f, "deposit"),
+            EntryKind::Withdrawal => write!(f, "withdrawal"),
+            EntryKind::Hold => write!(f, "hold"),
+            EntryKind::Release => write!(f, "release"),
+            EntryKind::Trade => write!(f, "trade"),
+            EntryKind::Fee => write!(f, "fee"),
+        }
+    }
+}
+
+impl fmt::Display for Bucket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Bucket::Available => write!(f, "available"),
+            Bucket::Held => write!(f, "held"),
+        }
+    }
+}
+
+impl Posting {
+    pub fn new(account: AccountId, asset: &str, bucket: Bucket, amount: Decimal) -> Posting {
+        Posting {
+            account,
+            asset: asset.to_string(),
+            bucket,
+            amount,
+        }
+    }
+}
+
+impl JournalEntry {
+    /// Determins whether the postings of every asset sum to zero
+    pub fn is_balanced(&self) -> bool {
+        let mut sums: HashMap<&str, Decimal> = HashMap::new();
+        for posting in &self.postings {
+            *sums.entry(&posting.asset).or_default() += posting.amount;
+        }
+        sums.values().all(|sum| sum.is_zero())
+    }
+}
+
+impl Journal {
+    pub fn new() -> Journal {
+        Journal::default()
+    }
+
+    /// Appends an entry with the next entry id, postings of zero are left out.
+    /// Returns None without recording anything if no posting is left.
+    pub fn record(
+        &mut self,
+        kind: EntryKind,
+        reference: Option<Reference>,
+        mut postings: Vec<Posting>,
+    ) -> Option<&JournalEntry> {
+        postings.retain(|posting| !posting.amount.is_zero());
+        if postings.is_empty() {
+            return None;
+        }
+
+        self.last_entry_id += 1;
+        let entry = JournalEntry {
+            id: self.last_entry_id,
+            kind,
+            reference,
+            postings,
+        };
+        if !entry.is_balanced() {
+            self.unbalanced
+                .extend(entry.postings.iter().map(|posting| posting.asset.clone()));
+        }
+        self.entries.push(entry);
+        self.entries.last()
+    }
+
+    /// Creates an empty journal whose entry ids continue after ``last_entry_id``
+    pub fn resume(last_entry_id: EntryId) -> Journal {
+        Journal {
+            last_entry_id,
+            ..Journal::default()
+        }
+    }
+
+    /// Forgets the entries recorded so far once they have been persisted,
+    /// entry ids continue after them
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Id of the last entry recorded, zero if there is none
+    pub fn last_entry_id(&self) -> EntryId {
+        self.last_entry_id
+    }
+
+    /// Entries recorded since the journal was last cleared, in the order they
+    /// were recorded
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// Assets that were part of an unbalanced entry, empty if every entry is balanced
+    pub fn unbalanced_assets(&self) -> &BTreeSet<String> {
+        &self.unbalanced
+    }
+
+    /// Writes the journal as csv, one line per posting
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_csv_header(writer)?;
+        for entry in &self.entries {
+            write_csv_entry(writer, entry)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_csv_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "entry_id,kind,order_id,trade_id,account,asset,bucket,amount")
+}
+
+fn write_csv_entry<W: Write>(writer: &mut W, entry: &JournalEntry) -> io::Result<()> {
+    let (order_id, trade_id) = match entry.reference {
+        Some(Reference::Order(order_id)) => (order_id.to_string(), String::new()),
+        Some(Reference::Trade(trade_id)) => (String::new(), trade_id.to_string()),
+        None => (String::new(), String::new()),
+    };
+    for posting in &entry.postings {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{}",
+            entry.id,
+            entry.kind,
+            order_id,
+            trade_id,
+            posting.account,
+            posting.asset,
+            posting.bucket,
+            posting.amount
+        )?;
+    }
+    Ok(())
+}
+
+impl JournalFile {
+    /// Opens the journal file at ``path`` for appending, creating it along with
+    /// its header if it doesn't exist yet. A torn last line left behind by a
+    /// crash is cut off along with the rest of its entry, which is written
+    /// again once it's replayed.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<JournalFile> {
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+
+        let mut end = content.rfind('\n').map_or(0, |end| end + 1);
+        let torn = end < content.len();
+
+        // Offset and entry id of every posting line, the header is skipped
+        let mut lines: Vec<(usize, EntryId)> = Vec::new();
+        let mut offset = 0;
+        for (index, line) in content[..end].split_inclusive('\n').enumerate() {
+            if index > 0 {
+                let id = line
+                    .split(',')
+                    .next()
+                    .and_then(|id| id.parse().ok())
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid journal line"))?;
+                lines.push((offset, id));
+            }
+            offset += line.len();
+        }
+
+        if torn {
+            let last_id = lines.last().map(|(_, id)| *id);
+            while lines.last().is_some_and(|(_, id)| Some(*id) == last_id) {
+                end = lines.pop().map_or(end, |(offset, _)| offset);
+            }
+            file.set_len(end as u64)?;
+        }
+        if end == 0 {
+            write_csv_header(&mut file)?;
+        }
+
+        let last_entry_id = lines.last().map_or(0, |(_, id)| *id);
+        Ok(JournalFile { file, last_entry_id })
+    }
+
+    /// Id of the last entry in the file, zero if there is none
+    pub fn last_entry_id(&self) -> EntryId {
+        self.last_entry_id
+    }
+
+    /// Appends the entries the file doesn't hold yet, they're written with a
+    /// single write so a crash can only tear the last line
+    pub fn append(&mut self, entries: &[JournalEntry]) -> io::Result<()> {
+        let mut buffer = Vec::new();
+        let mut last_entry_id = self.last_entry_id;
+        for entry in entries.iter().filter(|entry| entry.id > self.last_entry_id) {
+            write_csv_entry(&mut buffer, entry)?;
+            last_entry_id = entry.id;
+        }
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        self.file.write_all(&buffer)?;
+        self.file.flush()?;
+        self.last_entry_id = last_entry_id;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::journal::{Bucket, EntryKind, Journal, JournalFile, Posting, Reference, SYSTEM_ACCOUNT};
+
+    fn deposit(journal: &mut Journal, account: u64, amount: i64) {
+        let postings = vec![
+            Posting::new(SYSTEM_ACCOUNT, "USD", Bucket::Available, Decimal::from(-amount)),
+            Posting::new(account, "USD", Bucket::Available, Decimal::from(amount)),
+        ];
+        journal.record(EntryKind::Deposit, None, postings);
+    }
+
+    #[test]
+    fn test_record_and_export() {
+        let mut journal = Journal::new();
+        let deposit = vec![
+            Posting::new(SYSTEM_ACCOUNT, "USD", Bucket::Available, Decimal::from(-100)),
+            Posting::new(1, "USD", Bucket::Available, Decimal::from(100)),
+        ];
+        assert_eq!(journal.record(EntryKind::Deposit, None, deposit).unwrap().id, 1);
+
+        //Zero postings are dropped, entries without postings aren't recorded
+        let release = vec![
+            Posting::new(1, "USD", Bucket::Held, Decimal::ZERO),
+            Posting::new(1, "USD", Bucket::Available, Decimal::ZERO),
+        ];
+        assert_eq!(journal.record(EntryKind::Release, Some(Reference::Order(4)), release), None);
+
+        let hold = vec![
+            Posting::new(1, "USD", Bucket::Available, Decimal::from(-40)),
+            Posting::new(1, "USD", Bucket::Held, Decimal::from(40)),
+        ];
+        let entry = journal.record(EntryKind::Hold, Some(Reference::Order(4)), hold).unwrap();
+        assert_eq!(entry.id, 2);
+        assert_eq!(entry.is_balanced(), true);
+
+        let mut csv = Vec::new();
+        journal.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            format!(
+                "entry_id,kind,order_id,trade_id,account,asset,bucket,amount\n\
+                 1,deposit,,,{},USD,available,-100\n\
+                 1,deposit,,,1,USD,available,100\n\
+                 2,hold,4,,1,USD,available,-40\n\
+                 2,hold,4,,1,USD,held,40\n",
+                SYSTEM_ACCOUNT
+            )
+        );
+    }
+
+    #[test]
+    fn test_unbalanced_entry() {
+        let mut journal = Journal::new();
+        let postings = vec![
+            Posting::new(1, "USD", Bucket::Available, Decimal::from(-10)),
+            Posting::new(2, "BTC", Bucket::Available, Decimal::from(10)),
+        ];
+        assert_eq!(journal.record(EntryKind::Trade, Some(Reference::Trade(1)), postings).unwrap().is_balanced(), false);
+    }
+
+    #[test]
+    fn test_unbalanced_assets_survive_clear() {
+        let mut journal = Journal::new();
+        let postings = vec![Posting::new(1, "BTC", Bucket::Available, Decimal::from(1))];
+        journal.record(EntryKind::Trade, Some(Reference::Trade(1)), postings);
+        deposit(&mut journal, 1, 10);
+        journal.clear();
+
+        assert_eq!(journal.entries().len(), 0);
+        assert_eq!(journal.last_entry_id(), 2);
+        assert_eq!(journal.unbalanced_assets().iter().collect::<Vec<&String>>(), vec!["BTC"]);
+    }
+
+    #[test]
+    fn test_journal_file() {
+        let path = std::env::temp_dir().join(format!("journal_test_{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut journal = Journal::new();
+        deposit(&mut journal, 1, 100);
+        deposit(&mut journal, 2, 50);
+        let mut file = JournalFile::open(&path).unwrap();
+        assert_eq!(file.last_entry_id(), 0);
+        file.append(journal.entries()).unwrap();
+        assert_eq!(file.last_entry_id(), 2);
+
+        //Entries the file already holds are skipped, as happens when the log is replayed
+        deposit(&mut journal, 3, 20);
+        let mut file = JournalFile::open(&path).unwrap();
+        assert_eq!(file.last_entry_id(), 2);
+        file.append(journal.entries()).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 7);
+        assert_eq!(content.lines().last().unwrap(), "3,deposit,,,3,USD,available,20");
+
+        //A torn last line is cut off along with the rest of its entry, which is appended again
+        std::fs::write(&path, &content[..content.len() - 5]).unwrap();
+        let mut file = JournalFile::open(&path).unwrap();
+        assert_eq!(file.last_entry_id(), 2);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 5);
+        file.append(journal.entries()).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), content);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
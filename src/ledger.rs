@@ -0,0 +1,524 @@
+use std::collections::HashMap;
+use std::io;
+
+use rust_decimal::prelude::Zero;
+use rust_decimal::Decimal;
+
+use crate::error::ExchangeError;
+use crate::instrument::OrderRejection;
+use crate::journal::{Bucket, EntryKind, Journal, JournalFile, Posting, Reference, SYSTEM_ACCOUNT};
+use crate::snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
+use crate::symbol::AskOrBid;
+use crate::trade::Trade;
+use crate::{AccountId, OrderId};
+
+/// Account all fees are booked to, it pays out maker rebates as well. Not
+/// meant to place orders.
+pub const FEE_ACCOUNT: AccountId = 0;
+
+/// Whether ``account`` is run by the exchange itself, the fee or the system
+/// account. Clients can't act on behalf of them.
+pub fn is_reserved_account(account: AccountId) -> bool {
+    account == FEE_ACCOUNT || account == SYSTEM_ACCOUNT
+}
+
+/// Funds of an account in a single asset
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Balance {
+    /// Free to be used by new orders
+    pub available: Decimal,
+
+    /// Reserved by open orders
+    pub held: Decimal,
+}
+
+/// Funds an open order holds until it's filled or cancelled
+#[derive(Debug, Clone, PartialEq)]
+struct Hold {
+    account: AccountId,
+    asset: String,
+    amount: Decimal,
+}
+
+/// Per account, per asset balances along with the funds held by open orders.
+///
+/// Orders hold what they could spend before being matched: bids the quote asset
+/// at their limit price, asks the base asset. Fills take the traded funds out of
+/// the holds of both sides and credit the counterparties' available balances.
+///
+/// Every balance change is booked as a balanced entry of the ``Journal``, funds
+/// coming from or leaving the exchange are booked against the ``SYSTEM_ACCOUNT``.
+/// All balances of an asset, including the system account's, sum up to zero.
+#[derive(Default)]
+pub struct Ledger {
+    balances: HashMap<AccountId, HashMap<String, Balance>>,
+    holds: HashMap<OrderId, Hold>,
+    journal: Journal,
+}
+
+impl Ledger {
+    pub fn new() -> Ledger {
+        Ledger::default()
+    }
+
+    /// Balance of ``account`` in ``asset``, zero if it never held any
+    pub fn balance(&self, account: &AccountId, asset: &str) -> Balance {
+        self.balances
+            .get(account)
+            .and_then(|balances| balances.get(asset))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn balance_mut(&mut self, account: AccountId, asset: &str) -> &mut Balance {
+        self.balances
+            .entry(account)
+            .or_default()
+            .entry(asset.to_string())
+            .or_default()
+    }
+
+    /// Journal of the balance changes that haven't been persisted yet
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    /// Appends the journal entries to ``file`` and clears them from memory. The
+    /// entries are kept if they can't be written, to be appended next time.
+    pub fn persist_journal(&mut self, file: &mut JournalFile) -> io::Result<()> {
+        file.append(self.journal.entries())?;
+        self.journal.clear();
+        Ok(())
+    }
+
+    /// Applies the postings to the balances and records them in the journal
+    fn post(&mut self, kind: EntryKind, reference: Option<Reference>, postings: Vec<Posting>) {
+        if let Some(entry) = self.journal.record(kind, reference, postings) {
+            debug_assert!(entry.is_balanced());
+            for posting in entry.postings.clone() {
+                let balance = self.balance_mut(posting.account, &posting.asset);
+                match posting.bucket {
+                    Bucket::Available => balance.available += posting.amount,
+                    Bucket::Held => balance.held += posting.amount,
+                }
+            }
+        }
+    }
+
+    /// Moves ``amount`` between the available and held balance of an order's
+    /// account, positive amounts are held
+    fn post_hold(&mut self, order_id: OrderId, account: AccountId, asset: &str, amount: Decimal) {
+        let kind = match amount > Decimal::zero() {
+            true => EntryKind::Hold,
+            false => EntryKind::Release,
+        };
+        let postings = vec![
+            Posting::new(account, asset, Bucket::Available, -amount),
+            Posting::new(account, asset, Bucket::Held, amount),
+        ];
+        self.post(kind, Some(Reference::Order(order_id)), postings);
+    }
+
+    /// Deposits ``amount`` into the available balance of ``account``
+    pub fn credit(&mut self, account: AccountId, asset: &str, amount: &Decimal) {
+        let postings = vec![
+            Posting::new(SYSTEM_ACCOUNT, asset, Bucket::Available, -amount),
+            Posting::new(account, asset, Bucket::Available, *amount),
+        ];
+        self.post(EntryKind::Deposit, None, postings);
+    }
+
+    /// Withdraws ``amount`` from the available balance of ``account``. Funds
+    /// held by open orders can't be withdrawn.
+    pub fn debit(&mut self, account: AccountId, asset: &str, amount: &Decimal) -> Result<(), ExchangeError> {
+        if *amount > self.balance(&account, asset).available {
+            return Err(OrderRejection::InsufficientBalance.into());
+        }
+        let postings = vec![
+            Posting::new(account, asset, Bucket::Available, -amount),
+            Posting::new(SYSTEM_ACCOUNT, asset, Bucket::Available, *amount),
+        ];
+        self.post(EntryKind::Withdrawal, None, postings);
+        Ok(())
+    }
+
+    /// Assets whose balances over all accounts don't sum up to zero, or that
+    /// are part of an unbalanced journal entry. Empty if the ledger is consistent.
+    pub fn check_consistency(&self) -> Vec<String> {
+        let mut sums: HashMap<&str, Decimal> = HashMap::new();
+        for balances in self.balances.values() {
+            for (asset, balance) in balances {
+                *sums.entry(asset).or_default() += balance.available + balance.held;
+            }
+        }
+        let mut unbalanced: Vec<String> = sums
+            .into_iter()
+            .filter(|(_, sum)| !sum.is_zero())
+            .map(|(asset, _)| asset.to_string())
+            .collect();
+
+        for asset in self.journal.unbalanced_assets() {
+            if !unbalanced.contains(asset) {
+                unbalanced.push(asset.clone());
+            }
+        }
+        unbalanced.sort();
+        unbalanced
+    }
+
+    /// Account of an order holding funds, None for orders without hold
+    pub fn account_of(&self, order_id: &OrderId) -> Option<AccountId> {
+        self.holds.get(order_id).map(|hold| hold.account)
+    }
+
+    /// Funds currently held by an order, zero for orders without hold
+    pub fn held_by(&self, order_id: &OrderId) -> Decimal {
+        self.holds
+            .get(order_id)
+            .map(|hold| hold.amount)
+            .unwrap_or_default()
+    }
+
+    /// Holds ``amount`` of the available balance of ``account`` for an order.
+    ///
+    /// Rejects the order if the available balance doesn't cover it, nothing is
+    /// held in that case.
+    pub fn hold(
+        &mut self,
+        order_id: OrderId,
+        account: AccountId,
+        asset: &str,
+        amount: Decimal,
+    ) -> Result<(), ExchangeError> {
+        if amount > self.balance(&account, asset).available {
+            return Err(OrderRejection::InsufficientBalance.into());
+        }
+        self.post_hold(order_id, account, asset, amount);
+
+        let hold = self.holds.entry(order_id).or_insert(Hold {
+            account,
+            asset: asset.to_string(),
+            amount: Decimal::zero(),
+        });
+        hold.amount += amount;
+        Ok(())
+    }
+
+    /// Changes the funds held by an order to ``amount``, holding more of the
+    /// available balance or releasing the difference. Orders without hold are ignored.
+    pub fn resize_hold(&mut self, order_id: &OrderId, amount: Decimal) -> Result<(), ExchangeError> {
+        let (account, asset, current) = match self.holds.get(order_id) {
+            Some(hold) => (hold.account, hold.asset.clone(), hold.amount),
+            None => return Ok(()),
+        };
+        if amount > current {
+            return self.hold(*order_id, account, &asset, amount - current);
+        }
+
+        self.post_hold(*order_id, account, &asset, amount - current);
+        if let Some(hold) = self.holds.get_mut(order_id) {
+            hold.amount = amount;
+        }
+        Ok(())
+    }
+
+    /// Releases everything an order still holds back into the available balance,
+    /// returns the released amount
+    pub fn release(&mut self, order_id: &OrderId) -> Decimal {
+        let hold = match self.holds.remove(order_id) {
+            Some(hold) => hold,
+            None => return Decimal::zero(),
+        };
+        self.post_hold(*order_id, hold.account, &hold.asset, -hold.amount);
+        hold.amount
+    }
+
+    /// Moves the funds of a trade between buyer and seller. The buyer pays the
+    /// trade price out of its hold and receives the base asset, the seller
+    /// delivers the base asset out of its hold and receives the quote asset.
+    /// Fees of the trade are taken from what each side receives and booked to
+    /// the ``FEE_ACCOUNT``. Maker rebates are paid in the asset the taker fee is
+    /// collected in, see ``Trade::maker_fee``.
+    ///
+    /// Both orders must hold funds, nothing is settled if either of them doesn't.
+    pub fn settle(&mut self, trade: &Trade, base_asset: &str, quote_asset: &str) -> Result<(), ExchangeError> {
+        let (buyer_id, seller_id, buyer_fee, seller_fee) = match trade.aggressor {
+            AskOrBid::Bid => (trade.taker_id, trade.maker_id, trade.taker_fee, trade.maker_fee),
+            AskOrBid::Ask => (trade.maker_id, trade.taker_id, trade.maker_fee, trade.taker_fee),
+        };
+        let (buyer, seller) = match (self.account_of(&buyer_id), self.account_of(&seller_id)) {
+            (Some(buyer), Some(seller)) => (buyer, seller),
+            _ => return Err(ExchangeError::BookInconsistent),
+        };
+        let cost = trade.price * trade.amount;
+        self.take_held(&buyer_id, &cost);
+        self.take_held(&seller_id, &trade.amount);
+
+        let postings = vec![
+            Posting::new(buyer, quote_asset, Bucket::Held, -cost),
+            Posting::new(buyer, base_asset, Bucket::Available, trade.amount),
+            Posting::new(seller, base_asset, Bucket::Held, -trade.amount),
+            Posting::new(seller, quote_asset, Bucket::Available, cost),
+        ];
+        let (taker_asset, maker_asset) = match trade.aggressor {
+            AskOrBid::Bid => (base_asset, quote_asset),
+            AskOrBid::Ask => (quote_asset, base_asset),
+        };
+        let maker_fee_asset = match trade.maker_fee < Decimal::zero() {
+            true => taker_asset,
+            false => maker_asset,
+        };
+        let (buyer_fee_asset, seller_fee_asset) = match trade.aggressor {
+            AskOrBid::Bid => (taker_asset, maker_fee_asset),
+            AskOrBid::Ask => (maker_fee_asset, taker_asset),
+        };
+        let fees = vec![
+            Posting::new(buyer, buyer_fee_asset, Bucket::Available, -buyer_fee),
+            Posting::new(FEE_ACCOUNT, buyer_fee_asset, Bucket::Available, buyer_fee),
+            Posting::new(seller, seller_fee_asset, Bucket::Available, -seller_fee),
+            Posting::new(FEE_ACCOUNT, seller_fee_asset, Bucket::Available, seller_fee),
+        ];
+        self.post(EntryKind::Trade, Some(Reference::Trade(trade.id)), postings);
+        self.post(EntryKind::Fee, Some(Reference::Trade(trade.id)), fees);
+        Ok(())
+    }
+
+    /// Takes ``amount`` out of an order's hold. The held balance itself is left
+    /// to the caller's journal entry.
+    fn take_held(&mut self, order_id: &OrderId, amount: &Decimal) {
+        if let Some(hold) = self.holds.get_mut(order_id) {
+            hold.amount -= amount;
+        }
+    }
+}
+
+impl Snapshot for Balance {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        writer.put(&self.available);
+        writer.put(&self.held);
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        Ok(Balance {
+            available: reader.get()?,
+            held: reader.get()?,
+        })
+    }
+}
+
+impl Snapshot for Hold {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        writer.put(&self.account);
+        writer.put(&self.asset);
+        writer.put(&self.amount);
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        Ok(Hold {
+            account: reader.get()?,
+            asset: reader.get()?,
+            amount: reader.get()?,
+        })
+    }
+}
+
+/// Balances and holds are written in order of account and order id. Journal
+/// entries aren't part of the snapshot, they're persisted to the journal file
+/// after every message. A restored ledger starts a new journal that continues
+/// the entry ids.
+impl Snapshot for Ledger {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        let mut balances: Vec<(AccountId, Vec<(String, Balance)>)> = self
+            .balances
+            .iter()
+            .map(|(account, balances)| {
+                let mut balances: Vec<(String, Balance)> =
+                    balances.iter().map(|(asset, balance)| (asset.clone(), *balance)).collect();
+                balances.sort_by(|(asset_a, _), (asset_b, _)| asset_a.cmp(asset_b));
+                (*account, balances)
+            })
+            .collect();
+        balances.sort_by_key(|(account, _)| *account);
+        writer.put(&balances);
+
+        let mut holds: Vec<(OrderId, Hold)> =
+            self.holds.iter().map(|(order_id, hold)| (*order_id, hold.clone())).collect();
+        holds.sort_by_key(|(order_id, _)| *order_id);
+        writer.put(&holds);
+        writer.put(&self.journal.last_entry_id());
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        let mut ledger = Ledger::new();
+        for (account, balances) in reader.get::<Vec<(AccountId, Vec<(String, Balance)>)>>()? {
+            for (asset, balance) in balances {
+                *ledger.balance_mut(account, &asset) = balance;
+            }
+        }
+        ledger.holds = reader.get::<Vec<(OrderId, Hold)>>()?.into_iter().collect();
+        ledger.journal = Journal::resume(reader.get()?);
+        Ok(ledger)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::error::ExchangeError;
+    use crate::instrument::OrderRejection;
+    use crate::journal::{EntryKind, Reference, SYSTEM_ACCOUNT};
+    use crate::ledger::{Balance, Ledger, FEE_ACCOUNT};
+    use crate::symbol::{AskOrBid, Symbol};
+    use crate::trade::Trade;
+
+    fn balance(available: i64, held: i64) -> Balance {
+        Balance {
+            available: Decimal::from(available),
+            held: Decimal::from(held),
+        }
+    }
+
+    #[test]
+    fn test_hold_and_release() {
+        let mut ledger = Ledger::new();
+        ledger.credit(1, "USD", &Decimal::from(100));
+
+        assert_eq!(
+            ledger.hold(0, 1, "USD", Decimal::from(101)),
+            Err(ExchangeError::Rejected(OrderRejection::InsufficientBalance))
+        );
+        assert_eq!(ledger.balance(&1, "USD"), balance(100, 0));
+
+        ledger.hold(0, 1, "USD", Decimal::from(60)).unwrap();
+        assert_eq!(ledger.balance(&1, "USD"), balance(40, 60));
+
+        //Holding more than available is rejected, less gets released
+        assert_eq!(ledger.resize_hold(&0, Decimal::from(101)).is_err(), true);
+        ledger.resize_hold(&0, Decimal::from(20)).unwrap();
+        assert_eq!(ledger.balance(&1, "USD"), balance(80, 20));
+        assert_eq!(ledger.held_by(&0), Decimal::from(20));
+
+        assert_eq!(ledger.release(&0), Decimal::from(20));
+        assert_eq!(ledger.balance(&1, "USD"), balance(100, 0));
+        assert_eq!(ledger.release(&0), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_debit_available_only() {
+        let mut ledger = Ledger::new();
+        ledger.credit(1, "USD", &Decimal::from(100));
+        ledger.hold(0, 1, "USD", Decimal::from(60)).unwrap();
+
+        assert_eq!(
+            ledger.debit(1, "USD", &Decimal::from(41)),
+            Err(ExchangeError::Rejected(OrderRejection::InsufficientBalance))
+        );
+        ledger.debit(1, "USD", &Decimal::from(40)).unwrap();
+        assert_eq!(ledger.balance(&1, "USD"), balance(0, 60));
+        assert_eq!(ledger.journal().entries().last().unwrap().kind, EntryKind::Withdrawal);
+        assert_eq!(ledger.check_consistency(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_settle() {
+        let mut ledger = Ledger::new();
+        ledger.credit(1, "USD", &Decimal::from(100));
+        ledger.credit(2, "BTC", &Decimal::from(5));
+
+        //Bid of account 1 at 20 rests, ask of account 2 takes 3 at 20
+        ledger.hold(0, 1, "USD", Decimal::from(100)).unwrap();
+        ledger.hold(1, 2, "BTC", Decimal::from(3)).unwrap();
+        let mut last_trade_id = 0;
+        let trade = Trade::new(
+            &mut last_trade_id,
+            Symbol::new("BTC"),
+            0,
+            1,
+            AskOrBid::Ask,
+            Decimal::from(20),
+            Decimal::from(3),
+        );
+        ledger.settle(&trade, "BTC", "USD").unwrap();
+
+        assert_eq!(ledger.balance(&1, "USD"), balance(0, 40));
+        assert_eq!(ledger.balance(&1, "BTC"), balance(3, 0));
+        assert_eq!(ledger.balance(&2, "BTC"), balance(2, 0));
+        assert_eq!(ledger.balance(&2, "USD"), balance(60, 0));
+        assert_eq!(ledger.held_by(&1), Decimal::ZERO);
+
+        let kinds: Vec<EntryKind> = ledger.journal().entries().iter().map(|entry| entry.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![EntryKind::Deposit, EntryKind::Deposit, EntryKind::Hold, EntryKind::Hold, EntryKind::Trade]
+        );
+        assert_eq!(ledger.check_consistency(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_settle_requires_holds() {
+        let mut ledger = Ledger::new();
+        ledger.credit(1, "USD", &Decimal::from(60));
+        ledger.credit(2, "BTC", &Decimal::from(5));
+        ledger.hold(1, 2, "BTC", Decimal::from(3)).unwrap();
+
+        //Bid 0 holds nothing, the trade isn't settled at all
+        let mut last_trade_id = 0;
+        let mut trade = Trade::new(
+            &mut last_trade_id,
+            Symbol::new("BTC"),
+            0,
+            1,
+            AskOrBid::Ask,
+            Decimal::from(20),
+            Decimal::from(3),
+        );
+        trade.taker_fee = Decimal::from(6);
+        assert_eq!(ledger.settle(&trade, "BTC", "USD"), Err(ExchangeError::BookInconsistent));
+        assert_eq!(ledger.balance(&2, "BTC"), balance(2, 3));
+        assert_eq!(ledger.held_by(&1), Decimal::from(3));
+        assert_eq!(ledger.balance(&SYSTEM_ACCOUNT, "USD"), balance(-60, 0));
+
+        ledger.hold(0, 1, "USD", Decimal::from(60)).unwrap();
+        ledger.settle(&trade, "BTC", "USD").unwrap();
+        assert_eq!(ledger.balance(&2, "USD"), balance(54, 0));
+        assert_eq!(ledger.balance(&FEE_ACCOUNT, "USD"), balance(6, 0));
+
+        let entry = ledger.journal().entries().last().unwrap();
+        assert_eq!(entry.kind, EntryKind::Fee);
+        assert_eq!(entry.reference, Some(Reference::Trade(1)));
+        assert_eq!(ledger.check_consistency(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_settle_rebate_in_taker_fee_asset() {
+        let mut ledger = Ledger::new();
+        ledger.credit(1, "USD", &Decimal::from(60));
+        ledger.credit(2, "BTC", &Decimal::from(3));
+        ledger.hold(0, 1, "USD", Decimal::from(60)).unwrap();
+        ledger.hold(1, 2, "BTC", Decimal::from(3)).unwrap();
+
+        //Seller takes, its fee in USD funds the rebate of the buying maker
+        let mut last_trade_id = 0;
+        let mut trade = Trade::new(
+            &mut last_trade_id,
+            Symbol::new("BTC"),
+            0,
+            1,
+            AskOrBid::Ask,
+            Decimal::from(20),
+            Decimal::from(3),
+        );
+        trade.taker_fee = Decimal::from(6);
+        trade.maker_fee = Decimal::from(-4);
+        ledger.settle(&trade, "BTC", "USD").unwrap();
+
+        assert_eq!(ledger.balance(&1, "BTC"), balance(3, 0));
+        assert_eq!(ledger.balance(&1, "USD"), balance(4, 0));
+        assert_eq!(ledger.balance(&2, "USD"), balance(54, 0));
+        assert_eq!(ledger.balance(&FEE_ACCOUNT, "USD"), balance(2, 0));
+        assert_eq!(ledger.balance(&FEE_ACCOUNT, "BTC"), balance(0, 0));
+        assert_eq!(ledger.check_consistency(), Vec::<String>::new());
+    }
+}
@@ -1,9 +1,30 @@
+pub mod auction;
+pub mod clock;
 pub mod core;
+pub mod custody;
+pub mod error;
+pub mod fees;
 pub mod inbound_http_server;
 pub mod inbound_server;
+pub mod instrument;
+pub mod journal;
+pub mod ledger;
 pub mod order_matcher;
 pub mod order_matcher_fifo;
+pub mod order_matcher_fifo_top_order;
+pub mod order_matcher_pro_rata;
 pub mod orderbook;
+pub mod session;
+pub mod snapshot;
 pub mod symbol;
+pub mod trade;
+pub mod trigger_book;
+pub mod wal;
 
 pub type OrderId = u64;
+pub type TradeId = u64;
+pub type AccountId = u64;
+pub type EntryId = u64;
+
+/// Nanoseconds since unix epoch
+pub type Timestamp = u64;
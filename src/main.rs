@@ -1,10 +1,137 @@
-use log::info;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use log::{error, info};
 
 use exchange_prototype::core::ExchangeCore;
+use exchange_prototype::custody::FileCustodian;
+use exchange_prototype::instrument::InstrumentRegistry;
+use exchange_prototype::journal::JournalFile;
+use exchange_prototype::snapshot::latest_snapshot;
+use exchange_prototype::wal::{FsyncPolicy, WriteAheadLog};
+
+/// Instrument config used in case no path is passed as first argument
+const DEFAULT_INSTRUMENT_FILE: &str = "instruments.json";
+
+/// Environment variable holding the path of the write-ahead log, nothing is
+/// logged if it isn't set
+const WAL_FILE_VAR: &str = "EXCHANGE_WAL";
+
+/// Environment variable holding the fsync policy of the write-ahead log
+const WAL_FSYNC_VAR: &str = "EXCHANGE_WAL_FSYNC";
+
+/// Environment variable holding the directory snapshots are written to and
+/// restored from, no snapshots are taken if it isn't set
+const SNAPSHOT_DIR_VAR: &str = "EXCHANGE_SNAPSHOT_DIR";
+
+/// Environment variable holding the number of log entries between snapshots
+const SNAPSHOT_INTERVAL_VAR: &str = "EXCHANGE_SNAPSHOT_INTERVAL";
+
+/// Environment variable holding the path of the csv file the ledger journal is
+/// appended to, journal entries are only kept in memory if it isn't set
+const JOURNAL_FILE_VAR: &str = "EXCHANGE_JOURNAL";
+
+/// Log entries between snapshots in case no interval is set
+const DEFAULT_SNAPSHOT_INTERVAL: u64 = 10_000;
 
 fn main() {
     env_logger::init();
     info!("Starting Up...");
-    let core = ExchangeCore::new();
+
+    let instrument_file = env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_INSTRUMENT_FILE.to_string());
+    info!("Loading instruments from {}...", instrument_file);
+
+    let instruments = match InstrumentRegistry::from_file(&instrument_file) {
+        Ok(instruments) => instruments,
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut core = ExchangeCore::new(instruments.clone());
+
+    // Transfers are only kept in memory unless a custody file is passed as second argument
+    if let Some(custody_file) = env::args().nth(2) {
+        info!("Loading custody transfers from {}...", custody_file);
+        match FileCustodian::open(&custody_file) {
+            Ok(custodian) => core = core.with_custodian(Box::new(custodian)),
+            Err(err) => {
+                error!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Opened before recovery, so the entries booked while replaying reach the file
+    if let Ok(journal_file) = env::var(JOURNAL_FILE_VAR) {
+        info!("Appending the journal to {}...", journal_file);
+        match JournalFile::open(&journal_file) {
+            Ok(file) => core = core.with_journal_file(file),
+            Err(err) => {
+                error!("Unable to open journal file {}: {}", journal_file, err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // The latest snapshot is restored first, replay then only applies the log entries after it
+    if let Ok(snapshot_dir) = env::var(SNAPSHOT_DIR_VAR) {
+        let interval = match env::var(SNAPSHOT_INTERVAL_VAR).map(|interval| interval.parse::<u64>()) {
+            Ok(Ok(interval)) if interval > 0 => interval,
+            Err(_) => DEFAULT_SNAPSHOT_INTERVAL,
+            _ => {
+                error!("Invalid {}, expected a positive number of entries", SNAPSHOT_INTERVAL_VAR);
+                std::process::exit(1);
+            }
+        };
+
+        let restored = latest_snapshot(&snapshot_dir).and_then(|latest| match latest {
+            Some((seq, path)) => {
+                info!("Restoring snapshot taken at log entry {}...", seq);
+                fs::read(path).and_then(|snapshot| core.restore(&snapshot))
+            }
+            None => Ok(()),
+        });
+        if let Err(err) = restored {
+            error!("Unable to restore snapshot: {}", err);
+            std::process::exit(1);
+        }
+        core = core.with_snapshots(PathBuf::from(snapshot_dir), interval);
+    }
+
+    if let Ok(wal_file) = env::var(WAL_FILE_VAR) {
+        let policy = match env::var(WAL_FSYNC_VAR).map(|policy| FsyncPolicy::from_str(&policy)) {
+            Ok(Ok(policy)) => policy,
+            Ok(Err(_)) => {
+                error!("Invalid {}, expected always, never or a number of entries", WAL_FSYNC_VAR);
+                std::process::exit(1);
+            }
+            Err(_) => FsyncPolicy::default(),
+        };
+
+        info!("Replaying write-ahead log {}...", wal_file);
+        let recovered = WriteAheadLog::open(&wal_file, policy, &instruments).and_then(|(wal, entries)| {
+            if wal.last_seq() < core.last_seq() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Snapshot is ahead of the write-ahead log",
+                ));
+            }
+            core.replay(&entries).map(|_| wal)
+        });
+        match recovered {
+            Ok(wal) => core = core.with_write_ahead_log(wal),
+            Err(err) => {
+                error!("Unable to recover from write-ahead log: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
     core.run();
 }
@@ -1,17 +1,25 @@
-use crate::orderbook::{Orderbook};
+use crate::error::ExchangeError;
+use crate::orderbook::{Order, Orderbook, OrderbookPage, OrdersIndex};
+use crate::snapshot::{invalid_snapshot, Snapshot, SnapshotReader, SnapshotWriter};
 use crate::symbol::AskOrBid;
-use crate::OrderId;
-use rust_decimal::Decimal;
+use crate::trade::Trade;
+use crate::{OrderId, TradeId};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
 
-pub struct Maker {
-    order_id: OrderId,
-    price: Decimal,
-    filled: Decimal,
-}
+use rust_decimal::prelude::Zero;
+use rust_decimal::Decimal;
 
 pub struct Match {
     pub taker: OrderId,
-    pub makers: Vec<(OrderId, Decimal)>, //OrderId, filled
+
+    /// Trades in the order they have been executed
+    pub trades: Vec<Trade>,
+
+    /// Orders, taker included, whose amount got cancelled to prevent a
+    /// self-trade, together with the cancelled amount
+    pub self_trades: Vec<(OrderId, Decimal)>,
 }
 
 impl Match {
@@ -19,29 +27,500 @@ impl Match {
     pub fn new(taker: OrderId) -> Match {
         Match {
             taker,
-            makers: Vec::new(),
+            trades: Vec::new(),
+            self_trades: Vec::new(),
+        }
+    }
+
+    /// Cumulative amount of the taker cancelled by self-trade prevention
+    pub fn taker_cancelled(&self) -> Decimal {
+        self.self_trades
+            .iter()
+            .filter(|(order_id, _)| *order_id == self.taker)
+            .map(|(_, cancelled)| *cancelled)
+            .sum()
+    }
+
+    /// Neither trades nor self-trade cancellations happened
+    pub fn is_empty(&self) -> bool {
+        self.trades.is_empty() && self.self_trades.is_empty()
+    }
+
+    /// Cumulative amount the taker got filled across all makers
+    pub fn filled(&self) -> Decimal {
+        self.trades.iter().map(|trade| trade.amount).sum()
+    }
+
+    /// Volume weighted average execution price of the taker.
+    ///
+    /// Returns None in case nothing has been filled.
+    pub fn average_price(&self) -> Option<Decimal> {
+        let filled = self.filled();
+        if filled.is_zero() {
+            return None;
+        }
+        let notional: Decimal = self
+            .trades
+            .iter()
+            .map(|trade| trade.price * trade.amount)
+            .sum();
+        Some(notional / filled)
+    }
+}
+
+/// Time in force of a limit order, defines what happens to the unfilled remainder
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum TimeInForce {
+    /// Remainder rests on the book until it's filled or cancelled
+    #[default]
+    GoodTillCancel,
+
+    /// Match whatever is possible right away, cancel the remainder
+    ImmediateOrCancel,
+
+    /// Either fill the whole amount right away or reject the order without
+    /// touching the book
+    FillOrKill,
+}
+
+impl FromStr for TimeInForce {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "gtc" => Ok(TimeInForce::GoodTillCancel),
+            "ioc" => Ok(TimeInForce::ImmediateOrCancel),
+            "fok" => Ok(TimeInForce::FillOrKill),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for TimeInForce {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeInForce::GoodTillCancel => write!(f, "gtc"),
+            TimeInForce::ImmediateOrCancel => write!(f, "ioc"),
+            TimeInForce::FillOrKill => write!(f, "fok"),
         }
     }
 }
 
+/// What to do with a post-only limit order that would take liquidity on entry
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PostOnly {
+    /// Reject the order
+    Reject,
+
+    /// Reprice the order one tick behind the opposite best price
+    Slide,
+}
+
+impl FromStr for PostOnly {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(PostOnly::Reject),
+            "slide" => Ok(PostOnly::Slide),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for PostOnly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PostOnly::Reject => write!(f, "reject"),
+            PostOnly::Slide => write!(f, "slide"),
+        }
+    }
+}
+
+/// Self-trade prevention mode, applied once a taker would trade against a
+/// maker of the same account
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum SelfTradePrevention {
+    /// Cancel the remainder of the taker
+    #[default]
+    CancelNewest,
+
+    /// Cancel the maker, the taker continues matching
+    CancelOldest,
+
+    /// Cancel both taker remainder and maker
+    CancelBoth,
+
+    /// Decrement both by the smaller remaining amount without trading, the
+    /// order with nothing left is cancelled
+    DecrementAndCancel,
+}
+
+impl SelfTradePrevention {
+    /// Amounts to cancel from taker and maker once they'd trade against each other
+    pub fn cancellations(&self, taker_remaining: &Decimal, maker_remaining: &Decimal) -> (Decimal, Decimal) {
+        match self {
+            SelfTradePrevention::CancelNewest => (*taker_remaining, Decimal::ZERO),
+            SelfTradePrevention::CancelOldest => (Decimal::ZERO, *maker_remaining),
+            SelfTradePrevention::CancelBoth => (*taker_remaining, *maker_remaining),
+            SelfTradePrevention::DecrementAndCancel => {
+                let decrement = (*taker_remaining).min(*maker_remaining);
+                (decrement, decrement)
+            }
+        }
+    }
+}
+
+impl FromStr for SelfTradePrevention {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cancel_newest" => Ok(SelfTradePrevention::CancelNewest),
+            "cancel_oldest" => Ok(SelfTradePrevention::CancelOldest),
+            "cancel_both" => Ok(SelfTradePrevention::CancelBoth),
+            "decrement_cancel" => Ok(SelfTradePrevention::DecrementAndCancel),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for SelfTradePrevention {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SelfTradePrevention::CancelNewest => write!(f, "cancel_newest"),
+            SelfTradePrevention::CancelOldest => write!(f, "cancel_oldest"),
+            SelfTradePrevention::CancelBoth => write!(f, "cancel_both"),
+            SelfTradePrevention::DecrementAndCancel => write!(f, "decrement_cancel"),
+        }
+    }
+}
+
+/// Outcome of amending a resting order
+pub enum AmendResult {
+    /// Amount got reduced in place, the order keeps its position in the queue
+    Reduced,
+
+    /// Order got re-entered at the back of the queue and might have been matched
+    /// on the way
+    Replaced(Option<Match>),
+}
+
 pub enum MatchError {
     CantTrade,
 }
 
+/// Rounds an amount down to a multiple of the lot size
+pub(crate) fn round_down_to_lot(amount: Decimal, lot_size: &Decimal) -> Decimal {
+    if lot_size.is_zero() {
+        return amount;
+    }
+    (amount / lot_size).floor() * lot_size
+}
+
+/// Cancels amounts of a taker and a maker of the same account instead of
+/// letting them trade, according to the taker's ``SelfTradePrevention`` mode.
+/// Makers with nothing left are removed from the page and the index.
+pub(crate) fn prevent_self_trade(
+    page: &mut OrderbookPage,
+    orders_index: &mut OrdersIndex,
+    taker: &mut Order,
+    maker_id: &OrderId,
+    result: &mut Match,
+) {
+    let maker_remaining = match page.get(maker_id) {
+        Some(maker) => maker.remaining(),
+        None => return,
+    };
+    let (taker_cancelled, maker_cancelled) = taker.stp.cancellations(&taker.unfilled, &maker_remaining);
+
+    if maker_cancelled > Decimal::zero() {
+        if page.decrement(maker_id, &maker_cancelled) {
+            orders_index.remove(maker_id);
+        }
+        result.self_trades.push((*maker_id, maker_cancelled));
+    }
+    if taker_cancelled > Decimal::zero() {
+        taker.unfilled -= taker_cancelled;
+        result.self_trades.push((taker.id, taker_cancelled));
+    }
+}
+
+/// Limit order handling shared by matchers built on ``match_by_allocation``, see
+/// ``OrderMatcher::match_limit``
+pub(crate) fn match_limit_by_allocation<F>(
+    orderbook: &mut Orderbook,
+    last_trade_id: &mut TradeId,
+    order: Order,
+    side: AskOrBid,
+    price: &Decimal,
+    time_in_force: TimeInForce,
+    allocate: F,
+) -> Result<Option<Match>, ExchangeError>
+where
+    F: Fn(&OrderbookPage, &Decimal) -> Vec<(OrderId, Decimal)>,
+{
+    let amount = order.remaining();
+    orderbook.check_order_data(&order.id, Some(price), &amount)?;
+
+    // Kill the order up front if the book can't fill it completely
+    if time_in_force == TimeInForce::FillOrKill && orderbook.get_fillable_amount_by_page(side, price, &order) < amount {
+        return Ok(None);
+    }
+
+    // The taker matches with its whole amount, hidden or not
+    let mut taker = order.taker();
+    let result = match_by_allocation(orderbook, last_trade_id, &mut taker, side, Some(price), allocate);
+
+    // If taker isn't fully absorbed, insert order unless it's immediate or cancel
+    if taker.unfilled > Decimal::zero() && time_in_force == TimeInForce::GoodTillCancel {
+        orderbook.insert_limit(order.resized(taker.unfilled), side, *price)?;
+    }
+
+    Ok((!result.is_empty()).then_some(result))
+}
+
+/// Market order handling shared by matchers built on ``match_by_allocation``, see
+/// ``OrderMatcher::match_market``
+pub(crate) fn match_market_by_allocation<F>(
+    orderbook: &mut Orderbook,
+    last_trade_id: &mut TradeId,
+    order: Order,
+    side: AskOrBid,
+    allocate: F,
+) -> Result<Option<Match>, ExchangeError>
+where
+    F: Fn(&OrderbookPage, &Decimal) -> Vec<(OrderId, Decimal)>,
+{
+    orderbook.check_order_data(&order.id, None, &order.remaining())?;
+
+    // Market orders never rest on the book, any unfilled remainder is dropped
+    let mut taker = order.taker();
+    let result = match_by_allocation(orderbook, last_trade_id, &mut taker, side, None, allocate);
+
+    Ok((!result.is_empty()).then_some(result))
+}
+
+/// Matches ``order`` against the opposite side of the book page by page, leaving
+/// the split of fills between the orders of a page up to ``allocate``.
+///
+/// ``allocate`` is given the best matchable page and the unfilled amount of the
+/// taker and returns the amount each maker gets filled with, in the order the
+/// trades should be recorded in. Makers that get fully filled are removed from
+/// the book, iceberg makers are replenished from their reserve and moved to the
+/// back of the page's queue. Pages are allocated repeatedly until the taker is
+/// fully filled, the book runs out of pages within ``limit`` or ``allocate``
+/// doesn't hand out anything anymore.
+///
+/// Allocations aren't time ordered, so self-trades are prevented for all orders
+/// of the taker's account on a page before the page gets allocated.
+pub(crate) fn match_by_allocation<F>(
+    orderbook: &mut Orderbook,
+    last_trade_id: &mut TradeId,
+    order: &mut Order,
+    side: AskOrBid,
+    limit: Option<&Decimal>,
+    allocate: F,
+) -> Match
+where
+    F: Fn(&OrderbookPage, &Decimal) -> Vec<(OrderId, Decimal)>,
+{
+    let symbol = orderbook.get_symbol().clone();
+    let (orderbook_maker, orders_index) = match side {
+        AskOrBid::Ask => (&mut orderbook.orders_bid, &mut orderbook.orders_index),
+        AskOrBid::Bid => (&mut orderbook.orders_ask, &mut orderbook.orders_index),
+    };
+
+    let mut result = Match::new(order.id);
+
+    while order.unfilled > Decimal::zero() {
+        let best_page = match side {
+            AskOrBid::Bid => orderbook_maker.iter_mut().next(),
+            AskOrBid::Ask => orderbook_maker.iter_mut().next_back(),
+        };
+        let (page_price, page) = match best_page {
+            Some((page_price, page)) => (*page_price, page),
+            None => break,
+        };
+        let crosses = limit.is_none_or(|limit| match side {
+            AskOrBid::Bid => page_price <= *limit,
+            AskOrBid::Ask => page_price >= *limit,
+        });
+        if !crosses {
+            break;
+        }
+
+        let own_orders: Vec<OrderId> = page
+            .orders
+            .values()
+            .filter(|maker| order.is_self_trade(maker))
+            .map(|maker| maker.id)
+            .collect();
+        for maker_id in own_orders {
+            if order.unfilled.is_zero() {
+                break;
+            }
+            prevent_self_trade(page, orders_index, order, &maker_id, &mut result);
+        }
+
+        let allocations = match order.unfilled.is_zero() {
+            true => Vec::new(),
+            false => allocate(page, &order.unfilled),
+        };
+        if allocations.is_empty() {
+            // Self-trade prevention may have emptied the page, the next one can still fill
+            if page.orders.is_empty() {
+                orderbook_maker.remove(&page_price);
+                continue;
+            }
+            break;
+        }
+
+        for (maker_id, amount) in allocations {
+            if page.get(&maker_id).is_none() {
+                continue;
+            }
+            order.unfilled -= amount;
+
+            // Record the execution at the maker's price
+            result.trades.push(Trade::new(
+                last_trade_id,
+                symbol.clone(),
+                maker_id,
+                order.id,
+                side,
+                page_price,
+                amount,
+            ));
+
+            if page.fill(&maker_id, &amount) {
+                orders_index.remove(&maker_id);
+            }
+        }
+
+        if page.orders.is_empty() {
+            orderbook_maker.remove(&page_price);
+        }
+    }
+
+    result
+}
+
 pub trait OrderMatcher {
+    /// Matches a limit order against the book. What happens to the unfilled
+    /// remainder depends on ``time_in_force``: good till cancel orders are inserted
+    /// into the book at the limit price, immediate or cancel remainders are dropped.
+    /// Fill or kill orders are only matched if the whole amount can be filled.
+    ///
+    /// The whole remaining amount of ``order`` is matched, the peak of iceberg
+    /// orders only applies once the remainder rests on the book.
+    ///
+    /// Trade ids are drawn from ``last_trade_id``, the global trade sequence.
+    /// Returns Ok(None) in case nothing has been matched. The book is left
+    /// untouched in case of an error.
     fn match_limit(
         &self,
         orderbook: &mut Orderbook,
-        order_id: &OrderId,
+        last_trade_id: &mut TradeId,
+        order: Order,
         side: AskOrBid,
         price: &Decimal,
-        amount: &Decimal,
-    ) -> Option<Match>;
+        time_in_force: TimeInForce,
+    ) -> Result<Option<Match>, ExchangeError>;
+
+    /// Matches a market order against the book. Market orders never rest,
+    /// any unfilled remainder is cancelled.
     fn match_market(
         &self,
         orderbook: &mut Orderbook,
-        order_id: &OrderId,
+        last_trade_id: &mut TradeId,
+        order: Order,
         side: AskOrBid,
+    ) -> Result<Option<Match>, ExchangeError>;
+
+    /// Amends price and / or amount of a resting order, keeping its id.
+    ///
+    /// Reducing the amount at the same price keeps time priority. Increasing the
+    /// amount or changing the price is handled as cancel / replace: the order
+    /// loses its priority and is matched like a new limit order. Iceberg orders
+    /// keep their peak size either way, ``amount`` refers to the total remaining.
+    fn amend_order(
+        &self,
+        orderbook: &mut Orderbook,
+        last_trade_id: &mut TradeId,
+        order_id: &OrderId,
+        price: &Decimal,
         amount: &Decimal,
-    ) -> Option<Match>;
+    ) -> Result<AmendResult, ExchangeError> {
+        let (side, current_price) = orderbook.get_order_location(order_id)?;
+
+        if *price <= Decimal::zero() {
+            return Err(ExchangeError::InvalidPrice(*price));
+        }
+        if *amount <= Decimal::zero() {
+            return Err(ExchangeError::InvalidQuantity(*amount));
+        }
+
+        let remaining = orderbook
+            .get_order_mut(order_id)
+            .ok_or(ExchangeError::BookInconsistent)?
+            .remaining();
+        if *price == current_price && *amount <= remaining {
+            if *amount < remaining {
+                orderbook.reduce_order(order_id, amount)?;
+            }
+            return Ok(AmendResult::Reduced);
+        }
+
+        let cancelled = orderbook.cancel_limit(order_id)?;
+        let result = self.match_limit(
+            orderbook,
+            last_trade_id,
+            cancelled.resized(*amount),
+            side,
+            price,
+            TimeInForce::GoodTillCancel,
+        )?;
+        Ok(AmendResult::Replaced(result))
+    }
+}
+
+impl Snapshot for SelfTradePrevention {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        let mode: u8 = match self {
+            SelfTradePrevention::CancelNewest => 0,
+            SelfTradePrevention::CancelOldest => 1,
+            SelfTradePrevention::CancelBoth => 2,
+            SelfTradePrevention::DecrementAndCancel => 3,
+        };
+        writer.put(&mode);
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        match reader.get::<u8>()? {
+            0 => Ok(SelfTradePrevention::CancelNewest),
+            1 => Ok(SelfTradePrevention::CancelOldest),
+            2 => Ok(SelfTradePrevention::CancelBoth),
+            3 => Ok(SelfTradePrevention::DecrementAndCancel),
+            _ => Err(invalid_snapshot("invalid self-trade prevention")),
+        }
+    }
+}
+
+impl Snapshot for PostOnly {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        let mode: u8 = match self {
+            PostOnly::Reject => 0,
+            PostOnly::Slide => 1,
+        };
+        writer.put(&mode);
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        match reader.get::<u8>()? {
+            0 => Ok(PostOnly::Reject),
+            1 => Ok(PostOnly::Slide),
+            _ => Err(invalid_snapshot("invalid post-only mode")),
+        }
+    }
 }
@@ -1,13 +1,16 @@
 use std::collections::BTreeMap;
 
-use crate::order_matcher::{Match, OrderMatcher};
+use crate::error::ExchangeError;
+use crate::order_matcher::{prevent_self_trade, Match, OrderMatcher, TimeInForce};
 use crate::orderbook::{Order, Orderbook, OrderbookPage};
 use crate::symbol::AskOrBid;
-use crate::OrderId;
+use crate::trade::Trade;
+use crate::TradeId;
 
 use rust_decimal::prelude::Zero;
 use rust_decimal::Decimal;
 
+#[derive(Default)]
 pub struct OrderMatcherFifo {}
 
 impl OrderMatcherFifo {
@@ -27,143 +30,201 @@ fn delete_marked_page (page_to_remove: Option<Decimal>, orderbook_maker: &mut BT
     }
 }
 
-impl OrderMatcher for OrderMatcherFifo {
-    fn match_limit(
-        &self,
-        orderbook: &mut Orderbook,
-        order_id: &OrderId,
-        side: AskOrBid,
-        price: &Decimal,
-        amount: &Decimal,
-    ) -> Option<Match> {
-        let (orderbook_maker, _orderbook_taker) = match side {
-            AskOrBid::Ask => (&mut orderbook.orders_bid, &mut orderbook.orders_ask),
-            AskOrBid::Bid => (&mut orderbook.orders_ask, &mut orderbook.orders_bid),
+/// Matches ``order`` against the opposite side of the book with FIFO priority.
+///
+/// Pages are only taken as long as their price doesn't exceed ``limit``. Without a
+/// limit the order walks the book until it's either fully filled or the opposite
+/// side is empty. The unfilled remainder is left in ``order``, it's up to the caller
+/// to either rest or discard it.
+///
+/// Makers of the taker's own account are never traded against, they're handled
+/// by the taker's ``SelfTradePrevention`` mode once they reach the front of the queue.
+fn match_against_book(
+    orderbook: &mut Orderbook,
+    last_trade_id: &mut TradeId,
+    order: &mut Order,
+    side: AskOrBid,
+    limit: Option<&Decimal>,
+) -> Match {
+    let symbol = orderbook.get_symbol().clone();
+    let orderbook_maker = match side {
+        AskOrBid::Ask => &mut orderbook.orders_bid,
+        AskOrBid::Bid => &mut orderbook.orders_ask,
+    };
+
+    let mut result = Match::new(order.id);
+    let mut page_to_remove = None;
+
+    // Iterate over existing pages (discrete price levels) until the order is fully matched
+    'page_loop: loop {
+        // Get matchable orders book pages
+        let matchable_pages = match side {
+            AskOrBid::Bid => orderbook_maker
+                .iter_mut()
+                .find(|(page_price, _)| limit.is_none_or(|limit| *page_price <= limit)),
+            AskOrBid::Ask => orderbook_maker
+                .iter_mut()
+                .rev()
+                .find(|(page_price, _)| limit.is_none_or(|limit| *page_price >= limit)),
         };
 
-        let mut order = Order {
-            id: order_id.clone(),
-            unfilled: amount.clone(),
-        };
-        
-        let mut makers: Vec<(OrderId, Decimal)> = Vec::new(); //Decimal = filled
-        let mut page_to_remove = None;
-
-        // Iterate over existing pages (discrete price levels) until the order is fully matched
-        'page_loop: loop {
-            // Get matchable orders book pages
-            let mut matchable_pages = match side {
-                AskOrBid::Bid => orderbook_maker
-                    .iter_mut()
-                    .find(|(page_price, _)| *page_price <= price),
-                AskOrBid::Ask => orderbook_maker
-                    .iter_mut()
-                    .rev()
-                    .find(|(page_price, _)| *page_price >= price),
-            };
-
-            // Orderbook still contains matchable pages, match page with FIFO
-            if let Some((page_price, ref mut page)) = matchable_pages {
-                'order_loop: loop {
-                    // Order fully matched, break
-                    if order.unfilled == Decimal::zero() {
-                        break 'page_loop;
+        // Orderbook still contains matchable pages, match page with FIFO
+        if let Some((page_price, page)) = matchable_pages {
+            'order_loop: loop {
+                // Order fully matched, break
+                if order.unfilled == Decimal::zero() {
+                    // Page might have been emptied by the last fill
+                    if page.orders.is_empty() {
+                        page_to_remove = Some(*page_price);
                     }
+                    break 'page_loop;
+                }
 
-                    // Page still contains matchable orders, do the matching
-                    if let Some(mut maker_entry) = page.orders.entries().next() {
-                        let maker_order = maker_entry.get_mut();
-
-                        // Maker order fully absorbs taker order
-                        if maker_order.unfilled > order.unfilled {
-                            maker_order.unfilled -= order.unfilled;
+                // Page still contains matchable orders, do the matching
+                if let Some(mut maker_entry) = page.orders.entries().next() {
+                    if order.is_self_trade(maker_entry.get()) {
+                        let maker_id = *maker_entry.key();
+                        prevent_self_trade(page, &mut orderbook.orders_index, order, &maker_id, &mut result);
+                        continue 'order_loop;
+                    }
 
-                            // Adjust amount of assets at current price level
-                            page.amount -= order.unfilled;
+                    let maker_order = maker_entry.get_mut();
 
-                            // Add maker to the list of makers that matched our order
-                            makers.push((maker_order.id, order.unfilled));
+                    // Maker order fully absorbs taker order
+                    if maker_order.unfilled > order.unfilled {
+                        maker_order.unfilled -= order.unfilled;
 
-                            order.unfilled = Decimal::ZERO;
-                        } else {
-                            // Maker order partially absorbs taker order
-                            order.unfilled -= maker_order.unfilled;
+                        // Adjust amount of assets at current price level
+                        page.amount -= order.unfilled;
 
-                            // Adjust amount of assets at current price level
-                            page.amount -= maker_order.unfilled;
-                            makers.push((maker_order.id, maker_order.unfilled));
+                        // Record the execution at the maker's price
+                        result.trades.push(Trade::new(
+                            last_trade_id,
+                            symbol.clone(),
+                            maker_order.id,
+                            order.id,
+                            side,
+                            *page_price,
+                            order.unfilled,
+                        ));
 
-                            // Remove now empty taker order
+                        order.unfilled = Decimal::ZERO;
+                    } else {
+                        // Maker order partially absorbs taker order
+                        order.unfilled -= maker_order.unfilled;
+
+                        // Adjust amount of assets at current price level
+                        page.amount -= maker_order.unfilled;
+                        result.trades.push(Trade::new(
+                            last_trade_id,
+                            symbol.clone(),
+                            maker_order.id,
+                            order.id,
+                            side,
+                            *page_price,
+                            maker_order.unfilled,
+                        ));
+
+                        if maker_order.hidden > Decimal::zero() {
+                            // Display the next peak of the iceberg, it goes to the
+                            // back of the queue and loses its time priority
+                            maker_order.unfilled = Decimal::ZERO;
+                            let refill = maker_order.replenish();
+                            page.hidden -= refill;
+                            page.amount += refill;
+                            let maker_order = maker_entry.remove();
+                            page.orders.insert(maker_order.id, maker_order);
+                        } else {
+                            // Remove now empty maker order
                             orderbook.orders_index.remove(&maker_order.id);
                             maker_entry.remove();
                         }
-                    } else {
-                        //No more orders left on page, mark page for deletion
-                        page_to_remove = Some(page_price.clone());
-                        break 'order_loop;
                     }
+                } else {
+                    //No more orders left on page, mark page for deletion
+                    page_to_remove = Some(*page_price);
+                    break 'order_loop;
                 }
-            } else {
-                //No pages left
-                break 'page_loop;
             }
-            //Delete marked page
-           delete_marked_page(page_to_remove, orderbook_maker);
+        } else {
+            //No pages left
+            break 'page_loop;
         }
-        // Delete (last) marked page
+        //Delete marked page
         delete_marked_page(page_to_remove, orderbook_maker);
+    }
+    // Delete (last) marked page
+    delete_marked_page(page_to_remove, orderbook_maker);
 
-        // If taker isn't fully absorbed, insert order
-        if order.unfilled > Decimal::zero() {
-            orderbook.insert_limit(order.clone(), side, *price);
+    result
+}
+
+impl OrderMatcher for OrderMatcherFifo {
+    fn match_limit(
+        &self,
+        orderbook: &mut Orderbook,
+        last_trade_id: &mut TradeId,
+        order: Order,
+        side: AskOrBid,
+        price: &Decimal,
+        time_in_force: TimeInForce,
+    ) -> Result<Option<Match>, ExchangeError> {
+        let amount = order.remaining();
+        orderbook.check_order_data(&order.id, Some(price), &amount)?;
+
+        // Kill the order up front if the book can't fill it completely
+        if time_in_force == TimeInForce::FillOrKill
+            && orderbook.get_fillable_amount(side, price, &order) < amount
+        {
+            return Ok(None);
         }
-        
-        //Match whether any orders have been matched at all
-        match order.unfilled == *amount {
-            true => None,
-            false => Some(Match {
-                taker: order.id,
-                makers,
-            }),
+
+        // The taker matches with its whole amount, hidden or not
+        let mut taker = order.taker();
+        let result = match_against_book(orderbook, last_trade_id, &mut taker, side, Some(price));
+
+        // If taker isn't fully absorbed, insert order unless it's immediate or cancel
+        if taker.unfilled > Decimal::zero() && time_in_force == TimeInForce::GoodTillCancel {
+            orderbook.insert_limit(order.resized(taker.unfilled), side, *price)?;
         }
+
+        //Match whether anything has been matched or prevented at all
+        Ok((!result.is_empty()).then_some(result))
     }
 
     fn match_market(
         &self,
         orderbook: &mut Orderbook,
-        order_id: &OrderId,
+        last_trade_id: &mut TradeId,
+        order: Order,
         side: AskOrBid,
-        amount: &Decimal,
-    ) -> Option<Match> {
-        match side {
-            AskOrBid::Ask => self.match_limit(orderbook, order_id, side, &Decimal::ZERO, amount),
-            AskOrBid::Bid => self.match_limit(orderbook, order_id, side, &Decimal::MAX, amount),
-        }
+    ) -> Result<Option<Match>, ExchangeError> {
+        orderbook.check_order_data(&order.id, None, &order.remaining())?;
+
+        // Market orders never rest on the book, any unfilled remainder is dropped
+        let mut taker = order.taker();
+        let result = match_against_book(orderbook, last_trade_id, &mut taker, side, None);
+
+        Ok((!result.is_empty()).then_some(result))
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
-    use crate::order_matcher::OrderMatcher;
+    use crate::error::ExchangeError;
+    use crate::order_matcher::{OrderMatcher, SelfTradePrevention, TimeInForce};
     use crate::order_matcher_fifo::OrderMatcherFifo;
-    use crate::orderbook::Orderbook;
-    use crate::symbol::AskOrBid::Ask;
+    use crate::orderbook::{Order, Orderbook};
     use crate::symbol::{AskOrBid, Symbol};
-    use log::info;
-    use rand::distributions::Distribution;
-    use rand::prelude::StdRng;
-    use rand::rngs::ThreadRng;
-    use rand::{thread_rng, Rng, RngCore, SeedableRng};
-    use rand_distr::Normal;
     use rust_decimal::prelude::ToPrimitive;
     use rust_decimal::Decimal;
-    use std::convert::TryInto;
-    use std::time::{Duration, Instant};
 
     #[test]
     fn test_match_limit_single_price_level() {
-        let mut orderbook = Orderbook::new(Symbol::Asset2);
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
         let matcher = OrderMatcherFifo::new();
+        let mut last_trade_id = 0;
 
         let first_maker_id = 0;
         let second_maker_id = 1;
@@ -177,15 +238,17 @@ mod tests {
             matcher
                 .match_limit(
                     &mut orderbook,
-                    &first_maker_id,
+                    &mut last_trade_id,
+                    Order::new(first_maker_id, first_maker_id, first_maker_amount),
                     AskOrBid::Ask,
                     &price,
-                    &first_maker_amount
+                    TimeInForce::GoodTillCancel
                 )
+                .unwrap()
                 .is_none(),
             true
         );
-        assert_eq!(orderbook.orders_ask.get(&price).is_some(), true);
+        assert_eq!(orderbook.orders_ask.contains_key(&price), true);
         assert_eq!(
             orderbook.orders_ask.get(&price).unwrap().amount,
             first_maker_amount
@@ -204,11 +267,13 @@ mod tests {
             matcher
                 .match_limit(
                     &mut orderbook,
-                    &second_maker_id,
+                    &mut last_trade_id,
+                    Order::new(second_maker_id, second_maker_id, Decimal::from(16)),
                     AskOrBid::Ask,
                     &price,
-                    &Decimal::from(16)
+                    TimeInForce::GoodTillCancel
                 )
+                .unwrap()
                 .is_none(),
             true
         );
@@ -218,19 +283,22 @@ mod tests {
         let result = matcher
             .match_limit(
                 &mut orderbook,
-                &taker_id,
+                &mut last_trade_id,
+                Order::new(taker_id, taker_id, Decimal::from(31)),
                 AskOrBid::Bid,
                 &price,
-                &Decimal::from(31),
+                TimeInForce::GoodTillCancel,
             )
+            .unwrap()
             .unwrap();
 
         //Check match result
         assert_eq!(result.taker, taker_id);
-        assert_eq!(result.makers.len(), 1);
-        let (id, filled) = result.makers.iter().next().unwrap();
-        assert_eq!(*id, first_maker_id);
-        assert_eq!(*filled, Decimal::from(31));
+        assert_eq!(result.trades.len(), 1);
+        let trade = result.trades.first().unwrap();
+        assert_eq!(trade.maker_id, first_maker_id);
+        assert_eq!(trade.amount, Decimal::from(31));
+        assert_eq!(trade.price, price);
         assert_eq!(orderbook.orders_index.get(&first_maker_id).is_some(), true); //Maker order hasn't been fully filled yet
 
         //Remaining amount on the page
@@ -244,22 +312,31 @@ mod tests {
         let result = matcher
             .match_limit(
                 &mut orderbook,
-                &taker_id,
+                &mut last_trade_id,
+                Order::new(taker_id, taker_id, Decimal::from(16)),
                 AskOrBid::Bid,
                 &price,
-                &Decimal::from(16),
+                TimeInForce::GoodTillCancel,
             )
+            .unwrap()
             .unwrap();
 
         assert_eq!(result.taker, taker_id);
-        assert_eq!(result.makers.len(), 2);
-        let mut iter = result.makers.iter();
-        let (id, filled) = iter.next().unwrap();
-        assert_eq!(*id, first_maker_id);
-        assert_eq!(*filled, Decimal::from(1));
-        let (id, filled) = iter.next().unwrap();
-        assert_eq!(*id, second_maker_id);
-        assert_eq!(*filled, Decimal::from(15));
+        assert_eq!(result.trades.len(), 2);
+        let mut iter = result.trades.iter();
+        let trade = iter.next().unwrap();
+        assert_eq!(trade.maker_id, first_maker_id);
+        assert_eq!(trade.amount, Decimal::from(1));
+        let trade = iter.next().unwrap();
+        assert_eq!(trade.maker_id, second_maker_id);
+        assert_eq!(trade.amount, Decimal::from(15));
+
+        //Trades carry the taker, aggressor side and ids from the global sequence
+        assert_eq!(trade.taker_id, taker_id);
+        assert_eq!(trade.aggressor, AskOrBid::Bid);
+        assert_eq!(trade.symbol, Symbol::new("ETH"));
+        assert_eq!(trade.id, 3);
+        assert_eq!(last_trade_id, 3);
         //First maker has been fully filled
         assert_eq!(orderbook.orders_index.get(&first_maker_id).is_some(), false);
 
@@ -274,15 +351,17 @@ mod tests {
         let _result = matcher
             .match_limit(
                 &mut orderbook,
-                &taker_id,
+                &mut last_trade_id,
+                Order::new(taker_id, taker_id, Decimal::from(11)),
                 AskOrBid::Bid,
                 &price,
-                &Decimal::from(11),
+                TimeInForce::GoodTillCancel,
             )
+            .unwrap()
             .unwrap();
 
         //Page gets removed after last order is filled
-        assert_eq!(orderbook.orders_ask.get(&price).is_none(), true);
+        assert_eq!(orderbook.orders_ask.contains_key(&price), false);
 
         //Check whether remaining order was written on the orderbook of taker
         let new_page = orderbook.orders_bid.get(&price).unwrap();
@@ -296,8 +375,9 @@ mod tests {
 
     #[test]
     fn test_match_limit_multi_fill_best_price() {
-        let mut orderbook = Orderbook::new(Symbol::Asset2);
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
         let matcher = OrderMatcherFifo::new();
+        let mut last_trade_id = 0;
 
         let first_maker_id = 0;
         let second_maker_id = 1;
@@ -310,41 +390,47 @@ mod tests {
         //Setup 2 orders at two price levels
         matcher.match_limit(
             &mut orderbook,
-            &first_maker_id,
+            &mut last_trade_id,
+            Order::new(first_maker_id, first_maker_id, first_maker_amount),
             AskOrBid::Ask,
             &price1,
-            &first_maker_amount,
-        );
+            TimeInForce::GoodTillCancel,
+        ).unwrap();
 
         matcher.match_limit(
             &mut orderbook,
-            &second_maker_id,
+            &mut last_trade_id,
+            Order::new(second_maker_id, second_maker_id, first_maker_amount),
             AskOrBid::Ask,
             &price2,
-            &first_maker_amount,
-        );
+            TimeInForce::GoodTillCancel,
+        ).unwrap();
 
         //Place limit at higher ask level, check if the lowest order gets hit
         let result = matcher
             .match_limit(
                 &mut orderbook,
-                &3,
+                &mut last_trade_id,
+                Order::new(3, 3, first_maker_amount),
                 AskOrBid::Bid,
                 &Decimal::ONE_THOUSAND,
-                &first_maker_amount,
+                TimeInForce::GoodTillCancel,
             )
+            .unwrap()
             .unwrap();
-        assert_eq!(result.makers.len(), 1);
+        assert_eq!(result.trades.len(), 1);
 
-        let mut iter = result.makers.iter();
-        let (id, _filled) = iter.next().unwrap();
-        assert_eq!(*id, first_maker_id);
+        let mut iter = result.trades.iter();
+        let trade = iter.next().unwrap();
+        assert_eq!(trade.maker_id, first_maker_id);
+        assert_eq!(trade.price, price1);
     }
 
     #[test]
     fn test_match_limit_multi_insert_remaining_amount() {
-        let mut orderbook = Orderbook::new(Symbol::Asset2);
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
         let matcher = OrderMatcherFifo::new();
+        let mut last_trade_id = 0;
 
         let first_maker_id = 0;
         let second_maker_id = 1;
@@ -357,32 +443,36 @@ mod tests {
         //Setup 2 orders at two price levels
         matcher.match_limit(
             &mut orderbook,
-            &first_maker_id,
+            &mut last_trade_id,
+            Order::new(first_maker_id, first_maker_id, first_maker_amount),
             AskOrBid::Ask,
             &price1,
-            &first_maker_amount,
-        );
+            TimeInForce::GoodTillCancel,
+        ).unwrap();
 
         matcher.match_limit(
             &mut orderbook,
-            &second_maker_id,
+            &mut last_trade_id,
+            Order::new(second_maker_id, second_maker_id, first_maker_amount),
             AskOrBid::Ask,
             &price2,
-            &first_maker_amount,
-        );
+            TimeInForce::GoodTillCancel,
+        ).unwrap();
 
         let price_limit = &(price2 - Decimal::ONE);
         let _result = matcher
             .match_limit(
                 &mut orderbook,
-                &3,
+                &mut last_trade_id,
+                Order::new(3, 3, Decimal::ONE_HUNDRED),
                 AskOrBid::Bid,
-                &price_limit,
-                &Decimal::ONE_HUNDRED,
+                price_limit,
+                TimeInForce::GoodTillCancel,
             )
+            .unwrap()
             .unwrap();
 
-        let page_written = orderbook.orders_bid.get(&price_limit);
+        let page_written = orderbook.orders_bid.get(price_limit);
         assert_eq!(page_written.is_some(), true);
         let page_written = page_written.unwrap();
         assert_eq!(
@@ -401,8 +491,9 @@ mod tests {
 
     #[test]
     fn test_match_limit_multi_maker_prices() {
-        let mut orderbook = Orderbook::new(Symbol::Asset2);
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
         let matcher = OrderMatcherFifo::new();
+        let mut last_trade_id = 0;
 
         let mut order_id = 0;
 
@@ -410,56 +501,62 @@ mod tests {
         //Setup 2 orders at two price levels
         matcher.match_limit(
             &mut orderbook,
-            &order_id,
+            &mut last_trade_id,
+            Order::new(order_id, order_id, first_maker_amount),
             AskOrBid::Bid,
             &Decimal::from(4233),
-            &first_maker_amount,
-        );
+            TimeInForce::GoodTillCancel,
+        ).unwrap();
 
         order_id += 1;
         matcher.match_limit(
             &mut orderbook,
-            &order_id,
+            &mut last_trade_id,
+            Order::new(order_id, order_id, first_maker_amount),
             AskOrBid::Bid,
             &Decimal::from(700),
-            &first_maker_amount,
-        );
+            TimeInForce::GoodTillCancel,
+        ).unwrap();
 
         order_id += 1;
         matcher.match_limit(
             &mut orderbook,
-            &order_id,
+            &mut last_trade_id,
+            Order::new(order_id, order_id, first_maker_amount),
             AskOrBid::Bid,
             &Decimal::from(700),
-            &first_maker_amount,
-        );
+            TimeInForce::GoodTillCancel,
+        ).unwrap();
 
         let price_no_touch = Decimal::from(678);
         //Insert an order that shouldn't get hit
         order_id += 1;
         matcher.match_limit(
             &mut orderbook,
-            &order_id,
+            &mut last_trade_id,
+            Order::new(order_id, order_id, first_maker_amount),
             AskOrBid::Bid,
             &price_no_touch,
-            &first_maker_amount,
-        );
+            TimeInForce::GoodTillCancel,
+        ).unwrap();
 
         //Execute order
         order_id += 1;
         let result = matcher
             .match_limit(
                 &mut orderbook,
-                &order_id,
+                &mut last_trade_id,
+                Order::new(order_id, order_id, Decimal::from(4) * first_maker_amount),
                 AskOrBid::Ask,
                 &Decimal::from(679),
-                &(Decimal::from(4) * first_maker_amount),
+                TimeInForce::GoodTillCancel,
             )
+            .unwrap()
             .unwrap();
 
         //One order remaining on bid
         assert_eq!(orderbook.orders_bid.len(), 1);
-        assert_eq!(orderbook.orders_bid.get(&price_no_touch).is_some(), true);
+        assert_eq!(orderbook.orders_bid.contains_key(&price_no_touch), true);
         assert_eq!(
             orderbook
                 .orders_bid
@@ -475,44 +572,586 @@ mod tests {
         );
 
         //Matched against three makers
-        assert_eq!(result.makers.len(), 3);
+        assert_eq!(result.trades.len(), 3);
         for i in 0..3 {
-            let (order_id, _price) = result.makers.get(i).unwrap();
-            assert_eq!(*order_id, i.to_u64().unwrap());
+            let trade = result.trades.get(i).unwrap();
+            assert_eq!(trade.maker_id, i.to_u64().unwrap());
         }
     }
 
     #[test]
     fn test_match_endless_loop() {
-        let mut orderbook = Orderbook::new(Symbol::Asset2);
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
         let matcher = OrderMatcherFifo::new();
+        let mut last_trade_id = 0;
 
-        let mut order_id = 6;
-        // matcher.match_limit(&mut orderbook, &0, AskOrBid::Bid, &Decimal::from(6), &Decimal::ONE);order_id += 1;
-        // matcher.match_limit(&mut orderbook, &1, AskOrBid::Ask, &Decimal::from(1), &Decimal::ONE);order_id += 1;
         matcher.match_limit(
             &mut orderbook,
-            &2,
+            &mut last_trade_id,
+            Order::new(2, 2, Decimal::ONE),
             AskOrBid::Bid,
             &Decimal::from(7),
-            &Decimal::ONE,
-        );
-        order_id += 1;
+            TimeInForce::GoodTillCancel,
+        ).unwrap();
         matcher.match_limit(
             &mut orderbook,
-            &3,
+            &mut last_trade_id,
+            Order::new(3, 3, Decimal::ONE),
             AskOrBid::Ask,
             &Decimal::from(7),
-            &Decimal::ONE,
-        );
-        order_id += 1;
+            TimeInForce::GoodTillCancel,
+        ).unwrap();
         matcher.match_limit(
             &mut orderbook,
-            &7,
+            &mut last_trade_id,
+            Order::new(7, 7, Decimal::ONE),
             AskOrBid::Ask,
             &Decimal::from(6),
-            &Decimal::ONE,
+            TimeInForce::GoodTillCancel,
+        ).unwrap();
+    }
+
+    #[test]
+    fn test_match_market_sweeps_pages() {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        let matcher = OrderMatcherFifo::new();
+        let mut last_trade_id = 0;
+
+        matcher.match_limit(
+            &mut orderbook,
+            &mut last_trade_id,
+            Order::new(0, 0, Decimal::from(10)),
+            AskOrBid::Ask,
+            &Decimal::from(100),
+            TimeInForce::GoodTillCancel,
+        ).unwrap();
+        matcher.match_limit(
+            &mut orderbook,
+            &mut last_trade_id,
+            Order::new(1, 1, Decimal::from(10)),
+            AskOrBid::Ask,
+            &Decimal::from(110),
+            TimeInForce::GoodTillCancel,
+        ).unwrap();
+
+        let result = matcher
+            .match_market(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(2, 2, Decimal::from(15)),
+                AskOrBid::Bid,
+            )
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(result.taker, 2);
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.filled(), Decimal::from(15));
+        //(10 * 100 + 5 * 110) / 15
+        assert_eq!(
+            result.average_price().unwrap(),
+            Decimal::from(1550) / Decimal::from(15)
         );
-        order_id += 1;
+
+        //First page consumed, second one partially
+        assert_eq!(orderbook.orders_ask.contains_key(&Decimal::from(100)), false);
+        assert_eq!(
+            orderbook.orders_ask.get(&Decimal::from(110)).unwrap().amount,
+            Decimal::from(5)
+        );
+    }
+
+    #[test]
+    fn test_match_market_remainder_cancelled() {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        let matcher = OrderMatcherFifo::new();
+        let mut last_trade_id = 0;
+
+        //Market order on an empty book doesn't match and doesn't rest
+        assert_eq!(
+            matcher
+                .match_market(
+                    &mut orderbook,
+                    &mut last_trade_id,
+                    Order::new(0, 0, Decimal::from(5)),
+                    AskOrBid::Ask,
+                )
+                .unwrap()
+                .is_none(),
+            true
+        );
+        assert_eq!(orderbook.orders_ask.is_empty(), true);
+        assert_eq!(orderbook.contains_order(&0), false);
+
+        matcher.match_limit(
+            &mut orderbook,
+            &mut last_trade_id,
+            Order::new(1, 1, Decimal::from(3)),
+            AskOrBid::Bid,
+            &Decimal::from(50),
+            TimeInForce::GoodTillCancel,
+        ).unwrap();
+
+        let result = matcher
+            .match_market(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(2, 2, Decimal::from(5)),
+                AskOrBid::Ask,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.filled(), Decimal::from(3));
+        assert_eq!(result.average_price().unwrap(), Decimal::from(50));
+
+        //Remainder must not be written onto the book
+        assert_eq!(orderbook.orders_bid.is_empty(), true);
+        assert_eq!(orderbook.orders_ask.is_empty(), true);
+        assert_eq!(orderbook.contains_order(&2), false);
+    }
+
+    #[test]
+    fn test_match_invalid_order_leaves_book_untouched() {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        let matcher = OrderMatcherFifo::new();
+        let mut last_trade_id = 0;
+
+        matcher
+            .match_limit(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(0, 0, Decimal::ONE),
+                AskOrBid::Ask,
+                &Decimal::from(10),
+                TimeInForce::GoodTillCancel,
+            )
+            .unwrap();
+
+        //Order id is already resting on the book
+        assert_eq!(
+            matcher
+                .match_limit(
+                    &mut orderbook,
+                    &mut last_trade_id,
+                    Order::new(0, 0, Decimal::ONE),
+                    AskOrBid::Bid,
+                    &Decimal::from(10),
+                    TimeInForce::GoodTillCancel,
+                )
+                .err(),
+            Some(ExchangeError::DuplicateOrderId(0))
+        );
+        assert_eq!(
+            matcher
+                .match_market(
+                    &mut orderbook,
+                    &mut last_trade_id,
+                    Order::new(1, 1, Decimal::ZERO),
+                    AskOrBid::Bid,
+                )
+                .err(),
+            Some(ExchangeError::InvalidQuantity(Decimal::ZERO))
+        );
+        assert_eq!(
+            matcher
+                .match_limit(
+                    &mut orderbook,
+                    &mut last_trade_id,
+                    Order::new(1, 1, Decimal::ONE),
+                    AskOrBid::Bid,
+                    &Decimal::from(-10),
+                    TimeInForce::GoodTillCancel,
+                )
+                .err(),
+            Some(ExchangeError::InvalidPrice(Decimal::from(-10)))
+        );
+
+        assert_eq!(last_trade_id, 0);
+        assert_eq!(orderbook.orders_ask.get(&Decimal::from(10)).unwrap().amount, Decimal::ONE);
+    }
+
+    #[test]
+    fn test_match_limit_immediate_or_cancel() {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        let matcher = OrderMatcherFifo::new();
+        let mut last_trade_id = 0;
+
+        //Nothing to match against, remainder is dropped
+        assert_eq!(
+            matcher
+                .match_limit(
+                    &mut orderbook,
+                    &mut last_trade_id,
+                    Order::new(0, 0, Decimal::from(5)),
+                    AskOrBid::Bid,
+                    &Decimal::from(100),
+                    TimeInForce::ImmediateOrCancel,
+                )
+                .unwrap()
+                .is_none(),
+            true
+        );
+        assert_eq!(orderbook.contains_order(&0), false);
+
+        matcher
+            .match_limit(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(1, 1, Decimal::from(3)),
+                AskOrBid::Ask,
+                &Decimal::from(100),
+                TimeInForce::GoodTillCancel,
+            )
+            .unwrap();
+
+        let result = matcher
+            .match_limit(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(2, 2, Decimal::from(5)),
+                AskOrBid::Bid,
+                &Decimal::from(100),
+                TimeInForce::ImmediateOrCancel,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.filled(), Decimal::from(3));
+
+        assert_eq!(orderbook.orders_ask.is_empty(), true);
+        assert_eq!(orderbook.orders_bid.is_empty(), true);
+        assert_eq!(orderbook.contains_order(&2), false);
+    }
+
+    #[test]
+    fn test_match_limit_fill_or_kill() {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        let matcher = OrderMatcherFifo::new();
+        let mut last_trade_id = 0;
+
+        matcher
+            .match_limit(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(0, 0, Decimal::from(3)),
+                AskOrBid::Ask,
+                &Decimal::from(100),
+                TimeInForce::GoodTillCancel,
+            )
+            .unwrap();
+        matcher
+            .match_limit(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(1, 1, Decimal::from(3)),
+                AskOrBid::Ask,
+                &Decimal::from(110),
+                TimeInForce::GoodTillCancel,
+            )
+            .unwrap();
+
+        //Only 3 are available within the limit, order is killed without touching the book
+        assert_eq!(
+            matcher
+                .match_limit(
+                    &mut orderbook,
+                    &mut last_trade_id,
+                    Order::new(2, 2, Decimal::from(4)),
+                    AskOrBid::Bid,
+                    &Decimal::from(105),
+                    TimeInForce::FillOrKill,
+                )
+                .unwrap()
+                .is_none(),
+            true
+        );
+        assert_eq!(last_trade_id, 0);
+        assert_eq!(orderbook.orders_ask.get(&Decimal::from(100)).unwrap().amount, Decimal::from(3));
+        assert_eq!(orderbook.contains_order(&2), false);
+
+        //Both pages together can fill the order
+        let result = matcher
+            .match_limit(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(3, 3, Decimal::from(4)),
+                AskOrBid::Bid,
+                &Decimal::from(110),
+                TimeInForce::FillOrKill,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.filled(), Decimal::from(4));
+        assert_eq!(orderbook.orders_ask.get(&Decimal::from(110)).unwrap().amount, Decimal::from(2));
+        assert_eq!(orderbook.contains_order(&3), false);
+    }
+
+    #[test]
+    fn test_match_iceberg_replenishes_and_loses_priority() {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        let matcher = OrderMatcherFifo::new();
+        let mut last_trade_id = 0;
+        let price = Decimal::from(100);
+
+        matcher
+            .match_limit(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::iceberg(0, 0, Decimal::from(12), Decimal::from(5)),
+                AskOrBid::Ask,
+                &price,
+                TimeInForce::GoodTillCancel,
+            )
+            .unwrap();
+        matcher
+            .match_limit(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(1, 1, Decimal::from(3)),
+                AskOrBid::Ask,
+                &price,
+                TimeInForce::GoodTillCancel,
+            )
+            .unwrap();
+        assert_eq!(orderbook.orders_ask.get(&price).unwrap().amount, Decimal::from(8));
+
+        //Consuming the first peak puts the iceberg behind order 1
+        let result = matcher
+            .match_market(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(2, 2, Decimal::from(7)),
+                AskOrBid::Bid,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.trades[0].maker_id, 0);
+        assert_eq!(result.trades[0].amount, Decimal::from(5));
+        assert_eq!(result.trades[1].maker_id, 1);
+        assert_eq!(result.trades[1].amount, Decimal::from(2));
+
+        let page = orderbook.orders_ask.get(&price).unwrap();
+        assert_eq!(page.orders.keys().copied().collect::<Vec<u64>>(), vec![1, 0]);
+        assert_eq!(page.amount, Decimal::from(6));
+        assert_eq!(page.hidden, Decimal::from(2));
+
+        //Remaining reserve can be taken in one go, the book is empty afterwards
+        let result = matcher
+            .match_market(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(3, 3, Decimal::from(8)),
+                AskOrBid::Bid,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.filled(), Decimal::from(8));
+        assert_eq!(orderbook.orders_ask.is_empty(), true);
+        assert_eq!(orderbook.contains_order(&0), false);
+    }
+
+    #[test]
+    fn test_match_iceberg_taker_rests_with_peak() {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        let matcher = OrderMatcherFifo::new();
+        let mut last_trade_id = 0;
+
+        matcher
+            .match_limit(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(0, 0, Decimal::from(4)),
+                AskOrBid::Ask,
+                &Decimal::from(100),
+                TimeInForce::GoodTillCancel,
+            )
+            .unwrap();
+
+        //Taker matches with its whole amount, not just the peak
+        let result = matcher
+            .match_limit(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::iceberg(1, 1, Decimal::from(20), Decimal::from(2)),
+                AskOrBid::Bid,
+                &Decimal::from(100),
+                TimeInForce::GoodTillCancel,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.filled(), Decimal::from(4));
+
+        let page = orderbook.orders_bid.get(&Decimal::from(100)).unwrap();
+        assert_eq!(page.amount, Decimal::from(2));
+        assert_eq!(page.hidden, Decimal::from(14));
+
+        //Fill or kill takes the hidden reserve into account
+        assert_eq!(
+            matcher
+                .match_limit(
+                    &mut orderbook,
+                    &mut last_trade_id,
+                    Order::new(2, 2, Decimal::from(16)),
+                    AskOrBid::Ask,
+                    &Decimal::from(100),
+                    TimeInForce::FillOrKill,
+                )
+                .unwrap()
+                .unwrap()
+                .filled(),
+            Decimal::from(16)
+        );
+        assert_eq!(orderbook.orders_bid.is_empty(), true);
+    }
+
+    fn new_self_trade_book(matcher: &OrderMatcherFifo) -> Orderbook {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        let mut last_trade_id = 0;
+        for (id, account) in [(0, 1), (1, 2)] {
+            matcher
+                .match_limit(
+                    &mut orderbook,
+                    &mut last_trade_id,
+                    Order::new(id, account, Decimal::from(5)),
+                    AskOrBid::Ask,
+                    &Decimal::from(100),
+                    TimeInForce::GoodTillCancel,
+                )
+                .unwrap();
+        }
+        orderbook
+    }
+
+    #[test]
+    fn test_self_trade_prevention_modes() {
+        let matcher = OrderMatcherFifo::new();
+        let cases = [
+            //Mode, trades (maker, amount), self-trade cancellations, resting bid
+            (SelfTradePrevention::CancelNewest, vec![], vec![(2, 8)], 0),
+            (SelfTradePrevention::CancelOldest, vec![(1, 5)], vec![(0, 5)], 3),
+            (SelfTradePrevention::CancelBoth, vec![], vec![(0, 5), (2, 8)], 0),
+            (SelfTradePrevention::DecrementAndCancel, vec![(1, 3)], vec![(0, 5), (2, 5)], 0),
+        ];
+
+        for (stp, trades, self_trades, resting) in cases {
+            let mut orderbook = new_self_trade_book(&matcher);
+            let mut last_trade_id = 0;
+
+            //Taker of account 1 reaches its own ask first
+            let result = matcher
+                .match_limit(
+                    &mut orderbook,
+                    &mut last_trade_id,
+                    Order::new(2, 1, Decimal::from(8)).with_stp(stp),
+                    AskOrBid::Bid,
+                    &Decimal::from(100),
+                    TimeInForce::GoodTillCancel,
+                )
+                .unwrap()
+                .unwrap();
+
+            let fills: Vec<(u64, i64)> = result
+                .trades
+                .iter()
+                .map(|trade| (trade.maker_id, trade.amount.to_i64().unwrap()))
+                .collect();
+            let cancelled: Vec<(u64, i64)> = result
+                .self_trades
+                .iter()
+                .map(|(order_id, amount)| (*order_id, amount.to_i64().unwrap()))
+                .collect();
+            assert_eq!(fills, trades, "{:?}", stp);
+            assert_eq!(cancelled, self_trades, "{:?}", stp);
+            assert_eq!(result.trades.iter().all(|trade| trade.maker_id != 0), true);
+            assert_eq!(
+                orderbook
+                    .orders_bid
+                    .get(&Decimal::from(100))
+                    .map(|page| page.amount.to_i64().unwrap())
+                    .unwrap_or_default(),
+                resting,
+                "{:?}",
+                stp
+            );
+            assert_eq!(
+                orderbook.contains_order(&0),
+                stp == SelfTradePrevention::CancelNewest,
+                "{:?}",
+                stp
+            );
+        }
+    }
+
+    #[test]
+    fn test_self_trade_prevention_fill_or_kill() {
+        let matcher = OrderMatcherFifo::new();
+        let mut orderbook = new_self_trade_book(&matcher);
+        let mut last_trade_id = 0;
+
+        //Own order in the way would cut the taker short, it's killed up front
+        assert_eq!(
+            matcher
+                .match_limit(
+                    &mut orderbook,
+                    &mut last_trade_id,
+                    Order::new(2, 1, Decimal::from(5)).with_stp(SelfTradePrevention::CancelNewest),
+                    AskOrBid::Bid,
+                    &Decimal::from(100),
+                    TimeInForce::FillOrKill,
+                )
+                .unwrap()
+                .is_none(),
+            true
+        );
+        assert_eq!(orderbook.orders_ask.get(&Decimal::from(100)).unwrap().amount, Decimal::from(10));
+
+        //Own order gets cancelled, the other account's order fills the taker
+        let result = matcher
+            .match_limit(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(3, 1, Decimal::from(5)).with_stp(SelfTradePrevention::CancelOldest),
+                AskOrBid::Bid,
+                &Decimal::from(100),
+                TimeInForce::FillOrKill,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.filled(), Decimal::from(5));
+        assert_eq!(orderbook.orders_ask.is_empty(), true);
+    }
+
+    #[test]
+    fn test_fill_or_kill_own_order_behind_fill() {
+        let matcher = OrderMatcherFifo::new();
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        let mut last_trade_id = 0;
+        for (id, account, price) in [(0, 2, 100), (1, 1, 90)] {
+            matcher
+                .match_limit(
+                    &mut orderbook,
+                    &mut last_trade_id,
+                    Order::new(id, account, Decimal::from(5)),
+                    AskOrBid::Bid,
+                    &Decimal::from(price),
+                    TimeInForce::GoodTillCancel,
+                )
+                .unwrap();
+        }
+
+        //Own bid at 90 is only reached after the taker has been filled at 100
+        let result = matcher
+            .match_limit(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(2, 1, Decimal::from(5)).with_stp(SelfTradePrevention::CancelNewest),
+                AskOrBid::Ask,
+                &Decimal::from(90),
+                TimeInForce::FillOrKill,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.filled(), Decimal::from(5));
+        assert_eq!(orderbook.get_best_bid(), Some(Decimal::from(90)));
     }
 }
@@ -0,0 +1,198 @@
+use crate::error::ExchangeError;
+use crate::order_matcher::{
+    match_limit_by_allocation, match_market_by_allocation, round_down_to_lot, Match, OrderMatcher,
+    TimeInForce,
+};
+use crate::orderbook::{Order, Orderbook, OrderbookPage};
+use crate::symbol::AskOrBid;
+use crate::{OrderId, TradeId};
+
+use rust_decimal::prelude::Zero;
+use rust_decimal::Decimal;
+
+/// Price-time matcher granting the top order of each price level a guaranteed
+/// share of every incoming order, similar to lead market maker allocations.
+///
+/// The order at the front of a page's queue is allocated ``priority_pct`` percent
+/// of the incoming amount first, rounded down to the lot size. The rest is filled
+/// FIFO against the other orders of the page, the top order only gets more in
+/// case they can't absorb it.
+pub struct OrderMatcherFifoTopOrder {
+    /// Quantity increment the top order's share is rounded down to
+    lot_size: Decimal,
+
+    /// Share of the incoming amount allocated to the top order, in percent
+    priority_pct: Decimal,
+}
+
+impl OrderMatcherFifoTopOrder {
+    pub fn new(lot_size: Decimal, priority_pct: Decimal) -> OrderMatcherFifoTopOrder {
+        OrderMatcherFifoTopOrder {
+            lot_size,
+            priority_pct,
+        }
+    }
+
+    /// Splits ``amount`` between the orders of ``page``, see ``OrderMatcherFifoTopOrder``
+    fn allocate(&self, page: &OrderbookPage, amount: &Decimal) -> Vec<(OrderId, Decimal)> {
+        let mut orders = page.orders.values();
+        let top_order = match orders.next() {
+            Some(top_order) => top_order,
+            None => return Vec::new(),
+        };
+
+        let top_share = round_down_to_lot(amount * self.priority_pct / Decimal::ONE_HUNDRED, &self.lot_size)
+            .min(top_order.unfilled);
+        let mut allocations = vec![(top_order.id, top_share)];
+        let mut leftover = amount - top_share;
+
+        for order in orders {
+            if leftover.is_zero() {
+                break;
+            }
+            let fill = leftover.min(order.unfilled);
+            allocations.push((order.id, fill));
+            leftover -= fill;
+        }
+
+        // Whatever the rest of the queue can't absorb goes to the top order as well
+        allocations[0].1 += leftover.min(top_order.unfilled - top_share);
+
+        allocations.retain(|(_, fill)| *fill > Decimal::zero());
+        allocations
+    }
+}
+
+impl OrderMatcher for OrderMatcherFifoTopOrder {
+    fn match_limit(
+        &self,
+        orderbook: &mut Orderbook,
+        last_trade_id: &mut TradeId,
+        order: Order,
+        side: AskOrBid,
+        price: &Decimal,
+        time_in_force: TimeInForce,
+    ) -> Result<Option<Match>, ExchangeError> {
+        match_limit_by_allocation(orderbook, last_trade_id, order, side, price, time_in_force, |page, amount| {
+            self.allocate(page, amount)
+        })
+    }
+
+    fn match_market(
+        &self,
+        orderbook: &mut Orderbook,
+        last_trade_id: &mut TradeId,
+        order: Order,
+        side: AskOrBid,
+    ) -> Result<Option<Match>, ExchangeError> {
+        match_market_by_allocation(orderbook, last_trade_id, order, side, |page, amount| {
+            self.allocate(page, amount)
+        })
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use crate::order_matcher::{OrderMatcher, SelfTradePrevention, TimeInForce};
+    use crate::order_matcher_fifo_top_order::OrderMatcherFifoTopOrder;
+    use crate::orderbook::{Order, Orderbook};
+    use crate::symbol::{AskOrBid, Symbol};
+    use rust_decimal::Decimal;
+
+    fn new_book(matcher: &OrderMatcherFifoTopOrder, amounts: &[i64]) -> Orderbook {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        let mut last_trade_id = 0;
+        for (i, amount) in amounts.iter().enumerate() {
+            matcher
+                .match_limit(
+                    &mut orderbook,
+                    &mut last_trade_id,
+                    Order::new(i as u64, i as u64, Decimal::from(*amount)),
+                    AskOrBid::Ask,
+                    &Decimal::from(100),
+                    TimeInForce::GoodTillCancel,
+                )
+                .unwrap();
+        }
+        orderbook
+    }
+
+    #[test]
+    fn test_top_order_gets_priority_share() {
+        let matcher = OrderMatcherFifoTopOrder::new(Decimal::ONE, Decimal::from(40));
+        let mut orderbook = new_book(&matcher, &[10, 10, 10]);
+        let mut last_trade_id = 0;
+
+        //Top order gets 40% of 15 rounded down to the lot, the rest goes FIFO
+        let result = matcher
+            .match_market(&mut orderbook, &mut last_trade_id, Order::new(3, 3, Decimal::from(15)), AskOrBid::Bid)
+            .unwrap()
+            .unwrap();
+        let fills: Vec<(u64, Decimal)> = result.trades.iter().map(|trade| (trade.maker_id, trade.amount)).collect();
+        assert_eq!(fills, vec![(0, Decimal::from(6)), (1, Decimal::from(9))]);
+
+        let page = orderbook.orders_ask.get(&Decimal::from(100)).unwrap();
+        assert_eq!(page.get(&0).unwrap().unfilled, Decimal::from(4));
+        assert_eq!(page.get(&1).unwrap().unfilled, Decimal::ONE);
+        assert_eq!(page.amount, Decimal::from(15));
+    }
+
+    #[test]
+    fn test_top_order_absorbs_leftover() {
+        let matcher = OrderMatcherFifoTopOrder::new(Decimal::ONE, Decimal::from(50));
+        let mut orderbook = new_book(&matcher, &[10, 2]);
+        let mut last_trade_id = 0;
+
+        //Second order can only take 2 of the 5 not allocated to the top order
+        let result = matcher
+            .match_market(&mut orderbook, &mut last_trade_id, Order::new(2, 2, Decimal::from(10)), AskOrBid::Bid)
+            .unwrap()
+            .unwrap();
+        let fills: Vec<(u64, Decimal)> = result.trades.iter().map(|trade| (trade.maker_id, trade.amount)).collect();
+        assert_eq!(fills, vec![(0, Decimal::from(8)), (1, Decimal::from(2))]);
+        assert_eq!(orderbook.contains_order(&1), false);
+        assert_eq!(orderbook.orders_ask.get(&Decimal::from(100)).unwrap().amount, Decimal::from(2));
+
+        //Order 0 is the only order left and takes everything
+        let result = matcher
+            .match_market(&mut orderbook, &mut last_trade_id, Order::new(3, 3, Decimal::from(5)), AskOrBid::Bid)
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.filled(), Decimal::from(2));
+        assert_eq!(orderbook.orders_ask.is_empty(), true);
+    }
+
+    #[test]
+    fn test_top_order_self_trade_clears_page() {
+        let matcher = OrderMatcherFifoTopOrder::new(Decimal::ONE, Decimal::from(40));
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        let mut last_trade_id = 0;
+
+        orderbook
+            .insert_limit(Order::new(0, 7, Decimal::from(3)), AskOrBid::Ask, Decimal::from(100))
+            .unwrap();
+        orderbook
+            .insert_limit(Order::new(1, 7, Decimal::from(2)), AskOrBid::Ask, Decimal::from(100))
+            .unwrap();
+        orderbook
+            .insert_limit(Order::new(2, 8, Decimal::from(10)), AskOrBid::Ask, Decimal::from(101))
+            .unwrap();
+
+        //Both own orders of the best page are cancelled, the taker fills on the next page
+        let result = matcher
+            .match_market(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(3, 7, Decimal::from(4)).with_stp(SelfTradePrevention::CancelOldest),
+                AskOrBid::Bid,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.self_trades, vec![(0, Decimal::from(3)), (1, Decimal::from(2))]);
+        let fills: Vec<(u64, Decimal)> = result.trades.iter().map(|trade| (trade.maker_id, trade.amount)).collect();
+        assert_eq!(fills, vec![(2, Decimal::from(4))]);
+        assert_eq!(orderbook.orders_ask.contains_key(&Decimal::from(100)), false);
+        assert_eq!(orderbook.orders_ask.get(&Decimal::from(101)).unwrap().amount, Decimal::from(6));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::error::ExchangeError;
+use crate::order_matcher::{
+    match_limit_by_allocation, match_market_by_allocation, round_down_to_lot, Match, OrderMatcher,
+    TimeInForce,
+};
+use crate::orderbook::{Order, Orderbook, OrderbookPage};
+use crate::symbol::AskOrBid;
+use crate::{OrderId, TradeId};
+
+use rust_decimal::prelude::Zero;
+use rust_decimal::Decimal;
+
+/// Order matcher allocating fills within a price level proportionally to the
+/// displayed size of the resting orders.
+///
+/// Pages are still taken in price priority. Whenever a taker can't consume a
+/// whole page, every maker gets its pro-rata share rounded down to the lot size.
+/// Shares below ``min_allocation`` are dropped, whatever is left over after
+/// rounding is handed out in FIFO order.
+pub struct OrderMatcherProRata {
+    /// Quantity increment fills are rounded down to
+    lot_size: Decimal,
+
+    /// Smallest pro-rata share a maker is allocated, smaller shares are dropped
+    /// and handed out as leftover instead
+    min_allocation: Decimal,
+}
+
+impl OrderMatcherProRata {
+    pub fn new(lot_size: Decimal, min_allocation: Decimal) -> OrderMatcherProRata {
+        OrderMatcherProRata {
+            lot_size,
+            min_allocation,
+        }
+    }
+
+    /// Splits ``amount`` between the orders of ``page``, see ``OrderMatcherProRata``
+    fn allocate(&self, page: &OrderbookPage, amount: &Decimal) -> Vec<(OrderId, Decimal)> {
+        // Taker consumes everything that is displayed
+        if *amount >= page.amount {
+            return page
+                .orders
+                .values()
+                .filter(|order| order.unfilled > Decimal::zero())
+                .map(|order| (order.id, order.unfilled))
+                .collect();
+        }
+
+        let mut allocations: Vec<(OrderId, Decimal)> = page
+            .orders
+            .values()
+            .map(|order| {
+                let share = round_down_to_lot(amount * order.unfilled / page.amount, &self.lot_size);
+                match share < self.min_allocation {
+                    true => (order.id, Decimal::ZERO),
+                    false => (order.id, share),
+                }
+            })
+            .collect();
+
+        // Hand out the leftover in time priority
+        let mut leftover = amount - allocations.iter().map(|(_, share)| *share).sum::<Decimal>();
+        for (order_id, share) in allocations.iter_mut() {
+            if leftover.is_zero() {
+                break;
+            }
+            if let Some(order) = page.get(order_id) {
+                let extra = leftover.min(order.unfilled - *share);
+                *share += extra;
+                leftover -= extra;
+            }
+        }
+
+        allocations.retain(|(_, share)| *share > Decimal::zero());
+        allocations
+    }
+}
+
+impl OrderMatcher for OrderMatcherProRata {
+    fn match_limit(
+        &self,
+        orderbook: &mut Orderbook,
+        last_trade_id: &mut TradeId,
+        order: Order,
+        side: AskOrBid,
+        price: &Decimal,
+        time_in_force: TimeInForce,
+    ) -> Result<Option<Match>, ExchangeError> {
+        match_limit_by_allocation(orderbook, last_trade_id, order, side, price, time_in_force, |page, amount| {
+            self.allocate(page, amount)
+        })
+    }
+
+    fn match_market(
+        &self,
+        orderbook: &mut Orderbook,
+        last_trade_id: &mut TradeId,
+        order: Order,
+        side: AskOrBid,
+    ) -> Result<Option<Match>, ExchangeError> {
+        match_market_by_allocation(orderbook, last_trade_id, order, side, |page, amount| {
+            self.allocate(page, amount)
+        })
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use crate::order_matcher::{OrderMatcher, SelfTradePrevention, TimeInForce};
+    use crate::order_matcher_pro_rata::OrderMatcherProRata;
+    use crate::orderbook::{Order, Orderbook};
+    use crate::symbol::{AskOrBid, Symbol};
+    use rust_decimal::Decimal;
+
+    fn insert_asks(orderbook: &mut Orderbook, matcher: &OrderMatcherProRata, price: i64, amounts: &[i64]) {
+        let mut last_trade_id = 0;
+        let first_id = orderbook.orders_index.len() as u64;
+        for (i, amount) in amounts.iter().enumerate() {
+            let id = first_id + i as u64;
+            matcher
+                .match_limit(
+                    orderbook,
+                    &mut last_trade_id,
+                    Order::new(id, id, Decimal::from(*amount)),
+                    AskOrBid::Ask,
+                    &Decimal::from(price),
+                    TimeInForce::GoodTillCancel,
+                )
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_pro_rata_allocation() {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        let matcher = OrderMatcherProRata::new(Decimal::ONE, Decimal::ZERO);
+        let mut last_trade_id = 0;
+
+        insert_asks(&mut orderbook, &matcher, 100, &[10, 30, 60]);
+
+        let result = matcher
+            .match_market(&mut orderbook, &mut last_trade_id, Order::new(3, 3, Decimal::from(50)), AskOrBid::Bid)
+            .unwrap()
+            .unwrap();
+
+        let fills: Vec<(u64, Decimal)> = result.trades.iter().map(|trade| (trade.maker_id, trade.amount)).collect();
+        assert_eq!(
+            fills,
+            vec![(0, Decimal::from(5)), (1, Decimal::from(15)), (2, Decimal::from(30))]
+        );
+        assert_eq!(orderbook.orders_ask.get(&Decimal::from(100)).unwrap().amount, Decimal::from(50));
+    }
+
+    #[test]
+    fn test_pro_rata_leftover_fifo_and_min_allocation() {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        let matcher = OrderMatcherProRata::new(Decimal::ONE, Decimal::from(2));
+        let mut last_trade_id = 0;
+
+        insert_asks(&mut orderbook, &matcher, 100, &[3, 3, 14]);
+
+        //Shares of 1.05, 1.05 and 4.9 round down to 1, 1 and 4, the first two fall
+        //below the minimum allocation. Leftover of 3 goes to the orders in FIFO order
+        let result = matcher
+            .match_market(&mut orderbook, &mut last_trade_id, Order::new(3, 3, Decimal::from(7)), AskOrBid::Bid)
+            .unwrap()
+            .unwrap();
+
+        let fills: Vec<(u64, Decimal)> = result.trades.iter().map(|trade| (trade.maker_id, trade.amount)).collect();
+        assert_eq!(fills, vec![(0, Decimal::from(3)), (2, Decimal::from(4))]);
+        assert_eq!(orderbook.contains_order(&0), false);
+        assert_eq!(orderbook.orders_ask.get(&Decimal::from(100)).unwrap().amount, Decimal::from(13));
+    }
+
+    #[test]
+    fn test_pro_rata_sweeps_pages() {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        let matcher = OrderMatcherProRata::new(Decimal::ONE, Decimal::ZERO);
+        let mut last_trade_id = 0;
+
+        insert_asks(&mut orderbook, &matcher, 100, &[2, 2]);
+        insert_asks(&mut orderbook, &matcher, 101, &[5, 5]);
+
+        //First page is consumed completely, the rest is split on the second one
+        let result = matcher
+            .match_limit(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(4, 4, Decimal::from(8)),
+                AskOrBid::Bid,
+                &Decimal::from(101),
+                TimeInForce::GoodTillCancel,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.filled(), Decimal::from(8));
+        assert_eq!(result.trades.len(), 4);
+        assert_eq!(orderbook.orders_ask.contains_key(&Decimal::from(100)), false);
+        assert_eq!(orderbook.orders_ask.get(&Decimal::from(101)).unwrap().amount, Decimal::from(6));
+
+        //Limit below the remaining page rests without matching
+        assert_eq!(
+            matcher
+                .match_limit(
+                    &mut orderbook,
+                    &mut last_trade_id,
+                    Order::new(5, 5, Decimal::from(8)),
+                    AskOrBid::Bid,
+                    &Decimal::from(100),
+                    TimeInForce::GoodTillCancel,
+                )
+                .unwrap()
+                .is_none(),
+            true
+        );
+        assert_eq!(orderbook.get_best_bid(), Some(Decimal::from(100)));
+    }
+
+    #[test]
+    fn test_pro_rata_self_trade_prevention() {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        let matcher = OrderMatcherProRata::new(Decimal::ONE, Decimal::ZERO);
+        let mut last_trade_id = 0;
+
+        orderbook
+            .insert_limit(
+                Order::new(0, 7, Decimal::from(10)),
+                AskOrBid::Ask,
+                Decimal::from(100),
+            )
+            .unwrap();
+        insert_asks(&mut orderbook, &matcher, 100, &[30, 60]);
+
+        //Own order is cancelled before the page is split between the others
+        let result = matcher
+            .match_market(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(3, 7, Decimal::from(45)).with_stp(SelfTradePrevention::CancelOldest),
+                AskOrBid::Bid,
+            )
+            .unwrap()
+            .unwrap();
+
+        let fills: Vec<(u64, Decimal)> = result.trades.iter().map(|trade| (trade.maker_id, trade.amount)).collect();
+        assert_eq!(fills, vec![(1, Decimal::from(15)), (2, Decimal::from(30))]);
+        assert_eq!(result.self_trades, vec![(0, Decimal::from(10))]);
+        assert_eq!(orderbook.contains_order(&0), false);
+        assert_eq!(orderbook.orders_ask.get(&Decimal::from(100)).unwrap().amount, Decimal::from(45));
+    }
+
+    #[test]
+    fn test_pro_rata_self_trade_clears_page() {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        let matcher = OrderMatcherProRata::new(Decimal::ONE, Decimal::ZERO);
+        let mut last_trade_id = 0;
+
+        orderbook
+            .insert_limit(Order::new(0, 7, Decimal::from(5)), AskOrBid::Ask, Decimal::from(100))
+            .unwrap();
+        orderbook
+            .insert_limit(Order::new(1, 8, Decimal::from(5)), AskOrBid::Ask, Decimal::from(101))
+            .unwrap();
+
+        //Cancelling the only order of the best page moves on to the next page
+        let result = matcher
+            .match_market(
+                &mut orderbook,
+                &mut last_trade_id,
+                Order::new(2, 7, Decimal::from(5)).with_stp(SelfTradePrevention::CancelOldest),
+                AskOrBid::Bid,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.self_trades, vec![(0, Decimal::from(5))]);
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.filled(), Decimal::from(5));
+        assert_eq!(orderbook.orders_ask.is_empty(), true);
+    }
+}
@@ -1,33 +1,47 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
 
 use json::{object, JsonValue};
 use log::info;
 use rust_decimal::prelude::Zero;
 use rust_decimal::Decimal;
 
-use crate::order_matcher::OrderMatcher;
+use crate::error::ExchangeError;
 use crate::symbol::{AskOrBid, Symbol};
-use crate::OrderId;
+use crate::order_matcher::{PostOnly, SelfTradePrevention};
+use crate::snapshot::{invalid_snapshot, Snapshot, SnapshotReader, SnapshotWriter};
+use crate::{AccountId, OrderId};
 
-use crate::order_matcher_fifo::OrderMatcherFifo;
 use core::fmt;
 use linked_hash_map::LinkedHashMap;
 
-//TODO: Implement InsertLimitResult as Result<>?
-/// Different result states a limit order execution can have
+/// Different result states a successful limit order execution can have
 #[derive(PartialEq, Debug)]
 pub enum InsertLimitResult {
     Success(OrderId),
     PartiallyFilled(OrderId, Decimal),
     FullyFilled,
-    OrderDataInvalid,
+    /// Immediate or cancel order got partially filled.
+    /// Order id, filled, cancelled remainder
+    PartiallyFilledCancelled(OrderId, Decimal, Decimal),
+    /// Immediate or cancel order didn't match at all.
+    /// Order id, cancelled amount
+    Cancelled(OrderId, Decimal),
+    /// Fill or kill order couldn't be filled completely and never touched the book
+    Killed(OrderId),
 }
 
-/// Different result states a cancel order execution can have
+/// Different result states a market order execution can have
+///
+/// Market orders never rest on the book, whatever can't be filled is cancelled.
 #[derive(PartialEq, Debug)]
-pub enum CancelLimitResult {
-    Success,
-    OrderIdNotFound,
+pub enum InsertMarketResult {
+    /// Order id, filled, average price
+    FullyFilled(OrderId, Decimal, Decimal),
+    /// Order id, filled, average price, cancelled remainder
+    PartiallyFilled(OrderId, Decimal, Decimal, Decimal),
+    /// Order id, cancelled remainder
+    NoLiquidity(OrderId, Decimal),
 }
 
 /// Struct containing individual orders for a given discrete price level
@@ -37,7 +51,12 @@ pub struct OrderbookPage {
     pub orders: LinkedHashMap<OrderId, Order>,
 
     /// Cumulative value of orders sitting at the Page's price level
+    ///
+    /// Only the displayed peak of iceberg orders counts toward it.
     pub amount: Decimal,
+
+    /// Cumulative hidden reserve of iceberg orders sitting at the Page's price level
+    pub hidden: Decimal,
 }
 
 /// The orderbook. Contains orders for bid and ask side, as well as the order matcher
@@ -54,16 +73,140 @@ pub struct Orderbook {
     /// BTree of pages for the bid side
     pub orders_bid: BTreeMap<Decimal, OrderbookPage>,
 
-    /// Index for quickly looking up on which price level an order is sitting at
-    /// Used for efficiently resolving order book pages from order ids
-    pub orders_index: HashMap<OrderId, Decimal>,
+    /// Index for quickly looking up on which side and price level an order is sitting at
+    /// Used for efficiently resolving order book pages from order ids, even while
+    /// the book is crossed during an auction
+    pub orders_index: OrdersIndex,
+
+    /// Price of the most recent trade on the book, None if nothing traded yet
+    pub last_trade_price: Option<Decimal>,
+}
+
+/// Index of the orders resting on a book by id, along with the orders each
+/// account has resting on it.
+///
+/// Both are updated together, so the orders of an account can be listed without
+/// walking the book.
+#[derive(Default, Debug)]
+pub struct OrdersIndex {
+    /// Side and price level of each order
+    locations: HashMap<OrderId, (AskOrBid, Decimal)>,
+
+    /// Account of each order
+    owners: HashMap<OrderId, AccountId>,
+
+    /// Orders of each account in order of their ids
+    account_orders: HashMap<AccountId, BTreeSet<OrderId>>,
 }
 
 /// Struct holding details of an order inside the orderbook
 #[derive(Clone, PartialEq, Debug)]
 pub struct Order {
     pub id: OrderId,
+
+    /// Displayed amount that hasn't been filled yet
     pub unfilled: Decimal,
+
+    /// Hidden reserve of iceberg orders, zero for regular orders
+    pub hidden: Decimal,
+
+    /// Amount an iceberg order displays at once, zero for regular orders
+    pub peak: Decimal,
+
+    /// Account the order belongs to
+    pub account: AccountId,
+
+    /// What happens once the order would trade against an order of its own account
+    pub stp: SelfTradePrevention,
+
+    /// Post-only mode the order was placed with, re-applied when it is amended
+    pub post_only: Option<PostOnly>,
+}
+
+impl Order {
+    pub fn new(id: OrderId, account: AccountId, amount: Decimal) -> Order {
+        Order {
+            id,
+            unfilled: amount,
+            hidden: Decimal::ZERO,
+            peak: Decimal::ZERO,
+            account,
+            stp: SelfTradePrevention::default(),
+            post_only: None,
+        }
+    }
+
+    /// Creates an iceberg order that displays at most ``peak`` of ``amount``, the
+    /// rest is held back as hidden reserve
+    pub fn iceberg(id: OrderId, account: AccountId, amount: Decimal, peak: Decimal) -> Order {
+        let unfilled = amount.min(peak);
+        Order {
+            id,
+            unfilled,
+            hidden: amount - unfilled,
+            peak,
+            account,
+            stp: SelfTradePrevention::default(),
+            post_only: None,
+        }
+    }
+
+    /// Uses ``stp`` to prevent self-trades of the order
+    pub fn with_stp(mut self, stp: SelfTradePrevention) -> Order {
+        self.stp = stp;
+        self
+    }
+
+    /// Marks the order as post-only, amendments must not make it take liquidity
+    pub fn with_post_only(mut self, post_only: PostOnly) -> Order {
+        self.post_only = Some(post_only);
+        self
+    }
+
+    /// Copy of the order with ``amount`` left, iceberg orders keep their peak
+    pub fn resized(&self, amount: Decimal) -> Order {
+        let order = match self.is_iceberg() {
+            true => Order::iceberg(self.id, self.account, amount, self.peak),
+            false => Order::new(self.id, self.account, amount),
+        };
+        Order {
+            stp: self.stp,
+            post_only: self.post_only,
+            ..order
+        }
+    }
+
+    /// Copy of the order to be matched as taker, takers don't hide anything and
+    /// match with their whole remaining amount
+    pub fn taker(&self) -> Order {
+        Order {
+            stp: self.stp,
+            ..Order::new(self.id, self.account, self.remaining())
+        }
+    }
+
+    /// Determins whether trading against ``maker`` would be a self-trade
+    pub fn is_self_trade(&self, maker: &Order) -> bool {
+        self.account == maker.account
+    }
+
+    pub fn is_iceberg(&self) -> bool {
+        self.peak > Decimal::zero()
+    }
+
+    /// Total amount that hasn't been filled yet, displayed and hidden
+    pub fn remaining(&self) -> Decimal {
+        self.unfilled + self.hidden
+    }
+
+    /// Displays the next peak of an iceberg order from its hidden reserve,
+    /// returns the amount that has been moved out of the reserve
+    pub fn replenish(&mut self) -> Decimal {
+        let refill = self.hidden.min(self.peak - self.unfilled);
+        self.hidden -= refill;
+        self.unfilled += refill;
+        refill
+    }
 }
 
 impl OrderbookPage {
@@ -72,8 +215,13 @@ impl OrderbookPage {
     fn new(order: &Order) -> OrderbookPage {
         let mut orders = LinkedHashMap::<OrderId, Order>::new();
         let amount = order.unfilled;
+        let hidden = order.hidden;
         orders.insert(order.id, order.clone());
-        OrderbookPage { orders, amount }
+        OrderbookPage {
+            orders,
+            amount,
+            hidden,
+        }
     }
 
     /// Removes a order with a given id
@@ -81,13 +229,14 @@ impl OrderbookPage {
     fn remove(&mut self, order_id: &OrderId) -> Option<Order> {
         if let Some(removed) = self.orders.remove(order_id) {
             self.amount -= removed.unfilled;
+            self.hidden -= removed.hidden;
             return Some(removed);
         }
         None
     }
 
     /// Gets an order by id
-    fn get(&self, order_id: &OrderId) -> Option<&Order> {
+    pub fn get(&self, order_id: &OrderId) -> Option<&Order> {
         self.orders.get(order_id)
     }
 
@@ -95,6 +244,112 @@ impl OrderbookPage {
     fn insert(&mut self, order: &Order) {
         self.orders.insert(order.id, order.clone());
         self.amount += order.unfilled;
+        self.hidden += order.hidden;
+    }
+
+    /// Decrements the remaining amount of an order without trading it, the hidden
+    /// reserve of iceberg orders is decremented first.
+    ///
+    /// Returns whether nothing is left of the order and it has been removed from the page
+    pub(crate) fn decrement(&mut self, order_id: &OrderId, amount: &Decimal) -> bool {
+        let order = match self.orders.get_mut(order_id) {
+            Some(order) => order,
+            None => return false,
+        };
+        let from_hidden = (*amount).min(order.hidden);
+        order.hidden -= from_hidden;
+        self.hidden -= from_hidden;
+        order.unfilled -= amount - from_hidden;
+        self.amount -= amount - from_hidden;
+
+        if order.remaining() > Decimal::zero() {
+            return false;
+        }
+        self.orders.remove(order_id);
+        true
+    }
+
+    /// Fills ``amount`` of an order's displayed amount. Iceberg orders running out
+    /// of displayed amount are replenished from their reserve and moved to the back
+    /// of the queue, losing their time priority.
+    ///
+    /// Returns whether the order got fully filled and has been removed from the page
+    pub(crate) fn fill(&mut self, order_id: &OrderId, amount: &Decimal) -> bool {
+        let order = match self.orders.get_mut(order_id) {
+            Some(order) => order,
+            None => return false,
+        };
+        order.unfilled -= amount;
+        self.amount -= amount;
+
+        if order.unfilled > Decimal::zero() {
+            return false;
+        }
+        if order.hidden > Decimal::zero() {
+            let refill = order.replenish();
+            self.hidden -= refill;
+            self.amount += refill;
+            if let Some(order) = self.orders.remove(order_id) {
+                self.orders.insert(*order_id, order);
+            }
+            return false;
+        }
+        self.orders.remove(order_id);
+        true
+    }
+}
+
+impl OrdersIndex {
+    pub fn get(&self, order_id: &OrderId) -> Option<&(AskOrBid, Decimal)> {
+        self.locations.get(order_id)
+    }
+
+    pub fn contains_key(&self, order_id: &OrderId) -> bool {
+        self.locations.contains_key(order_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    /// Indexes an order at ``location``, filed under its ``account``
+    pub fn insert(&mut self, order_id: OrderId, location: (AskOrBid, Decimal), account: AccountId) {
+        self.locations.insert(order_id, location);
+        self.owners.insert(order_id, account);
+        self.account_orders.entry(account).or_default().insert(order_id);
+    }
+
+    /// Drops an order from the index, returns where it's been sitting at
+    pub fn remove(&mut self, order_id: &OrderId) -> Option<(AskOrBid, Decimal)> {
+        if let Some(account) = self.owners.remove(order_id) {
+            if let Some(orders) = self.account_orders.get_mut(&account) {
+                orders.remove(order_id);
+                if orders.is_empty() {
+                    self.account_orders.remove(&account);
+                }
+            }
+        }
+        self.locations.remove(order_id)
+    }
+
+    pub fn clear(&mut self) {
+        self.locations.clear();
+        self.owners.clear();
+        self.account_orders.clear();
+    }
+
+    /// Account an order belongs to, None for unknown orders
+    pub fn owner(&self, order_id: &OrderId) -> Option<AccountId> {
+        self.owners.get(order_id).copied()
+    }
+
+    /// Ids of the orders ``account`` has resting on the book, oldest first
+    pub fn account_orders(&self, account: &AccountId) -> impl Iterator<Item = &OrderId> {
+        self.account_orders.get(account).into_iter().flatten()
     }
 }
 
@@ -104,16 +359,21 @@ impl Orderbook {
             symbol,
             orders_ask: BTreeMap::<Decimal, OrderbookPage>::new(),
             orders_bid: BTreeMap::<Decimal, OrderbookPage>::new(),
-            orders_index: HashMap::<OrderId, Decimal>::new(),
+            orders_index: OrdersIndex::default(),
+            last_trade_price: None,
         }
     }
 
+    pub fn get_symbol(&self) -> &Symbol {
+        &self.symbol
+    }
+
     pub fn get_best_ask(&self) -> Option<Decimal> {
         self.orders_ask.iter().next().map(|(price, _)| *price)
     }
 
     pub fn get_best_bid(&self) -> Option<Decimal> {
-        self.orders_bid.iter().rev().next().map(|(price, _)| *price)
+        self.orders_bid.iter().next_back().map(|(price, _)| *price)
     }
 
     pub fn get_best_price_for_side(&self, side: AskOrBid) -> Option<Decimal> {
@@ -137,6 +397,104 @@ impl Orderbook {
         }
     }
 
+    /// Cumulative amount resting on the opposite side of ``new_side`` that an order
+    /// with limit ``new_price`` could be matched against, including hidden reserves
+    pub fn get_matchable_amount(&self, new_side: AskOrBid, new_price: &Decimal) -> Decimal {
+        match new_side {
+            AskOrBid::Bid => self
+                .orders_ask
+                .range(..=*new_price)
+                .map(|(_, page)| page.amount + page.hidden)
+                .sum(),
+            AskOrBid::Ask => self
+                .orders_bid
+                .range(*new_price..)
+                .map(|(_, page)| page.amount + page.hidden)
+                .sum(),
+        }
+    }
+
+    /// Amount a fill or kill ``taker`` can rely on getting filled with up to
+    /// ``new_price``, see ``get_matchable_amount``.
+    ///
+    /// The book is walked in matching order until the taker's remaining amount is
+    /// covered. Orders of the taker's own account don't count. Unless self-trade
+    /// prevention just cancels them, reaching one of them before the taker is
+    /// covered cuts it short, nothing beyond it can be relied upon in that case.
+    pub fn get_fillable_amount(&self, new_side: AskOrBid, new_price: &Decimal, taker: &Order) -> Decimal {
+        self.walk_fillable(new_side, new_price, taker, false)
+    }
+
+    /// Like ``get_fillable_amount`` for matchers that allocate a whole page at
+    /// once, those handle every own order of a page before its first fill
+    pub fn get_fillable_amount_by_page(&self, new_side: AskOrBid, new_price: &Decimal, taker: &Order) -> Decimal {
+        self.walk_fillable(new_side, new_price, taker, true)
+    }
+
+    fn walk_fillable(&self, new_side: AskOrBid, new_price: &Decimal, taker: &Order, by_page: bool) -> Decimal {
+        let pages: Box<dyn Iterator<Item = (&Decimal, &OrderbookPage)>> = match new_side {
+            AskOrBid::Bid => Box::new(self.orders_ask.range(..=*new_price)),
+            AskOrBid::Ask => Box::new(self.orders_bid.range(*new_price..).rev()),
+        };
+        let amount = taker.remaining();
+        let blocks = |maker: &Order| taker.is_self_trade(maker) && taker.stp != SelfTradePrevention::CancelOldest;
+
+        let mut fillable = Decimal::zero();
+        for (_, page) in pages {
+            if fillable >= amount {
+                break;
+            }
+            if by_page && page.orders.values().any(blocks) {
+                return fillable;
+            }
+
+            // Displayed amounts are matched in queue order, hidden reserves only
+            // once they have been replenished at the back of the queue
+            for maker in page.orders.values() {
+                if fillable >= amount {
+                    return fillable;
+                }
+                if blocks(maker) {
+                    return fillable;
+                }
+                if !taker.is_self_trade(maker) {
+                    fillable += maker.unfilled;
+                }
+            }
+            fillable += page
+                .orders
+                .values()
+                .filter(|maker| !taker.is_self_trade(maker))
+                .map(|maker| maker.hidden)
+                .sum::<Decimal>();
+        }
+        fillable
+    }
+
+    /// Quote asset a market order of ``taker`` would have to pay at most to take
+    /// ``amount`` off the book, walking it from the best price. Orders of the
+    /// taker's own account are skipped, they never trade with it.
+    pub fn get_market_cost(&self, new_side: AskOrBid, amount: &Decimal, taker: &Order) -> Decimal {
+        let pages: Box<dyn Iterator<Item = (&Decimal, &OrderbookPage)>> = match new_side {
+            AskOrBid::Bid => Box::new(self.orders_ask.iter()),
+            AskOrBid::Ask => Box::new(self.orders_bid.iter().rev()),
+        };
+
+        let mut left = *amount;
+        let mut cost = Decimal::zero();
+        for (price, page) in pages {
+            for maker in page.orders.values().filter(|maker| !taker.is_self_trade(maker)) {
+                if left.is_zero() {
+                    return cost;
+                }
+                let fill = left.min(maker.remaining());
+                cost += price * fill;
+                left -= fill;
+            }
+        }
+        cost
+    }
+
     pub fn get_best_page_for_price(
         &mut self,
         _side: &AskOrBid,
@@ -149,6 +507,22 @@ impl Orderbook {
         self.orders_index.contains_key(order_id)
     }
 
+    /// Resting orders of ``account`` together with their side and price, oldest first
+    pub fn get_account_orders(&self, account: &AccountId) -> Vec<(AskOrBid, Decimal, &Order)> {
+        self.orders_index
+            .account_orders(account)
+            .filter_map(|order_id| {
+                let (side, price) = self.orders_index.get(order_id)?;
+                let orderbook = match side {
+                    AskOrBid::Ask => &self.orders_ask,
+                    AskOrBid::Bid => &self.orders_bid,
+                };
+                let order = orderbook.get(price)?.get(order_id)?;
+                Some((*side, *price, order))
+            })
+            .collect()
+    }
+
     /// Determins whether a given price currently sits on the bid or ask side
     /// Returns None if either no orders are in the orderbook or if the orderbook is in an inconsistent state
     pub fn get_side_for_price(&self, price: &Decimal) -> Option<AskOrBid> {
@@ -169,21 +543,22 @@ impl Orderbook {
         None
     }
 
+    /// Resolves side and price level of a resting order
+    pub fn get_order_location(&self, order_id: &OrderId) -> Result<(AskOrBid, Decimal), ExchangeError> {
+        self.orders_index
+            .get(order_id)
+            .copied()
+            .ok_or(ExchangeError::UnknownOrder(*order_id))
+    }
+
     /// Get's an order by order id
     pub fn get_order_mut(&mut self, order_id: &OrderId) -> Option<&mut Order> {
-        if let Some(price) = self.orders_index.get(order_id) {
-            if let Some(side) = self.get_side_for_price(&price) {
-                let orderbook = match side {
-                    AskOrBid::Ask => &mut self.orders_ask,
-                    AskOrBid::Bid => &mut self.orders_bid,
-                };
-
-                if let Some(order_page) = orderbook.get_mut(&price) {
-                    return order_page.orders.get_mut(order_id);
-                }
-            }
-        }
-        return None;
+        let (side, price) = self.orders_index.get(order_id)?;
+        let orderbook = match side {
+            AskOrBid::Ask => &mut self.orders_ask,
+            AskOrBid::Bid => &mut self.orders_bid,
+        };
+        orderbook.get_mut(price)?.orders.get_mut(order_id)
     }
 
     /// Returns whether the book is in a state where orders can be matched
@@ -202,7 +577,7 @@ impl Orderbook {
     /// Prints the current best ask and bid price levels
     /// 
     /// In case no order exists on one of the sides, -1 is returned.
-    fn log_best_ask_bid(&self) {
+    pub fn log_best_ask_bid(&self) {
         info!(
             "Best Ask: {}",
             self.get_best_ask().unwrap_or(Decimal::from(-1))
@@ -213,38 +588,37 @@ impl Orderbook {
         );
     }
 
-    /// Inserts a new limit order into the book and then tries to execute the
-    /// order matcher
-    pub fn insert_try_exec_limit(
-        &mut self,
-        order: Order,
-        side: AskOrBid,
-        price: &Decimal,
-    ) -> InsertLimitResult {
-        // Insert limit order
-        if let InsertLimitResult::OrderDataInvalid =
-            self.insert_limit(order, side.clone(), *price)
-        {
-            return InsertLimitResult::OrderDataInvalid;
+    /// Checks whether an order with the given data could be inserted into the book
+    pub fn check_order_data(
+        &self,
+        order_id: &OrderId,
+        price: Option<&Decimal>,
+        amount: &Decimal,
+    ) -> Result<(), ExchangeError> {
+        if let Some(price) = price {
+            if *price <= Decimal::zero() {
+                return Err(ExchangeError::InvalidPrice(*price));
+            }
+        }
+        if *amount <= Decimal::zero() {
+            return Err(ExchangeError::InvalidQuantity(*amount));
+        }
+
+        //Ids have to be unique accross the book
+        if self.orders_index.contains_key(order_id) {
+            return Err(ExchangeError::DuplicateOrderId(*order_id));
         }
-    InsertLimitResult::OrderDataInvalid
+        Ok(())
     }
 
-    /// Inserts a new limit order into the
+    /// Inserts a new limit order into the book without matching it
     pub fn insert_limit(
         &mut self,
         order: Order,
         side: AskOrBid,
         price: Decimal
-    ) -> InsertLimitResult {
-        if price <= Decimal::zero() || order.unfilled <= Decimal::zero() {
-            panic!("Order price or amount invalid!");
-        }
-
-        //Return false if an order with the same id is already inserted into orderbook
-        if self.orders_index.contains_key(&order.id) {
-            panic!("Order with that id already exists");
-        }
+    ) -> Result<(), ExchangeError> {
+        self.check_order_data(&order.id, Some(&price), &order.remaining())?;
 
         let orderbook = match side {
             AskOrBid::Ask => &mut self.orders_ask,
@@ -258,45 +632,62 @@ impl Orderbook {
             .or_insert_with(|| OrderbookPage::new(&order));
 
         // Update index
-        self.orders_index.insert(order.id, price);
+        self.orders_index.insert(order.id, (side, price), order.account);
 
         //info!("Inserted order {} at price {}", order_id, price);
         //self.log_best_ask_bid();
 
-        InsertLimitResult::Success(order.id)
+        Ok(())
     }
 
-    pub fn cancel_limit(&mut self, order_id: &OrderId) -> CancelLimitResult {
-        if let Some(price) = self.orders_index.get(order_id) {
-            if let Some(side) = self.get_side_for_price(price) {
-                let orderbook = match side {
-                    AskOrBid::Ask => &mut self.orders_ask,
-                    AskOrBid::Bid => &mut self.orders_bid,
-                };
-                if let Some(orderbook_page) = orderbook.get_mut(price) {
-                    if let Some(_removed) = orderbook_page.remove(order_id) {
-                        if orderbook_page.amount == Decimal::from(0) {
-                            orderbook.remove(price);
-                        }
-                        return CancelLimitResult::Success;
-                    }
-                }
-            } else {
-                panic!("cancel_limit called in an inconsistent state!")
-            }
+    /// Reduces the remaining amount of a resting order in place.
+    ///
+    /// The order keeps its position in the page's queue, increasing the amount
+    /// isn't possible this way since it would have to lose time priority. Iceberg
+    /// orders are reduced from their hidden reserve first.
+    pub fn reduce_order(&mut self, order_id: &OrderId, amount: &Decimal) -> Result<(), ExchangeError> {
+        let (side, price) = self.get_order_location(order_id)?;
+        let orderbook = match side {
+            AskOrBid::Ask => &mut self.orders_ask,
+            AskOrBid::Bid => &mut self.orders_bid,
+        };
+        let page = orderbook
+            .get_mut(&price)
+            .ok_or(ExchangeError::BookInconsistent)?;
+        let remaining = page
+            .get(order_id)
+            .ok_or(ExchangeError::BookInconsistent)?
+            .remaining();
+
+        if *amount <= Decimal::zero() || *amount > remaining {
+            return Err(ExchangeError::InvalidQuantity(*amount));
         }
-        CancelLimitResult::OrderIdNotFound
+
+        page.decrement(order_id, &(remaining - amount));
+        Ok(())
     }
-}
 
-impl InsertLimitResult {
-    pub fn is_success(&self) -> bool {
-        match self {
-            InsertLimitResult::Success(_) => true,
-            InsertLimitResult::PartiallyFilled(_, _) => true,
-            InsertLimitResult::FullyFilled => true,
-            InsertLimitResult::OrderDataInvalid => false,
+    /// Removes an order from the book, returns the removed order
+    pub fn cancel_limit(&mut self, order_id: &OrderId) -> Result<Order, ExchangeError> {
+        let (side, price) = self.get_order_location(order_id)?;
+
+        let orderbook = match side {
+            AskOrBid::Ask => &mut self.orders_ask,
+            AskOrBid::Bid => &mut self.orders_bid,
+        };
+        let orderbook_page = orderbook
+            .get_mut(&price)
+            .ok_or(ExchangeError::BookInconsistent)?;
+        let removed = orderbook_page
+            .remove(order_id)
+            .ok_or(ExchangeError::BookInconsistent)?;
+
+        if orderbook_page.orders.is_empty() {
+            orderbook.remove(&price);
         }
+        self.orders_index.remove(order_id);
+
+        Ok(removed)
     }
 }
 
@@ -306,7 +697,9 @@ impl fmt::Display for InsertLimitResult {
             InsertLimitResult::Success(_) => write!(f, "success"),
             InsertLimitResult::PartiallyFilled(_, _) => write!(f, "partially_filled"),
             InsertLimitResult::FullyFilled => write!(f, "fully_filled"),
-            InsertLimitResult::OrderDataInvalid => write!(f, "order_data_invalid"),
+            InsertLimitResult::PartiallyFilledCancelled(_, _, _) => write!(f, "partially_filled"),
+            InsertLimitResult::Cancelled(_, _) => write!(f, "cancelled"),
+            InsertLimitResult::Killed(_) => write!(f, "killed"),
         }
     }
 }
@@ -321,11 +714,6 @@ impl From<InsertLimitResult> for JsonValue {
                     "order_id" => order_id
                 }
             }
-            InsertLimitResult::OrderDataInvalid => {
-                object! {
-                    "status" => status
-                }
-            }
             InsertLimitResult::PartiallyFilled(order_id, unfilled) => {
                 object! {
                     "status" => status,
@@ -338,17 +726,214 @@ impl From<InsertLimitResult> for JsonValue {
                     "status" => status
                 }
             }
+            InsertLimitResult::PartiallyFilledCancelled(order_id, filled, cancelled) => {
+                object! {
+                    "status" => status,
+                    "order_id" => order_id,
+                    "filled" => filled.to_string(),
+                    "cancelled" => cancelled.to_string()
+                }
+            }
+            InsertLimitResult::Cancelled(order_id, cancelled) => {
+                object! {
+                    "status" => status,
+                    "order_id" => order_id,
+                    "cancelled" => cancelled.to_string()
+                }
+            }
+            InsertLimitResult::Killed(order_id) => {
+                object! {
+                    "status" => status,
+                    "order_id" => order_id
+                }
+            }
         }
     }
 }
 
-impl fmt::Display for CancelLimitResult {
+impl fmt::Display for InsertMarketResult {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            InsertMarketResult::FullyFilled(_, _, _) => write!(f, "fully_filled"),
+            InsertMarketResult::PartiallyFilled(_, _, _, _) => write!(f, "partially_filled"),
+            InsertMarketResult::NoLiquidity(_, _) => write!(f, "no_liquidity"),
+        }
+    }
+}
+
+impl From<InsertMarketResult> for JsonValue {
+    fn from(result: InsertMarketResult) -> Self {
+        let status = result.to_string();
+        match result {
+            InsertMarketResult::FullyFilled(order_id, filled, average_price) => {
+                object! {
+                    "status" => status,
+                    "order_id" => order_id,
+                    "filled" => filled.to_string(),
+                    "average_price" => average_price.to_string()
+                }
+            }
+            InsertMarketResult::PartiallyFilled(order_id, filled, average_price, cancelled) => {
+                object! {
+                    "status" => status,
+                    "order_id" => order_id,
+                    "filled" => filled.to_string(),
+                    "average_price" => average_price.to_string(),
+                    "cancelled" => cancelled.to_string()
+                }
+            }
+            InsertMarketResult::NoLiquidity(order_id, cancelled) => {
+                object! {
+                    "status" => status,
+                    "order_id" => order_id,
+                    "cancelled" => cancelled.to_string()
+                }
+            }
+        }
+    }
+}
+
+/// Full depth of a book, price levels of both sides best first with their
+/// orders in queue order
+impl From<&Orderbook> for JsonValue {
+    fn from(orderbook: &Orderbook) -> Self {
+        let levels = |pages: &mut dyn Iterator<Item = (&Decimal, &OrderbookPage)>| {
+            pages
+                .map(|(price, page)| object! {
+                    "price" => price.to_string(),
+                    "amount" => page.amount.to_string(),
+                    "hidden" => page.hidden.to_string(),
+                    "orders" => page.orders.values().map(|order| object! {
+                        "order_id" => order.id,
+                        "unfilled" => order.unfilled.to_string(),
+                        "hidden" => order.hidden.to_string()
+                    }).collect::<Vec<JsonValue>>()
+                })
+                .collect::<Vec<JsonValue>>()
+        };
+        object! {
+            "symbol" => orderbook.symbol.to_string(),
+            "last_trade_price" => orderbook.last_trade_price.map(|price| price.to_string()),
+            "asks" => levels(&mut orderbook.orders_ask.iter()),
+            "bids" => levels(&mut orderbook.orders_bid.iter().rev())
+        }
+    }
+}
+
+impl Snapshot for Order {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        writer.put(&self.id);
+        writer.put(&self.unfilled);
+        writer.put(&self.hidden);
+        writer.put(&self.peak);
+        writer.put(&self.account);
+        writer.put(&self.stp);
+        writer.put(&self.post_only);
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        Ok(Order {
+            id: reader.get()?,
+            unfilled: reader.get()?,
+            hidden: reader.get()?,
+            peak: reader.get()?,
+            account: reader.get()?,
+            stp: reader.get()?,
+            post_only: reader.get()?,
+        })
+    }
+}
+
+/// Orders are written in queue order, which is restored as is
+impl Snapshot for OrderbookPage {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        writer.put(&self.amount);
+        writer.put(&self.hidden);
+        writer.put_len(self.orders.len());
+        for order in self.orders.values() {
+            writer.put(order);
+        }
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        let mut page = OrderbookPage {
+            orders: LinkedHashMap::new(),
+            amount: reader.get()?,
+            hidden: reader.get()?,
+        };
+        for _ in 0..reader.get_len()? {
+            let order: Order = reader.get()?;
+            page.orders.insert(order.id, order);
+        }
+        Ok(page)
+    }
+}
+
+/// Pages of both sides are written in price order, followed by the order
+/// index. Reading checks that every indexed order sits on its page and that
+/// no order is missing from the index.
+impl Snapshot for Orderbook {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        writer.put(&self.symbol.to_string());
+        writer.put(&self.last_trade_price);
+        for pages in [&self.orders_ask, &self.orders_bid] {
+            writer.put_len(pages.len());
+            for (price, page) in pages {
+                writer.put(price);
+                writer.put(page);
+            }
+        }
+
+        let mut locations: Vec<(OrderId, (AskOrBid, Decimal))> = self
+            .orders_index
+            .locations
+            .iter()
+            .map(|(order_id, location)| (*order_id, *location))
+            .collect();
+        locations.sort_by_key(|(order_id, _)| *order_id);
+        writer.put(&locations);
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        let mut orderbook = Orderbook::new(Symbol::new(&reader.get::<String>()?));
+        orderbook.last_trade_price = reader.get()?;
+        for side in [AskOrBid::Ask, AskOrBid::Bid] {
+            for _ in 0..reader.get_len()? {
+                let (price, page): (Decimal, OrderbookPage) = (reader.get()?, reader.get()?);
+                match side {
+                    AskOrBid::Ask => orderbook.orders_ask.insert(price, page),
+                    AskOrBid::Bid => orderbook.orders_bid.insert(price, page),
+                };
+            }
+        }
+
+        for (order_id, (side, price)) in reader.get::<Vec<(OrderId, (AskOrBid, Decimal))>>()? {
+            let pages = match side {
+                AskOrBid::Ask => &orderbook.orders_ask,
+                AskOrBid::Bid => &orderbook.orders_bid,
+            };
+            let account = pages
+                .get(&price)
+                .and_then(|page| page.get(&order_id))
+                .ok_or_else(|| invalid_snapshot("indexed order isn't on its page"))?
+                .account;
+            orderbook.orders_index.insert(order_id, (side, price), account);
+        }
+        let order_count: usize = orderbook
+            .orders_ask
+            .values()
+            .chain(orderbook.orders_bid.values())
+            .map(|page| page.orders.len())
+            .sum();
+        if order_count != orderbook.orders_index.len() {
+            return Err(invalid_snapshot("order missing from index"));
+        }
+        Ok(orderbook)
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::clone_on_copy)]
 mod orderbook_tests {
 
     use rand::{RngCore, SeedableRng};
@@ -362,20 +947,18 @@ mod orderbook_tests {
         side: AskOrBid,
         price: &Decimal,
         size: &Decimal,
-    ) -> InsertLimitResult {
+    ) -> Result<(), ExchangeError> {
         let order_id = order_id.clone();
         let size = size.clone();
         let price = price.clone();
-        let order = Order {
-            id: order_id, unfilled: size
-        };
+        let order = Order::new(order_id, order_id, size);
 
         orderbook.insert_limit(order, side, price)
     }
 
     #[test]
     fn test_can_match_against() {
-        let mut orderbook = Orderbook::new(Symbol::Asset2);
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
         assert_eq!(
             orderbook.can_be_matched_against(AskOrBid::Ask, &Decimal::zero()),
             false
@@ -387,7 +970,7 @@ mod orderbook_tests {
             AskOrBid::Ask,
             &Decimal::from(2004),
             &Decimal::from(56),
-        );
+        ).unwrap();
 
         assert_eq!(
             orderbook.can_be_matched_against(AskOrBid::Ask, &Decimal::from(2004)),
@@ -412,7 +995,7 @@ mod orderbook_tests {
             AskOrBid::Bid,
             &Decimal::from(1999),
             &Decimal::from(56),
-        );
+        ).unwrap();
 
         assert_eq!(
             orderbook.can_be_matched_against(AskOrBid::Bid, &Decimal::from(1950)),
@@ -432,12 +1015,28 @@ mod orderbook_tests {
         );
     }
 
+    #[test]
+    fn test_get_matchable_amount() {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        assert_eq!(orderbook.get_matchable_amount(AskOrBid::Bid, &Decimal::from(100)), Decimal::ZERO);
+
+        insert_limit(&mut orderbook, &0, AskOrBid::Ask, &Decimal::from(100), &Decimal::from(3)).unwrap();
+        insert_limit(&mut orderbook, &1, AskOrBid::Ask, &Decimal::from(100), &Decimal::from(2)).unwrap();
+        insert_limit(&mut orderbook, &2, AskOrBid::Ask, &Decimal::from(101), &Decimal::from(4)).unwrap();
+        insert_limit(&mut orderbook, &3, AskOrBid::Bid, &Decimal::from(99), &Decimal::from(7)).unwrap();
+        insert_limit(&mut orderbook, &4, AskOrBid::Bid, &Decimal::from(98), &Decimal::from(1)).unwrap();
+
+        assert_eq!(orderbook.get_matchable_amount(AskOrBid::Bid, &Decimal::from(99)), Decimal::ZERO);
+        assert_eq!(orderbook.get_matchable_amount(AskOrBid::Bid, &Decimal::from(100)), Decimal::from(5));
+        assert_eq!(orderbook.get_matchable_amount(AskOrBid::Bid, &Decimal::from(200)), Decimal::from(9));
+        assert_eq!(orderbook.get_matchable_amount(AskOrBid::Ask, &Decimal::from(100)), Decimal::ZERO);
+        assert_eq!(orderbook.get_matchable_amount(AskOrBid::Ask, &Decimal::from(99)), Decimal::from(7));
+        assert_eq!(orderbook.get_matchable_amount(AskOrBid::Ask, &Decimal::from(1)), Decimal::from(8));
+    }
+
     #[test]
     fn test_new_page() {
-        let order = Order {
-            id: 0,
-            unfilled: Decimal::from(10),
-        };
+        let order = Order::new(0, 0, Decimal::from(10));
         let page = OrderbookPage::new(&order);
 
         assert_eq!(page.orders.len(), 1);
@@ -453,20 +1052,40 @@ mod orderbook_tests {
         assert_eq!(order.unfilled, page.amount);
     }
 
+    #[test]
+    fn test_iceberg_order() {
+        let mut order = Order::iceberg(0, 0, Decimal::from(25), Decimal::from(10));
+        assert_eq!(order.is_iceberg(), true);
+        assert_eq!(order.unfilled, Decimal::from(10));
+        assert_eq!(order.hidden, Decimal::from(15));
+        assert_eq!(order.remaining(), Decimal::from(25));
+
+        //Only the displayed peak counts toward the page amount
+        let mut page = OrderbookPage::new(&order);
+        assert_eq!(page.amount, Decimal::from(10));
+        assert_eq!(page.hidden, Decimal::from(15));
+
+        order.unfilled = Decimal::ZERO;
+        assert_eq!(order.replenish(), Decimal::from(10));
+        order.unfilled = Decimal::ZERO;
+        assert_eq!(order.replenish(), Decimal::from(5));
+        assert_eq!(order.unfilled, Decimal::from(5));
+        assert_eq!(order.hidden, Decimal::ZERO);
+
+        page.remove(&0);
+        assert_eq!(page.amount, Decimal::ZERO);
+        assert_eq!(page.hidden, Decimal::ZERO);
+        assert_eq!(Order::new(1, 1, Decimal::ONE).is_iceberg(), false);
+    }
+
     #[test]
     fn test_page_remove_order() {
         let order1_amount = Decimal::from(10);
         let order2_amount = Decimal::from(3244);
 
-        let order = Order {
-            id: 0,
-            unfilled: order1_amount,
-        };
+        let order = Order::new(0, 0, order1_amount);
         let mut page = OrderbookPage::new(&order);
-        page.insert(&Order {
-            id: 1,
-            unfilled: order2_amount,
-        });
+        page.insert(&Order::new(1, 1, order2_amount));
 
         let removed_order = page.remove(&0);
         assert_eq!(removed_order, Some(order));
@@ -476,7 +1095,7 @@ mod orderbook_tests {
 
     #[test]
     fn test_orderbook_insert_limit() {
-        let mut orderbook = Orderbook::new(Symbol::Asset1);
+        let mut orderbook = Orderbook::new(Symbol::new("BTC"));
         let mut id = 16u64;
         let price = Decimal::from(100);
         let unfilled = Decimal::from(16);
@@ -484,7 +1103,7 @@ mod orderbook_tests {
         //Adding limit order with an unused order_id
         assert_eq!(
             insert_limit(&mut orderbook, &id, AskOrBid::Ask, &price, &unfilled),
-            InsertLimitResult::Success(id)
+            Ok(())
         );
 
         //Check if order got written into the BTree
@@ -501,19 +1120,19 @@ mod orderbook_tests {
         );
 
         //Check if index got written into HashMap
-        assert_eq!(orderbook.orders_index.get(&id).unwrap(), &price);
+        assert_eq!(orderbook.orders_index.get(&id).unwrap(), &(AskOrBid::Ask, price));
 
         //Adding an order with the same order_id twice shouldn't be possible.
-        // assert_eq!(
-        //     insert_limit(&mut orderbook, &id, AskOrBid::Ask, &price, &unfilled),
-        //     InsertLimitResult::OrderDataInvalid
-        // );
+        assert_eq!(
+            insert_limit(&mut orderbook, &id, AskOrBid::Ask, &price, &unfilled),
+            Err(ExchangeError::DuplicateOrderId(id))
+        );
 
         //Check bid side (Don't hanve to check indicies since there is only one HashMap
         id += 1;
         assert_eq!(
             insert_limit(&mut orderbook, &id, AskOrBid::Bid, &price, &unfilled),
-            InsertLimitResult::Success(id)
+            Ok(())
         );
         assert_eq!(
             orderbook
@@ -527,57 +1146,56 @@ mod orderbook_tests {
             id
         );
 
-        //TODO: Test for panic, not for result
         //Test for price <= 0 and amount <= 0
-        // id += 1;
-        // assert_eq!(
-        //     insert_limit(
-        //         &mut orderbook,
-        //         &id,
-        //         AskOrBid::Bid,
-        //         &Decimal::from(0),
-        //         &unfilled
-        //     ),
-        //     InsertLimitResult::OrderDataInvalid
-        // );
-        // id += 1;
-        // assert_eq!(
-        //     insert_limit(
-        //         &mut orderbook,
-        //         &id,
-        //         AskOrBid::Bid,
-        //         &Decimal::from(-1),
-        //         &unfilled
-        //     ),
-        //     InsertLimitResult::OrderDataInvalid
-        // );
-        // id += 1;
-        // assert_eq!(
-        //     insert_limit(
-        //         &mut orderbook,
-        //         &id,
-        //         AskOrBid::Bid,
-        //         &price,
-        //         &Decimal::from(-1)
-        //     ),
-        //     InsertLimitResult::OrderDataInvalid
-        // );
-        // id += 1;
-        // assert_eq!(
-        //     insert_limit(
-        //         &mut orderbook,
-        //         &id,
-        //         AskOrBid::Bid,
-        //         &price,
-        //         &Decimal::from(0)
-        //     ),
-        //     InsertLimitResult::OrderDataInvalid
-        // );
+        id += 1;
+        assert_eq!(
+            insert_limit(
+                &mut orderbook,
+                &id,
+                AskOrBid::Bid,
+                &Decimal::from(0),
+                &unfilled
+            ),
+            Err(ExchangeError::InvalidPrice(Decimal::from(0)))
+        );
+        assert_eq!(
+            insert_limit(
+                &mut orderbook,
+                &id,
+                AskOrBid::Bid,
+                &Decimal::from(-1),
+                &unfilled
+            ),
+            Err(ExchangeError::InvalidPrice(Decimal::from(-1)))
+        );
+        assert_eq!(
+            insert_limit(
+                &mut orderbook,
+                &id,
+                AskOrBid::Bid,
+                &price,
+                &Decimal::from(-1)
+            ),
+            Err(ExchangeError::InvalidQuantity(Decimal::from(-1)))
+        );
+        assert_eq!(
+            insert_limit(
+                &mut orderbook,
+                &id,
+                AskOrBid::Bid,
+                &price,
+                &Decimal::from(0)
+            ),
+            Err(ExchangeError::InvalidQuantity(Decimal::from(0)))
+        );
+
+        //Rejected orders don't end up in the book
+        assert_eq!(orderbook.contains_order(&id), false);
     }
 
     #[test]
     fn test_get_best_ask_bid() {
-        let mut orderbook = Orderbook::new(Symbol::Asset1);
+        let mut orderbook = Orderbook::new(Symbol::new("BTC"));
         let mut id = 0u64;
         let amount = Decimal::from(50);
 
@@ -602,9 +1220,9 @@ mod orderbook_tests {
                 best_bid = rand_price;
             }
 
-            insert_limit(&mut orderbook, &id, AskOrBid::Ask, &rand_price, &amount);
+            insert_limit(&mut orderbook, &id, AskOrBid::Ask, &rand_price, &amount).unwrap();
             id += 1;
-            insert_limit(&mut orderbook, &id, AskOrBid::Bid, &rand_price, &amount);
+            insert_limit(&mut orderbook, &id, AskOrBid::Bid, &rand_price, &amount).unwrap();
             id += 1;
         }
         assert_eq!(orderbook.get_best_ask().unwrap(), best_ask);
@@ -613,7 +1231,7 @@ mod orderbook_tests {
 
     #[test]
     fn test_contains_order() {
-        let mut orderbook = Orderbook::new(Symbol::Asset1);
+        let mut orderbook = Orderbook::new(Symbol::new("BTC"));
         let id = 1234u64;
 
         assert_eq!(orderbook.contains_order(&0), false);
@@ -625,13 +1243,13 @@ mod orderbook_tests {
             AskOrBid::Bid,
             &Decimal::from(328),
             &Decimal::from(834),
-        );
+        ).unwrap();
         assert_eq!(orderbook.contains_order(&id), true);
     }
 
     #[test]
     fn test_can_match() {
-        let mut orderbook = Orderbook::new(Symbol::Asset1);
+        let mut orderbook = Orderbook::new(Symbol::new("BTC"));
         let amount = Decimal::from(3945);
 
         assert_eq!(orderbook.can_match(), false);
@@ -642,7 +1260,7 @@ mod orderbook_tests {
             AskOrBid::Bid,
             &Decimal::from(500),
             &amount,
-        );
+        ).unwrap();
         assert_eq!(orderbook.can_match(), false);
         insert_limit(
             &mut orderbook,
@@ -650,7 +1268,7 @@ mod orderbook_tests {
             AskOrBid::Ask,
             &Decimal::from(501),
             &amount,
-        );
+        ).unwrap();
 
         assert_eq!(orderbook.can_match(), false);
 
@@ -660,14 +1278,14 @@ mod orderbook_tests {
             AskOrBid::Bid,
             &Decimal::from(501),
             &amount,
-        );
+        ).unwrap();
 
         assert_eq!(orderbook.can_match(), true);
     }
 
     #[test]
     fn test_get_orderbook_side_for_price() {
-        let mut orderbook = Orderbook::new(Symbol::Asset1);
+        let mut orderbook = Orderbook::new(Symbol::new("BTC"));
         let price_ask = Decimal::from(510);
         let price_bid = Decimal::from(505);
         let amount = Decimal::from(3945);
@@ -676,7 +1294,7 @@ mod orderbook_tests {
         assert_eq!(orderbook.get_side_for_price(&price_ask).is_none(), true);
 
         //check for asks
-        insert_limit(&mut orderbook, &0, AskOrBid::Ask, &price_ask, &amount);
+        insert_limit(&mut orderbook, &0, AskOrBid::Ask, &price_ask, &amount).unwrap();
 
         assert_eq!(
             orderbook
@@ -690,7 +1308,7 @@ mod orderbook_tests {
         );
 
         //Check for bids
-        insert_limit(&mut orderbook, &1, AskOrBid::Bid, &price_bid, &amount);
+        insert_limit(&mut orderbook, &1, AskOrBid::Bid, &price_bid, &amount).unwrap();
 
         assert_eq!(
             orderbook
@@ -704,26 +1322,26 @@ mod orderbook_tests {
         );
 
         //Bring orderbook into inconsistent state
-        insert_limit(&mut orderbook, &2, AskOrBid::Bid, &price_ask, &amount);
+        insert_limit(&mut orderbook, &2, AskOrBid::Bid, &price_ask, &amount).unwrap();
         assert_eq!(orderbook.get_side_for_price(&price_ask).is_none(), true);
     }
 
     #[test]
     fn test_get_order_mut() {
-        let mut orderbook = Orderbook::new(Symbol::Asset1);
+        let mut orderbook = Orderbook::new(Symbol::new("BTC"));
         let price = Decimal::from(505);
         let amount = Decimal::from(3945);
 
         assert_eq!(orderbook.get_order_mut(&0).is_none(), true);
 
-        insert_limit(&mut orderbook, &432, AskOrBid::Bid, &price, &amount);
+        insert_limit(&mut orderbook, &432, AskOrBid::Bid, &price, &amount).unwrap();
         insert_limit(
             &mut orderbook,
             &2130,
             AskOrBid::Ask,
             &(price + Decimal::from(1)),
             &amount,
-        );
+        ).unwrap();
 
         assert_eq!(orderbook.get_order_mut(&432).unwrap().unfilled, amount);
         assert_eq!(orderbook.get_order_mut(&212).is_none(), true);
@@ -731,11 +1349,11 @@ mod orderbook_tests {
 
     #[test]
     fn test_cancel_limit() {
-        let mut orderbook = Orderbook::new(Symbol::Asset1);
+        let mut orderbook = Orderbook::new(Symbol::new("BTC"));
 
         assert_eq!(
             orderbook.cancel_limit(&0),
-            CancelLimitResult::OrderIdNotFound
+            Err(ExchangeError::UnknownOrder(0))
         );
         insert_limit(
             &mut orderbook,
@@ -743,9 +1361,139 @@ mod orderbook_tests {
             AskOrBid::Bid,
             &Decimal::from(20),
             &Decimal::from(20),
-        );
+        ).unwrap();
 
-        assert_eq!(orderbook.cancel_limit(&0), CancelLimitResult::Success);
+        assert_eq!(
+            orderbook.cancel_limit(&0),
+            Ok(Order::new(0, 0, Decimal::from(20)))
+        );
         assert_eq!(orderbook.get_best_bid(), None);
+        assert_eq!(orderbook.contains_order(&0), false);
+
+        //Cancelling twice fails
+        assert_eq!(
+            orderbook.cancel_limit(&0),
+            Err(ExchangeError::UnknownOrder(0))
+        );
+    }
+
+    #[test]
+    fn test_cancel_limit_crossed_book() {
+        let mut orderbook = Orderbook::new(Symbol::new("BTC"));
+
+        insert_limit(&mut orderbook, &0, AskOrBid::Ask, &Decimal::from(20), &Decimal::ONE).unwrap();
+        insert_limit(&mut orderbook, &1, AskOrBid::Bid, &Decimal::from(21), &Decimal::ONE).unwrap();
+
+        //Side of an order is taken from the index, crossed books can be cancelled from
+        assert_eq!(orderbook.cancel_limit(&0), Ok(Order::new(0, 0, Decimal::ONE)));
+        assert_eq!(orderbook.get_best_bid(), Some(Decimal::from(21)));
+        assert_eq!(orderbook.cancel_limit(&1), Ok(Order::new(1, 1, Decimal::ONE)));
+        assert_eq!(orderbook.orders_bid.is_empty(), true);
+    }
+
+    #[test]
+    fn test_account_orders_index() {
+        let mut orderbook = Orderbook::new(Symbol::new("BTC"));
+
+        for (id, side, price, account) in [(0, AskOrBid::Ask, 20, 7), (1, AskOrBid::Bid, 10, 8), (2, AskOrBid::Bid, 11, 7)] {
+            orderbook
+                .insert_limit(Order::new(id, account, Decimal::ONE), side, Decimal::from(price))
+                .unwrap();
+        }
+        insert_limit(&mut orderbook, &3, AskOrBid::Ask, &Decimal::from(21), &Decimal::ONE).unwrap();
+
+        let orders: Vec<(u64, AskOrBid, Decimal)> = orderbook
+            .get_account_orders(&7)
+            .iter()
+            .map(|(side, price, order)| (order.id, *side, *price))
+            .collect();
+        assert_eq!(
+            orders,
+            vec![(0, AskOrBid::Ask, Decimal::from(20)), (2, AskOrBid::Bid, Decimal::from(11))]
+        );
+        assert_eq!(orderbook.orders_index.owner(&1), Some(8));
+        assert_eq!(orderbook.orders_index.owner(&3), Some(3));
+        assert_eq!(orderbook.orders_index.owner(&4), None);
+
+        //Removing orders from the book drops them from their account's index
+        orderbook.cancel_limit(&0).unwrap();
+        orderbook.cancel_limit(&1).unwrap();
+        assert_eq!(orderbook.orders_index.account_orders(&7).copied().collect::<Vec<u64>>(), vec![2]);
+        assert_eq!(orderbook.get_account_orders(&8).is_empty(), true);
+        assert_eq!(orderbook.orders_index.owner(&1), None);
+    }
+
+    #[test]
+    fn test_reduce_iceberg_from_hidden_first() {
+        let mut orderbook = Orderbook::new(Symbol::new("ETH"));
+        orderbook
+            .insert_limit(Order::iceberg(0, 0, Decimal::from(25), Decimal::from(10)), AskOrBid::Ask, Decimal::from(100))
+            .unwrap();
+        assert_eq!(orderbook.get_matchable_amount(AskOrBid::Bid, &Decimal::from(100)), Decimal::from(25));
+
+        orderbook.reduce_order(&0, &Decimal::from(12)).unwrap();
+        let page = orderbook.orders_ask.get(&Decimal::from(100)).unwrap();
+        assert_eq!(page.amount, Decimal::from(10));
+        assert_eq!(page.hidden, Decimal::from(2));
+
+        orderbook.reduce_order(&0, &Decimal::from(4)).unwrap();
+        let page = orderbook.orders_ask.get(&Decimal::from(100)).unwrap();
+        assert_eq!(page.amount, Decimal::from(4));
+        assert_eq!(page.hidden, Decimal::ZERO);
+
+        assert_eq!(
+            orderbook.reduce_order(&0, &Decimal::from(5)),
+            Err(ExchangeError::InvalidQuantity(Decimal::from(5)))
+        );
+    }
+
+    #[test]
+    fn test_reduce_order_keeps_priority() {
+        let mut orderbook = Orderbook::new(Symbol::new("BTC"));
+        let price = Decimal::from(20);
+
+        insert_limit(&mut orderbook, &0, AskOrBid::Ask, &price, &Decimal::from(10)).unwrap();
+        insert_limit(&mut orderbook, &1, AskOrBid::Ask, &price, &Decimal::from(5)).unwrap();
+
+        assert_eq!(orderbook.reduce_order(&0, &Decimal::from(4)), Ok(()));
+
+        let page = orderbook.orders_ask.get(&price).unwrap();
+        assert_eq!(page.amount, Decimal::from(9));
+        assert_eq!(page.orders.front().unwrap().1.id, 0);
+        assert_eq!(page.orders.front().unwrap().1.unfilled, Decimal::from(4));
+
+        //Increasing or zeroing the amount isn't a reduction
+        assert_eq!(
+            orderbook.reduce_order(&0, &Decimal::from(5)),
+            Err(ExchangeError::InvalidQuantity(Decimal::from(5)))
+        );
+        assert_eq!(
+            orderbook.reduce_order(&0, &Decimal::ZERO),
+            Err(ExchangeError::InvalidQuantity(Decimal::ZERO))
+        );
+        assert_eq!(
+            orderbook.reduce_order(&7, &Decimal::ONE),
+            Err(ExchangeError::UnknownOrder(7))
+        );
+    }
+
+    #[test]
+    fn test_orderbook_to_json() {
+        let mut orderbook = Orderbook::new(Symbol::new("BTC"));
+        insert_limit(&mut orderbook, &0, AskOrBid::Bid, &Decimal::from(9), &Decimal::from(1)).unwrap();
+        insert_limit(&mut orderbook, &1, AskOrBid::Bid, &Decimal::from(10), &Decimal::from(2)).unwrap();
+        insert_limit(&mut orderbook, &2, AskOrBid::Bid, &Decimal::from(10), &Decimal::from(3)).unwrap();
+        insert_limit(&mut orderbook, &3, AskOrBid::Ask, &Decimal::from(12), &Decimal::from(4)).unwrap();
+
+        //Best price level first, orders in queue order
+        let json = JsonValue::from(&orderbook);
+        assert_eq!(json["symbol"], "BTC");
+        assert_eq!(json["last_trade_price"].is_null(), true);
+        assert_eq!(json["asks"][0]["price"], "12");
+        assert_eq!(json["bids"][0]["price"], "10");
+        assert_eq!(json["bids"][0]["amount"], "5");
+        assert_eq!(json["bids"][0]["orders"][0]["order_id"], 1);
+        assert_eq!(json["bids"][0]["orders"][1]["order_id"], 2);
+        assert_eq!(json["bids"][1]["price"], "9");
     }
 }
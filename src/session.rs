@@ -0,0 +1,287 @@
+use core::fmt;
+use std::collections::BTreeMap;
+use std::io;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+
+use crate::inbound_server::{InboundMessage, MessageType};
+use crate::instrument::OrderRejection;
+use crate::order_matcher::TimeInForce;
+use crate::snapshot::{invalid_snapshot, Snapshot, SnapshotReader, SnapshotWriter};
+use crate::Timestamp;
+
+/// Trading session state of a single instrument.
+///
+/// During the call phases (pre-open and auction) limit orders accumulate on the
+/// book without being matched. Entering continuous trading or closing the
+/// session uncrosses the book at a single price.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum SessionState {
+    /// Order entry before the opening auction, nothing is matched
+    PreOpen,
+    /// Call auction, e.g. opening, closing or volatility auction
+    Auction,
+    /// Orders are matched as soon as they arrive
+    #[default]
+    Continuous,
+    /// Trading is suspended, resting orders can only be cancelled
+    Halted,
+    /// Trading is over for the day, resting orders can only be cancelled
+    Closed,
+}
+
+/// Session state of an instrument together with its scheduled transitions
+#[derive(Debug, Default)]
+pub struct Session {
+    pub state: SessionState,
+
+    /// Reference price of the static price band, updated by every uncrossing
+    pub reference_price: Option<Decimal>,
+
+    /// Transitions to be applied once the clock reaches their timestamp
+    schedule: BTreeMap<Timestamp, SessionState>,
+}
+
+impl SessionState {
+    /// Determins whether orders are collected without being matched
+    pub fn is_call_phase(&self) -> bool {
+        matches!(self, SessionState::PreOpen | SessionState::Auction)
+    }
+
+    /// Determins whether new orders and amends are accepted
+    pub fn accepts_orders(&self) -> bool {
+        !matches!(self, SessionState::Halted | SessionState::Closed)
+    }
+
+    /// Determins whether the session may move from this state to ``to``
+    pub fn can_transition_to(&self, to: SessionState) -> bool {
+        use SessionState::*;
+
+        match self {
+            PreOpen => matches!(to, Auction | Continuous | Halted | Closed),
+            Auction => matches!(to, Continuous | Halted | Closed),
+            Continuous => matches!(to, Auction | Halted | Closed),
+            Halted => matches!(to, Auction | Continuous | Closed),
+            Closed => matches!(to, PreOpen),
+        }
+    }
+
+    /// Checks whether an inbound message may be processed in this state.
+    ///
+    /// Cancels are always allowed. During call phases, orders that can't rest
+    /// on the book are rejected.
+    pub fn check_message(&self, msg: &InboundMessage) -> Result<(), OrderRejection> {
+        match msg.message_type {
+            MessageType::CancelLimitOrder
+            | MessageType::SetSession
+            | MessageType::OpenOrders
+            | MessageType::Deposit
+            | MessageType::Withdraw
+            | MessageType::CheckLedger => Ok(()),
+            _ if !self.accepts_orders() => Err(OrderRejection::NotAllowedInSession),
+            MessageType::PlaceMarketOrder if self.is_call_phase() => Err(OrderRejection::NotAllowedInAuction),
+            MessageType::PlaceLimitOrder
+                if self.is_call_phase()
+                    && (msg.post_only.is_some()
+                        || msg.time_in_force.unwrap_or_default() != TimeInForce::GoodTillCancel) =>
+            {
+                Err(OrderRejection::NotAllowedInAuction)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Session {
+    pub fn new(state: SessionState) -> Session {
+        Session {
+            state,
+            reference_price: None,
+            schedule: BTreeMap::new(),
+        }
+    }
+
+    /// Schedules a transition to ``state`` at ``at``, replacing any transition
+    /// already scheduled for that exact timestamp
+    pub fn schedule(&mut self, at: Timestamp, state: SessionState) {
+        self.schedule.insert(at, state);
+    }
+
+    /// Time of the earliest scheduled transition, None if none is scheduled
+    pub fn next_due(&self) -> Option<Timestamp> {
+        self.schedule.keys().next().copied()
+    }
+
+    /// Takes all transitions that are due at ``now`` off the schedule, earliest first
+    pub fn take_due(&mut self, now: Timestamp) -> Vec<(Timestamp, SessionState)> {
+        let pending = match now.checked_add(1) {
+            Some(next) => self.schedule.split_off(&next),
+            None => BTreeMap::new(),
+        };
+        std::mem::replace(&mut self.schedule, pending).into_iter().collect()
+    }
+}
+
+impl FromStr for SessionState {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pre_open" => Ok(SessionState::PreOpen),
+            "auction" => Ok(SessionState::Auction),
+            "continuous" => Ok(SessionState::Continuous),
+            "halted" => Ok(SessionState::Halted),
+            "closed" => Ok(SessionState::Closed),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for SessionState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionState::PreOpen => write!(f, "pre_open"),
+            SessionState::Auction => write!(f, "auction"),
+            SessionState::Continuous => write!(f, "continuous"),
+            SessionState::Halted => write!(f, "halted"),
+            SessionState::Closed => write!(f, "closed"),
+        }
+    }
+}
+
+impl Snapshot for SessionState {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        let state: u8 = match self {
+            SessionState::PreOpen => 0,
+            SessionState::Auction => 1,
+            SessionState::Continuous => 2,
+            SessionState::Halted => 3,
+            SessionState::Closed => 4,
+        };
+        writer.put(&state);
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        match reader.get::<u8>()? {
+            0 => Ok(SessionState::PreOpen),
+            1 => Ok(SessionState::Auction),
+            2 => Ok(SessionState::Continuous),
+            3 => Ok(SessionState::Halted),
+            4 => Ok(SessionState::Closed),
+            _ => Err(invalid_snapshot("invalid session state")),
+        }
+    }
+}
+
+impl Snapshot for Session {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        writer.put(&self.state);
+        writer.put(&self.reference_price);
+        writer.put(&self.schedule.iter().map(|(at, state)| (*at, *state)).collect::<Vec<_>>());
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        Ok(Session {
+            state: reader.get()?,
+            reference_price: reader.get()?,
+            schedule: reader.get::<Vec<(Timestamp, SessionState)>>()?.into_iter().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::inbound_server::{InboundMessage, MessageType};
+    use crate::instrument::OrderRejection;
+    use crate::order_matcher::TimeInForce;
+    use crate::session::{Session, SessionState};
+
+    fn message(message_type: MessageType) -> InboundMessage {
+        InboundMessage {
+            message_type,
+            symbol: None,
+            side: None,
+            limit_price: None,
+            amount: None,
+            order_id: None,
+            peak: None,
+            stop_price: None,
+            time_in_force: None,
+            post_only: None,
+            session_state: None,
+            at: None,
+            account: None,
+            stp: None,
+            asset: None,
+        }
+    }
+
+    #[test]
+    fn test_transitions() {
+        assert_eq!(SessionState::Closed.can_transition_to(SessionState::PreOpen), true);
+        assert_eq!(SessionState::Closed.can_transition_to(SessionState::Continuous), false);
+        assert_eq!(SessionState::PreOpen.can_transition_to(SessionState::Auction), true);
+        assert_eq!(SessionState::Continuous.can_transition_to(SessionState::Auction), true);
+        assert_eq!(SessionState::Continuous.can_transition_to(SessionState::PreOpen), false);
+        assert_eq!(SessionState::Halted.can_transition_to(SessionState::Continuous), true);
+        assert_eq!(SessionState::Halted.can_transition_to(SessionState::Halted), false);
+    }
+
+    #[test]
+    fn test_check_message() {
+        let mut limit = message(MessageType::PlaceLimitOrder);
+        let market = message(MessageType::PlaceMarketOrder);
+        let cancel = message(MessageType::CancelLimitOrder);
+
+        assert_eq!(SessionState::Continuous.check_message(&market), Ok(()));
+        assert_eq!(SessionState::PreOpen.check_message(&limit), Ok(()));
+        assert_eq!(
+            SessionState::PreOpen.check_message(&market),
+            Err(OrderRejection::NotAllowedInAuction)
+        );
+        assert_eq!(
+            SessionState::Halted.check_message(&limit),
+            Err(OrderRejection::NotAllowedInSession)
+        );
+        assert_eq!(
+            SessionState::Closed.check_message(&message(MessageType::AmendOrder)),
+            Err(OrderRejection::NotAllowedInSession)
+        );
+        assert_eq!(SessionState::Closed.check_message(&cancel), Ok(()));
+
+        limit.time_in_force = Some(TimeInForce::ImmediateOrCancel);
+        assert_eq!(
+            SessionState::Auction.check_message(&limit),
+            Err(OrderRejection::NotAllowedInAuction)
+        );
+        assert_eq!(SessionState::Continuous.check_message(&limit), Ok(()));
+    }
+
+    #[test]
+    fn test_take_due() {
+        let mut session = Session::new(SessionState::Closed);
+        session.schedule(300, SessionState::Closed);
+        session.schedule(100, SessionState::PreOpen);
+        session.schedule(200, SessionState::Continuous);
+
+        assert_eq!(session.take_due(50).is_empty(), true);
+        assert_eq!(
+            session.take_due(200),
+            vec![(100, SessionState::PreOpen), (200, SessionState::Continuous)]
+        );
+        assert_eq!(session.take_due(u64::MAX), vec![(300, SessionState::Closed)]);
+        assert_eq!(session.take_due(u64::MAX).is_empty(), true);
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(SessionState::from_str("pre_open"), Ok(SessionState::PreOpen));
+        assert_eq!(SessionState::from_str("HALTED"), Ok(SessionState::Halted));
+        assert_eq!(SessionState::from_str("open"), Err(()));
+        assert_eq!(SessionState::Continuous.to_string(), "continuous");
+    }
+}
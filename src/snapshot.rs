@@ -0,0 +1,335 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use rust_decimal::Decimal;
+
+/// Version of the snapshot format, bumped whenever the layout changes
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+/// Bytes every snapshot starts with
+const MAGIC: &[u8; 4] = b"EXSN";
+
+/// Prefix and extension of snapshot files, the sequence number goes in between
+const FILE_PREFIX: &str = "snapshot-";
+const FILE_EXTENSION: &str = ".bin";
+
+/// Types that can be written to and restored from a snapshot.
+///
+/// Integers are encoded little endian, decimals in their 16 byte
+/// representation and collections are prefixed with their length. Maps are
+/// written in a deterministic order, so equal state always yields equal bytes.
+pub trait Snapshot: Sized {
+    fn write(&self, writer: &mut SnapshotWriter);
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self>;
+}
+
+/// Buffer a snapshot is encoded into, starting with the format header
+pub struct SnapshotWriter {
+    buf: Vec<u8>,
+}
+
+/// Cursor over an encoded snapshot
+pub struct SnapshotReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+/// Error for snapshots that can't be decoded
+pub fn invalid_snapshot(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid snapshot: {}", reason))
+}
+
+impl SnapshotWriter {
+    pub fn new() -> SnapshotWriter {
+        let mut writer = SnapshotWriter { buf: Vec::new() };
+        writer.bytes(MAGIC);
+        writer.put(&SNAPSHOT_VERSION);
+        writer
+    }
+
+    pub fn put<T: Snapshot>(&mut self, value: &T) {
+        value.write(self);
+    }
+
+    /// Writes a collection length
+    pub fn put_len(&mut self, len: usize) {
+        self.put(&(len as u64));
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl Default for SnapshotWriter {
+    fn default() -> Self {
+        SnapshotWriter::new()
+    }
+}
+
+impl<'a> SnapshotReader<'a> {
+    /// Starts reading a snapshot, fails if it wasn't written in the current version
+    pub fn new(data: &'a [u8]) -> io::Result<SnapshotReader<'a>> {
+        let mut reader = SnapshotReader { data, pos: 0 };
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(invalid_snapshot("not a snapshot"));
+        }
+        let version: u16 = reader.get()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid_snapshot(&format!("unsupported version {}", version)));
+        }
+        Ok(reader)
+    }
+
+    pub fn get<T: Snapshot>(&mut self) -> io::Result<T> {
+        T::read(self)
+    }
+
+    /// Reads a collection length
+    pub fn get_len(&mut self) -> io::Result<usize> {
+        let len: u64 = self.get()?;
+        // Every element takes at least one byte, longer collections can't be valid
+        match len <= (self.data.len() - self.pos) as u64 {
+            true => Ok(len as usize),
+            false => Err(invalid_snapshot("collection exceeds snapshot")),
+        }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| invalid_snapshot("unexpected end"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Checks that the whole snapshot has been read
+    pub fn finish(&self) -> io::Result<()> {
+        match self.pos == self.data.len() {
+            true => Ok(()),
+            false => Err(invalid_snapshot("trailing bytes")),
+        }
+    }
+}
+
+impl Snapshot for u8 {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        writer.bytes(&[*self]);
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        Ok(reader.bytes(1)?[0])
+    }
+}
+
+impl Snapshot for u16 {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        writer.bytes(&self.to_le_bytes());
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(reader.bytes(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+}
+
+impl Snapshot for u64 {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        writer.bytes(&self.to_le_bytes());
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(reader.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+impl Snapshot for Decimal {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        writer.bytes(&self.serialize());
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(reader.bytes(16)?);
+        Ok(Decimal::deserialize(bytes))
+    }
+}
+
+impl Snapshot for String {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        writer.put_len(self.len());
+        writer.bytes(self.as_bytes());
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        let len = reader.get_len()?;
+        String::from_utf8(reader.bytes(len)?.to_vec()).map_err(|_| invalid_snapshot("string isn't utf-8"))
+    }
+}
+
+impl<T: Snapshot> Snapshot for Option<T> {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        match self {
+            Some(value) => {
+                writer.put(&1u8);
+                writer.put(value);
+            }
+            None => writer.put(&0u8),
+        }
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        match reader.get::<u8>()? {
+            0 => Ok(None),
+            1 => Ok(Some(reader.get()?)),
+            _ => Err(invalid_snapshot("invalid option")),
+        }
+    }
+}
+
+impl<A: Snapshot, B: Snapshot> Snapshot for (A, B) {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        writer.put(&self.0);
+        writer.put(&self.1);
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        Ok((reader.get()?, reader.get()?))
+    }
+}
+
+impl<T: Snapshot> Snapshot for Vec<T> {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        writer.put_len(self.len());
+        for value in self {
+            writer.put(value);
+        }
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        let len = reader.get_len()?;
+        (0..len).map(|_| reader.get()).collect()
+    }
+}
+
+/// 64 bit FNV-1a hash of a snapshot. Unlike the hashers of the standard library
+/// it never changes between builds, equal state of the same snapshot version
+/// always hashes the same.
+pub fn fingerprint(snapshot: &[u8]) -> u64 {
+    snapshot.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Path of the snapshot taken after the log entry ``seq`` within ``dir``
+pub fn snapshot_path<P: AsRef<Path>>(dir: P, seq: u64) -> PathBuf {
+    dir.as_ref().join(format!("{}{:020}{}", FILE_PREFIX, seq, FILE_EXTENSION))
+}
+
+/// Writes a snapshot taken after the log entry ``seq`` into ``dir``.
+///
+/// The snapshot is written to a temporary file first and renamed once it's
+/// synced, so a crash never leaves a partial snapshot behind.
+pub fn write_snapshot_file<P: AsRef<Path>>(dir: P, seq: u64, snapshot: &[u8]) -> io::Result<PathBuf> {
+    fs::create_dir_all(&dir)?;
+    let path = snapshot_path(&dir, seq);
+    let tmp_path = path.with_extension("tmp");
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(snapshot)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, &path)?;
+    Ok(path)
+}
+
+/// Latest snapshot within ``dir`` together with the sequence number it was
+/// taken at, None if there is none
+pub fn latest_snapshot<P: AsRef<Path>>(dir: P) -> io::Result<Option<(u64, PathBuf)>> {
+    if !dir.as_ref().exists() {
+        return Ok(None);
+    }
+
+    let mut latest: Option<(u64, PathBuf)> = None;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let seq = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(FILE_PREFIX)?.strip_suffix(FILE_EXTENSION)?.parse::<u64>().ok());
+        if let Some(seq) = seq {
+            if latest.as_ref().is_none_or(|(latest_seq, _)| seq > *latest_seq) {
+                latest = Some((seq, path));
+            }
+        }
+    }
+    Ok(latest)
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use std::fs;
+
+    use rust_decimal::Decimal;
+
+    use crate::snapshot::{fingerprint, latest_snapshot, write_snapshot_file, SnapshotReader, SnapshotWriter};
+
+    #[test]
+    fn test_round_trip() {
+        let mut writer = SnapshotWriter::new();
+        writer.put(&42u64);
+        writer.put(&Decimal::new(-12345, 3));
+        writer.put(&Some("BTC".to_string()));
+        writer.put(&vec![(1u64, None::<Decimal>), (2, Some(Decimal::ONE))]);
+        let bytes = writer.into_bytes();
+
+        let mut reader = SnapshotReader::new(&bytes).unwrap();
+        assert_eq!(reader.get::<u64>().unwrap(), 42);
+        assert_eq!(reader.get::<Decimal>().unwrap().to_string(), "-12.345");
+        assert_eq!(reader.get::<Option<String>>().unwrap(), Some("BTC".to_string()));
+        assert_eq!(
+            reader.get::<Vec<(u64, Option<Decimal>)>>().unwrap(),
+            vec![(1, None), (2, Some(Decimal::ONE))]
+        );
+        assert_eq!(reader.finish().is_ok(), true);
+
+        //Truncated snapshots and other versions are rejected
+        assert_eq!(SnapshotReader::new(&bytes[..10]).unwrap().get::<u64>().is_err(), true);
+        let mut other_version = bytes.clone();
+        other_version[4] = 99;
+        assert_eq!(SnapshotReader::new(&other_version).is_err(), true);
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(fingerprint(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fingerprint(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fingerprint(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn test_latest_snapshot() {
+        let dir = std::env::temp_dir().join(format!("snapshot_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(latest_snapshot(&dir).unwrap(), None);
+
+        write_snapshot_file(&dir, 9, b"old").unwrap();
+        let path = write_snapshot_file(&dir, 120, b"new").unwrap();
+        fs::write(dir.join("snapshot-00000000000000000200.tmp"), b"partial").unwrap();
+
+        assert_eq!(latest_snapshot(&dir).unwrap(), Some((120, path)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
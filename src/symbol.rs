@@ -1,13 +1,15 @@
-//TODO Read Symbols from file
-
 use std::fmt;
+use std::io;
 use std::str::FromStr;
 
+use crate::snapshot::{invalid_snapshot, Snapshot, SnapshotReader, SnapshotWriter};
+
+/// Name of a tradeable instrument.
+///
+/// Symbols are normalized to upper case, valid symbols are defined by the
+/// ``InstrumentRegistry``.
 #[derive(Debug, Eq, PartialEq, Hash, Clone)]
-pub enum Symbol {
-    Asset1 = 1,
-    Asset2 = 2,
-}
+pub struct Symbol(String);
 
 #[derive(Debug, Clone, Eq, PartialEq, Copy)]
 pub enum AskOrBid {
@@ -27,20 +29,41 @@ impl FromStr for AskOrBid {
     }
 }
 
-impl FromStr for Symbol {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "btc" => Ok(Symbol::Asset1),
-            "eth" => Ok(Symbol::Asset2),
-            _ => Err(()),
+impl fmt::Display for AskOrBid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AskOrBid::Ask => write!(f, "ask"),
+            AskOrBid::Bid => write!(f, "bid"),
         }
     }
 }
 
+impl Symbol {
+    pub fn new(name: &str) -> Symbol {
+        Symbol(name.to_uppercase())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
 impl fmt::Display for Symbol {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Snapshot for AskOrBid {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        writer.put(&(*self as u8));
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        match reader.get::<u8>()? {
+            0 => Ok(AskOrBid::Ask),
+            1 => Ok(AskOrBid::Bid),
+            _ => Err(invalid_snapshot("invalid side")),
+        }
     }
 }
@@ -0,0 +1,78 @@
+use json::{object, JsonValue};
+use rust_decimal::Decimal;
+
+use crate::symbol::{AskOrBid, Symbol};
+use crate::{OrderId, Timestamp, TradeId};
+
+/// A single execution between a resting maker order and an incoming taker order
+#[derive(Clone, PartialEq, Debug)]
+pub struct Trade {
+    /// Globally unique trade id, shared accross books
+    pub id: TradeId,
+    pub symbol: Symbol,
+    pub maker_id: OrderId,
+    pub taker_id: OrderId,
+
+    /// Side of the taker order that caused the trade
+    pub aggressor: AskOrBid,
+
+    /// Execution price, which is always the price of the maker's page
+    pub price: Decimal,
+    pub amount: Decimal,
+
+    /// Nanoseconds since unix epoch, the time the core processed the message
+    /// causing the trade at, or the time a scheduled session transition was
+    /// scheduled for
+    pub timestamp: Timestamp,
+
+    /// Fees charged to maker and taker in the asset they receive, negative
+    /// for rebates. Rebates are paid in the asset the taker fee is charged in.
+    pub maker_fee: Decimal,
+    pub taker_fee: Decimal,
+}
+
+impl Trade {
+    /// Creates a new trade, assigning it the next id of the global trade sequence.
+    /// Matchers don't read the clock, the timestamp is set once the core records
+    /// the trade.
+    pub fn new(
+        last_trade_id: &mut TradeId,
+        symbol: Symbol,
+        maker_id: OrderId,
+        taker_id: OrderId,
+        aggressor: AskOrBid,
+        price: Decimal,
+        amount: Decimal,
+    ) -> Trade {
+        *last_trade_id += 1;
+        Trade {
+            id: *last_trade_id,
+            symbol,
+            maker_id,
+            taker_id,
+            aggressor,
+            price,
+            amount,
+            timestamp: 0,
+            maker_fee: Decimal::ZERO,
+            taker_fee: Decimal::ZERO,
+        }
+    }
+}
+
+impl From<&Trade> for JsonValue {
+    fn from(trade: &Trade) -> Self {
+        object! {
+            "trade_id" => trade.id,
+            "symbol" => trade.symbol.to_string(),
+            "maker_id" => trade.maker_id,
+            "taker_id" => trade.taker_id,
+            "aggressor" => trade.aggressor.to_string(),
+            "price" => trade.price.to_string(),
+            "amount" => trade.amount.to_string(),
+            "timestamp" => trade.timestamp,
+            "maker_fee" => trade.maker_fee.to_string(),
+            "taker_fee" => trade.taker_fee.to_string()
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+use linked_hash_map::LinkedHashMap;
+use rust_decimal::Decimal;
+
+use crate::error::ExchangeError;
+use crate::order_matcher::SelfTradePrevention;
+use crate::orderbook::Order;
+use crate::snapshot::{invalid_snapshot, Snapshot, SnapshotReader, SnapshotWriter};
+use crate::symbol::AskOrBid;
+use crate::{AccountId, OrderId};
+
+/// Conditional order waiting for the market to trade through its stop price.
+///
+/// Once triggered, stop-market orders are executed as market orders and
+/// stop-limit orders as good till cancel limit orders at ``limit_price``.
+#[derive(Clone, PartialEq, Debug)]
+pub struct StopOrder {
+    pub id: OrderId,
+    pub side: AskOrBid,
+    pub stop_price: Decimal,
+
+    /// Limit price of stop-limit orders, None for stop-market orders
+    pub limit_price: Option<Decimal>,
+    pub amount: Decimal,
+
+    /// Account and self-trade prevention the order is executed with once triggered
+    pub account: AccountId,
+    pub stp: SelfTradePrevention,
+}
+
+/// Book of stop orders for a single symbol.
+///
+/// Stops are kept in BTreeMaps keyed by their stop price, so the triggered ones
+/// can be taken off without walking the whole book.
+#[derive(Default)]
+pub struct TriggerBook {
+    /// Buy stops, triggered once the last trade price rises to the stop price
+    stops_bid: BTreeMap<Decimal, LinkedHashMap<OrderId, StopOrder>>,
+
+    /// Sell stops, triggered once the last trade price falls to the stop price
+    stops_ask: BTreeMap<Decimal, LinkedHashMap<OrderId, StopOrder>>,
+
+    /// Index for looking up the side and stop price of an order
+    stops_index: HashMap<OrderId, (AskOrBid, Decimal)>,
+}
+
+impl StopOrder {
+    /// Order the stop is executed as once triggered
+    pub fn order(&self) -> Order {
+        Order::new(self.id, self.account, self.amount).with_stp(self.stp)
+    }
+
+    /// Determines whether a trade at ``last_price`` triggers the stop
+    pub fn is_triggered_by(&self, last_price: &Decimal) -> bool {
+        match self.side {
+            AskOrBid::Bid => *last_price >= self.stop_price,
+            AskOrBid::Ask => *last_price <= self.stop_price,
+        }
+    }
+}
+
+impl TriggerBook {
+    pub fn new() -> TriggerBook {
+        TriggerBook::default()
+    }
+
+    /// Looks up a pending stop order by id
+    pub fn get(&self, order_id: &OrderId) -> Option<&StopOrder> {
+        let (side, stop_price) = self.stops_index.get(order_id)?;
+        let stops = match side {
+            AskOrBid::Bid => &self.stops_bid,
+            AskOrBid::Ask => &self.stops_ask,
+        };
+        stops.get(stop_price)?.get(order_id)
+    }
+
+    pub fn contains_order(&self, order_id: &OrderId) -> bool {
+        self.stops_index.contains_key(order_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.stops_index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stops_index.is_empty()
+    }
+
+    fn get_side_mut(&mut self, side: AskOrBid) -> &mut BTreeMap<Decimal, LinkedHashMap<OrderId, StopOrder>> {
+        match side {
+            AskOrBid::Bid => &mut self.stops_bid,
+            AskOrBid::Ask => &mut self.stops_ask,
+        }
+    }
+
+    pub fn insert(&mut self, order: StopOrder) -> Result<(), ExchangeError> {
+        if self.contains_order(&order.id) {
+            return Err(ExchangeError::DuplicateOrderId(order.id));
+        }
+
+        self.stops_index.insert(order.id, (order.side, order.stop_price));
+        self.get_side_mut(order.side)
+            .entry(order.stop_price)
+            .or_default()
+            .insert(order.id, order);
+        Ok(())
+    }
+
+    /// Removes a stop order that hasn't been triggered yet
+    pub fn cancel(&mut self, order_id: &OrderId) -> Option<StopOrder> {
+        let (side, stop_price) = self.stops_index.remove(order_id)?;
+        let stops = self.get_side_mut(side);
+
+        let page = stops.get_mut(&stop_price)?;
+        let order = page.remove(order_id);
+        if page.is_empty() {
+            stops.remove(&stop_price);
+        }
+        order
+    }
+
+    /// Takes all stops triggered by a trade at ``last_price`` off the book.
+    ///
+    /// Triggered stops are returned in the order they have been placed in,
+    /// regardless of side and stop price.
+    pub fn take_triggered(&mut self, last_price: &Decimal) -> Vec<StopOrder> {
+        let mut triggered_prices: Vec<(AskOrBid, Decimal)> = self
+            .stops_bid
+            .range(..=*last_price)
+            .map(|(price, _)| (AskOrBid::Bid, *price))
+            .collect();
+        triggered_prices.extend(
+            self.stops_ask
+                .range(*last_price..)
+                .map(|(price, _)| (AskOrBid::Ask, *price)),
+        );
+
+        let mut triggered = Vec::new();
+        for (side, price) in triggered_prices {
+            if let Some(page) = self.get_side_mut(side).remove(&price) {
+                triggered.extend(page.into_iter().map(|(_, order)| order));
+            }
+        }
+        for order in &triggered {
+            self.stops_index.remove(&order.id);
+        }
+
+        triggered.sort_by_key(|order| order.id);
+        triggered
+    }
+}
+
+impl Snapshot for StopOrder {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        writer.put(&self.id);
+        writer.put(&self.side);
+        writer.put(&self.stop_price);
+        writer.put(&self.limit_price);
+        writer.put(&self.amount);
+        writer.put(&self.account);
+        writer.put(&self.stp);
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        Ok(StopOrder {
+            id: reader.get()?,
+            side: reader.get()?,
+            stop_price: reader.get()?,
+            limit_price: reader.get()?,
+            amount: reader.get()?,
+            account: reader.get()?,
+            stp: reader.get()?,
+        })
+    }
+}
+
+/// Stops are written side by side, in order of their stop price and then in
+/// the order they have been placed in. The index is rebuilt while reading.
+impl Snapshot for TriggerBook {
+    fn write(&self, writer: &mut SnapshotWriter) {
+        let stops: Vec<StopOrder> = self
+            .stops_bid
+            .values()
+            .chain(self.stops_ask.values())
+            .flat_map(|page| page.values().cloned())
+            .collect();
+        writer.put(&stops);
+    }
+
+    fn read(reader: &mut SnapshotReader) -> io::Result<Self> {
+        let mut trigger_book = TriggerBook::new();
+        for stop in reader.get::<Vec<StopOrder>>()? {
+            trigger_book
+                .insert(stop)
+                .map_err(|_| invalid_snapshot("duplicate stop order"))?;
+        }
+        Ok(trigger_book)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::error::ExchangeError;
+    use crate::order_matcher::SelfTradePrevention;
+    use crate::symbol::AskOrBid;
+    use crate::trigger_book::{StopOrder, TriggerBook};
+
+    fn stop(id: u64, side: AskOrBid, stop_price: i64) -> StopOrder {
+        StopOrder {
+            id,
+            side,
+            stop_price: Decimal::from(stop_price),
+            limit_price: None,
+            amount: Decimal::ONE,
+            account: 1,
+            stp: SelfTradePrevention::default(),
+        }
+    }
+
+    #[test]
+    fn test_is_triggered_by() {
+        let buy_stop = stop(0, AskOrBid::Bid, 100);
+        assert_eq!(buy_stop.is_triggered_by(&Decimal::from(99)), false);
+        assert_eq!(buy_stop.is_triggered_by(&Decimal::from(100)), true);
+        assert_eq!(buy_stop.is_triggered_by(&Decimal::from(101)), true);
+
+        let sell_stop = stop(1, AskOrBid::Ask, 100);
+        assert_eq!(sell_stop.is_triggered_by(&Decimal::from(99)), true);
+        assert_eq!(sell_stop.is_triggered_by(&Decimal::from(100)), true);
+        assert_eq!(sell_stop.is_triggered_by(&Decimal::from(101)), false);
+    }
+
+    #[test]
+    fn test_insert_and_cancel() {
+        let mut trigger_book = TriggerBook::new();
+
+        trigger_book.insert(stop(0, AskOrBid::Bid, 100)).unwrap();
+        trigger_book.insert(stop(1, AskOrBid::Bid, 100)).unwrap();
+        assert_eq!(
+            trigger_book.insert(stop(1, AskOrBid::Ask, 90)),
+            Err(ExchangeError::DuplicateOrderId(1))
+        );
+        assert_eq!(trigger_book.len(), 2);
+
+        assert_eq!(trigger_book.cancel(&0), Some(stop(0, AskOrBid::Bid, 100)));
+        assert_eq!(trigger_book.cancel(&0), None);
+        assert_eq!(trigger_book.contains_order(&1), true);
+
+        trigger_book.cancel(&1).unwrap();
+        assert_eq!(trigger_book.is_empty(), true);
+        assert_eq!(trigger_book.stops_bid.is_empty(), true);
+    }
+
+    #[test]
+    fn test_take_triggered_in_placement_order() {
+        let mut trigger_book = TriggerBook::new();
+
+        trigger_book.insert(stop(0, AskOrBid::Bid, 105)).unwrap();
+        trigger_book.insert(stop(1, AskOrBid::Bid, 101)).unwrap();
+        trigger_book.insert(stop(2, AskOrBid::Bid, 110)).unwrap();
+        trigger_book.insert(stop(3, AskOrBid::Bid, 101)).unwrap();
+        trigger_book.insert(stop(4, AskOrBid::Ask, 90)).unwrap();
+
+        assert_eq!(trigger_book.take_triggered(&Decimal::from(100)).is_empty(), true);
+
+        let triggered: Vec<u64> = trigger_book
+            .take_triggered(&Decimal::from(105))
+            .iter()
+            .map(|order| order.id)
+            .collect();
+        assert_eq!(triggered, vec![0, 1, 3]);
+        assert_eq!(trigger_book.len(), 2);
+        assert_eq!(trigger_book.contains_order(&1), false);
+
+        let triggered: Vec<u64> = trigger_book
+            .take_triggered(&Decimal::from(90))
+            .iter()
+            .map(|order| order.id)
+            .collect();
+        assert_eq!(triggered, vec![4]);
+        assert_eq!(trigger_book.len(), 1);
+    }
+}
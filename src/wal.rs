@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use json::{object, JsonValue};
+
+use crate::inbound_server::InboundMessage;
+use crate::instrument::InstrumentRegistry;
+use crate::{OrderId, Timestamp};
+
+/// When entries appended to the write-ahead log are synced to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FsyncPolicy {
+    /// Sync every entry before it's processed, nothing accepted is lost on a crash
+    #[default]
+    Always,
+
+    /// Sync once every n entries, up to n - 1 entries may be lost
+    EveryN(u64),
+
+    /// Leave syncing to the operating system
+    Never,
+}
+
+/// Inbound message accepted by the core, together with the state it was
+/// accepted in
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalEntry {
+    /// Sequence number of the entry, starting at one
+    pub seq: u64,
+
+    /// Id the core assigns to the next order it creates, replay checks it to
+    /// detect diverging state
+    pub order_id: OrderId,
+
+    /// Time the message was processed at
+    pub timestamp: Timestamp,
+
+    pub message: InboundMessage,
+}
+
+/// Append only log of accepted inbound messages, one json object per line.
+///
+/// Messages are written before they are processed. Replaying all entries in
+/// order against a fresh core rebuilds every book.
+pub struct WriteAheadLog {
+    file: File,
+    policy: FsyncPolicy,
+    last_seq: u64,
+
+    /// Entries written since the last sync
+    unsynced: u64,
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = ();
+
+    /// Parses ``always``, ``never`` or the number of entries between syncs
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "always" => Ok(FsyncPolicy::Always),
+            "never" => Ok(FsyncPolicy::Never),
+            n => match n.parse::<u64>() {
+                Ok(0) | Err(_) => Err(()),
+                Ok(1) => Ok(FsyncPolicy::Always),
+                Ok(n) => Ok(FsyncPolicy::EveryN(n)),
+            },
+        }
+    }
+}
+
+impl From<&WalEntry> for JsonValue {
+    fn from(entry: &WalEntry) -> Self {
+        object! {
+            "seq" => entry.seq,
+            "order_id" => entry.order_id,
+            "timestamp" => entry.timestamp,
+            "message" => entry.message.to_hashmap()
+        }
+    }
+}
+
+impl WalEntry {
+    /// Parses an entry written by ``From<&WalEntry> for JsonValue``, symbols of
+    /// the message are resolved against ``instruments``
+    pub fn from_json(value: &JsonValue, instruments: &InstrumentRegistry) -> Option<WalEntry> {
+        let fields: HashMap<String, String> = value["message"]
+            .entries()
+            .filter_map(|(key, value)| Some((key.to_string(), value.as_str()?.to_string())))
+            .collect();
+
+        Some(WalEntry {
+            seq: value["seq"].as_u64()?,
+            order_id: value["order_id"].as_u64()?,
+            timestamp: value["timestamp"].as_u64()?,
+            message: InboundMessage::from_hashmap(&fields, instruments)?,
+        })
+    }
+}
+
+impl WriteAheadLog {
+    /// Opens the log at ``path`` for appending, creating it if it doesn't exist.
+    /// Returns the entries already in the log for replay.
+    ///
+    /// A torn last line left behind by a crash is cut off, any other invalid
+    /// line fails with ``InvalidData``.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        policy: FsyncPolicy,
+        instruments: &InstrumentRegistry,
+    ) -> io::Result<(WriteAheadLog, Vec<WalEntry>)> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+
+        let (entries, valid_len, torn) = Self::parse(&file, instruments)?;
+        if torn {
+            file.set_len(valid_len)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::End(0))?;
+
+        let wal = WriteAheadLog {
+            file,
+            policy,
+            last_seq: entries.last().map_or(0, |entry| entry.seq),
+            unsynced: 0,
+        };
+        Ok((wal, entries))
+    }
+
+    /// Reads the entries of the log at ``path`` without opening it for writing.
+    /// A torn last line is skipped but left in the file.
+    pub fn read_entries<P: AsRef<Path>>(path: P, instruments: &InstrumentRegistry) -> io::Result<Vec<WalEntry>> {
+        let (entries, _, _) = Self::parse(&File::open(path)?, instruments)?;
+        Ok(entries)
+    }
+
+    /// Parses all entries of a log. Returns them along with the length of the
+    /// valid part of the file and whether a torn last line follows it.
+    fn parse(file: &File, instruments: &InstrumentRegistry) -> io::Result<(Vec<WalEntry>, u64, bool)> {
+        let mut entries: Vec<WalEntry> = Vec::new();
+        let mut valid_len = 0;
+        let mut torn = false;
+        for line in BufReader::new(file).split(b'\n') {
+            let line = line?;
+            if torn {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid entry in write-ahead log"));
+            }
+            let entry = std::str::from_utf8(&line)
+                .ok()
+                .and_then(|line| json::parse(line).ok())
+                .and_then(|value| WalEntry::from_json(&value, instruments));
+            match entry {
+                Some(entry) if entry.seq == entries.last().map_or(1, |last| last.seq + 1) => {
+                    valid_len += line.len() as u64 + 1;
+                    entries.push(entry);
+                }
+                _ => torn = true,
+            }
+        }
+        Ok((entries, valid_len, torn))
+    }
+
+    /// Log every append of which fails, for testing what happens to messages
+    /// that can't be logged
+    #[cfg(all(test, target_os = "linux"))]
+    pub(crate) fn unwritable() -> WriteAheadLog {
+        WriteAheadLog {
+            file: OpenOptions::new().write(true).open("/dev/full").unwrap(),
+            policy: FsyncPolicy::Always,
+            last_seq: 0,
+            unsynced: 0,
+        }
+    }
+
+    /// Sequence number of the last entry in the log, zero if it's empty
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    /// Syncs all entries written so far to disk
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    /// Appends a message with the next sequence number and syncs it according
+    /// to the fsync policy. Returns the sequence number of the entry.
+    ///
+    /// An entry that can't be written or synced is cut off again, so a message
+    /// the core rejects never shows up in the log.
+    pub fn append(&mut self, order_id: OrderId, timestamp: Timestamp, message: &InboundMessage) -> io::Result<u64> {
+        let entry = WalEntry {
+            seq: self.last_seq + 1,
+            order_id,
+            timestamp,
+            message: message.clone(),
+        };
+        let len = self.file.metadata()?.len();
+        let sync = match self.policy {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::EveryN(n) => self.unsynced + 1 >= n,
+            FsyncPolicy::Never => false,
+        };
+
+        let written = self
+            .file
+            .write_all(format!("{}\n", JsonValue::from(&entry)).as_bytes())
+            .and_then(|_| if sync { self.file.sync_data() } else { Ok(()) });
+        if let Err(err) = written {
+            // Original error is what the caller needs to see, even if the entry can't be cut off
+            let _ = self.file.set_len(len).and_then(|_| self.file.sync_data());
+            return Err(err);
+        }
+
+        self.last_seq = entry.seq;
+        self.unsynced = if sync { 0 } else { self.unsynced + 1 };
+        Ok(entry.seq)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    use rust_decimal::Decimal;
+
+    use crate::inbound_server::{InboundMessage, MessageType};
+    use crate::instrument::{Instrument, InstrumentRegistry};
+    use crate::order_matcher::{SelfTradePrevention, TimeInForce};
+    use crate::symbol::{AskOrBid, Symbol};
+    use crate::wal::{FsyncPolicy, WriteAheadLog};
+
+    fn instruments() -> InstrumentRegistry {
+        let mut instruments = InstrumentRegistry::new();
+        instruments
+            .insert(Instrument::new(Symbol::new("BTC"), "BTC", "USD", Decimal::ONE, Decimal::ONE))
+            .unwrap();
+        instruments
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("wal_test_{}_{}.jsonl", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn message(params: &[(&str, &str)]) -> InboundMessage {
+        let map: HashMap<String, String> = params
+            .iter()
+            .map(|(key, val)| (key.to_string(), val.to_string()))
+            .collect();
+        InboundMessage::from_hashmap(&map, &instruments()).unwrap()
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        let msg = message(&[
+            ("action", "place_limit"),
+            ("symbol", "btc"),
+            ("side", "bid"),
+            ("price", "100.5"),
+            ("amount", "2"),
+            ("tif", "ioc"),
+            ("account", "7"),
+            ("stp", "cancel_both"),
+        ]);
+        assert_eq!(msg.time_in_force, Some(TimeInForce::ImmediateOrCancel));
+        assert_eq!(msg.stp, Some(SelfTradePrevention::CancelBoth));
+        assert_eq!(InboundMessage::from_hashmap(&msg.to_hashmap(), &instruments()), Some(msg));
+    }
+
+    #[test]
+    fn test_append_and_reopen() {
+        let path = temp_path("reopen");
+        let limit = message(&[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "10"), ("amount", "1")]);
+        let cancel = message(&[("action", "cancel_limit"), ("order_id", "1")]);
+
+        let (mut wal, entries) = WriteAheadLog::open(&path, FsyncPolicy::Always, &instruments()).unwrap();
+        assert_eq!(entries.len(), 0);
+        assert_eq!(wal.append(1, 100, &limit).unwrap(), 1);
+        assert_eq!(wal.append(2, 200, &cancel).unwrap(), 2);
+
+        let (wal, entries) = WriteAheadLog::open(&path, FsyncPolicy::Always, &instruments()).unwrap();
+        assert_eq!(wal.last_seq(), 2);
+        assert_eq!(entries[0].message, limit);
+        assert_eq!(entries[1].message.message_type, MessageType::CancelLimitOrder);
+        assert_eq!((entries[1].seq, entries[1].order_id, entries[1].timestamp), (2, 2, 200));
+        assert_eq!(entries[0].message.side, Some(AskOrBid::Ask));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_last_line_is_cut_off() {
+        let path = temp_path("torn");
+        let limit = message(&[("action", "place_limit"), ("symbol", "btc"), ("side", "ask"), ("price", "10"), ("amount", "1")]);
+
+        let (mut wal, _) = WriteAheadLog::open(&path, FsyncPolicy::EveryN(10), &instruments()).unwrap();
+        wal.append(1, 100, &limit).unwrap();
+        drop(wal);
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"seq\":2,\"order_")
+            .unwrap();
+
+        //Reading the entries leaves the torn line in place
+        let len = fs::metadata(&path).unwrap().len();
+        assert_eq!(WriteAheadLog::read_entries(&path, &instruments()).unwrap().len(), 1);
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+
+        let (mut wal, entries) = WriteAheadLog::open(&path, FsyncPolicy::Never, &instruments()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(wal.append(2, 100, &limit).unwrap(), 2);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        //Invalid lines in the middle of the log aren't a torn write
+        fs::write(&path, format!("garbage\n{}", fs::read_to_string(&path).unwrap())).unwrap();
+        assert_eq!(WriteAheadLog::open(&path, FsyncPolicy::Always, &instruments()).is_err(), true);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_fsync_policy() {
+        assert_eq!(FsyncPolicy::from_str("always"), Ok(FsyncPolicy::Always));
+        assert_eq!(FsyncPolicy::from_str("Never"), Ok(FsyncPolicy::Never));
+        assert_eq!(FsyncPolicy::from_str("100"), Ok(FsyncPolicy::EveryN(100)));
+        assert_eq!(FsyncPolicy::from_str("0"), Err(()));
+    }
+}